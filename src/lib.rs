@@ -0,0 +1,4 @@
+// public api of the renderer, everything else live under rtracer module
+pub use crate::rtracer::*;
+
+mod rtracer;
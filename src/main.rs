@@ -1,131 +1,394 @@
-use std::f32::consts::FRAC_PI_4;
-use std::time::Instant;
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
 
-use nalgebra::{Point3, Rotation3, Unit, UnitQuaternion, Vector2, Vector3};
+use structopt::StructOpt;
 
-use rtracer::Color3;
-use rtracer::geometric::{Disc, InfinitePlane, Sphere};
-use rtracer::light::{AreaLight, DirectionalLight, PointLight};
-use rtracer::renderer::{render, RenderImage};
-use rtracer::SceneObject;
+use custom_error::custom_error;
 
-use crate::rtracer::SceneData;
+use rtracer::{Adaptive, Aov, Checkpoint, CheckpointError, CropWindow, Denoise, Integrator, LightSelection, MisHeuristic, Progressive, Progress, render_controlled, render_progressive, RenderControl, RenderError, RenderOutput, RenderSettings, SamplerKind, SceneData, SceneParserError, ToneMap};
 
-mod rtracer;
+#[derive(StructOpt)]
+#[structopt(name = "rtracer", about = "Render ron scene file into image")]
+struct Opt {
+	/// Scene file to render
+	#[structopt(parse(from_os_str), default_value = "data.ron")]
+	scene: PathBuf,
+	/// Output image, frame number is appended to file name when rendering animation
+	#[structopt(short, long, parse(from_os_str), default_value = "render.png")]
+	output: PathBuf,
+	/// Image width in pixel [default: resolution of scene file, or 250]
+	#[structopt(short = "W", long)]
+	width: Option<u32>,
+	/// Image height in pixel [default: resolution of scene file, or same as width]
+	#[structopt(short = "H", long)]
+	height: Option<u32>,
+	/// Sample per pixel, at least 16 when the camera shutter is open. Target sample count with --progressive
+	#[structopt(long, default_value = "1")]
+	spp: u32,
+	/// Refine the whole image in pass of 1, 2, 4 ... spp, output is rewritten after every pass
+	#[structopt(long)]
+	progressive: bool,
+	/// Stop once average relative standard error of pixel is below this, with --progressive
+	#[structopt(long)]
+	target_error: Option<f32>,
+	/// Stop before a pass that would end after this many second, with --progressive
+	#[structopt(long)]
+	time_budget: Option<f32>,
+	/// Save accumulated sample here after every pass, with --progressive
+	#[structopt(long, parse(from_os_str))]
+	checkpoint: Option<PathBuf>,
+	/// Continue the render saved in --checkpoint instead of starting over
+	#[structopt(long)]
+	resume: bool,
+	/// Sample noisy pixel more and stop converged one early, spp is ignored.
+	/// Sample count per pixel is saved as <output>.sample-count.pfm
+	#[structopt(long)]
+	adaptive: bool,
+	/// Sample every pixel take before it can stop, with --adaptive
+	#[structopt(long, default_value = "8")]
+	min_spp: u32,
+	/// Most sample a pixel can take, with --adaptive
+	#[structopt(long, default_value = "256")]
+	max_spp: u32,
+	/// Relative standard error a pixel must reach to stop, with --adaptive
+	#[structopt(long, default_value = "0.05")]
+	adaptive_threshold: f32,
+	/// Render only this region: x,y,width,height in pixel, or x0,y0,x1,y1 in [0, 1] like 0.25,0.25,0.75,0.75
+	/// Needs --vmax with normalize tone mapping, so the region has the exposure of the full frame
+	#[structopt(long)]
+	crop: Option<CropWindow>,
+	/// Save the full frame with the crop region filled in, instead of only the region
+	#[structopt(long)]
+	crop_full_frame: bool,
+	/// Random seed [default: seed from entropy]
+	#[structopt(long)]
+	seed: Option<u64>,
+	/// Sample pattern: independent, stratified, halton, sobol, cmj
+	#[structopt(long, default_value = "independent")]
+	sampler: SamplerKind,
+	/// Number of render thread, 0 = every core
+	#[structopt(short = "j", long, default_value = "0")]
+	threads: usize,
+	/// Light transport algorithm: whitted, or debug view ao, normal, depth, uv, object-id, heatmap
+	#[structopt(long, default_value = "whitted")]
+	integrator: Integrator,
+	/// Distance within which geometry occlude, for ao integrator
+	#[structopt(long, default_value = "1.0")]
+	ao_radius: f32,
+	/// Occlusion ray per camera sample, for ao integrator
+	#[structopt(long, default_value = "16")]
+	ao_samples: u32,
+	/// Weighting of light and bsdf sample for area light: power, balance
+	#[structopt(long, default_value = "power")]
+	mis_heuristic: MisHeuristic,
+	/// Light sampled at each shading point: all, uniform, power, bvh
+	#[structopt(long, default_value = "all")]
+	light_selection: LightSelection,
+	/// Extra pass saved as <output>.<aov>.pfm, comma separated: depth, normal, albedo, object-id, material-id,
+	/// direct-diffuse, indirect-diffuse, direct-specular, indirect-specular, shadow, sample-count
+	#[structopt(long, use_delimiter = true)]
+	aov: Vec<Aov>,
+	/// Filter noise out of the image, guided by albedo and normal
+	#[structopt(long)]
+	denoise: bool,
+	/// Number of denoise pass, each one double the filter radius
+	#[structopt(long, default_value = "5")]
+	denoise_iterations: u32,
+	/// Also save the image before denoising as <output>.noisy.<ext>
+	#[structopt(long)]
+	keep_noisy: bool,
+	/// Tone mapping: normalize, clamp, reinhard
+	#[structopt(long, default_value = "normalize")]
+	tone_map: String,
+	/// Exposure multiplier for clamp and reinhard tone mapping
+	#[structopt(long, default_value = "1.0")]
+	exposure: f32,
+	/// Lower bound of normalize tone mapping, use "auto" to take it from image
+	#[structopt(long, default_value = "0.0")]
+	vmin: Bound,
+	/// Upper bound of normalize tone mapping, use "auto" to take it from image
+	#[structopt(long, default_value = "auto")]
+	vmax: Bound,
+	/// Inclusive frame range to render, like "1..24" or "7" [default: every keyframed frame]
+	#[structopt(long)]
+	frames: Option<FrameRange>,
+	/// Only load and check the scene, don't render anything
+	#[structopt(long)]
+	dry_run: bool,
+	#[structopt(subcommand)]
+	command: Option<Command>,
+}
 
-fn main() {
-	// let scene_data = setup();
-	// rtracer::parser::save_scene_data("data.ron", &scene_data).unwrap();
-	// test();
-	let scene=  rtracer::parser::load_scene_data("data.ron").unwrap();
-	test_render(&scene);
+#[derive(StructOpt)]
+enum Command {
+	/// Load scene in any supported format and save it in format given by output extension (ron, json, obj)
+	Convert {
+		#[structopt(parse(from_os_str))]
+		input: PathBuf,
+		#[structopt(parse(from_os_str))]
+		output: PathBuf,
+	},
 }
 
-fn test() {
+struct Bound(Option<f32>);
 
-	use std::any::type_name;
+impl FromStr for Bound {
+	type Err = std::num::ParseFloatError;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		if s == "auto" { Ok(Bound(None)) } else { s.parse().map(|x| Bound(Some(x))) }
+	}
+}
 
-	let q2: UnitQuaternion<f64> =
-		UnitQuaternion::from_axis_angle(&Vector3::x_axis(), std::f64::consts::PI/4.0);
+struct FrameRange(RangeInclusive<u32>);
 
-	// println!("{}", (q2 * Vector3::y_axis()).into_inner());
+impl FromStr for FrameRange {
+	type Err = String;
 
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let parse = |x: &str| x.trim().parse::<u32>().map_err(|e| format!("invalid frame '{}': {}", x, e));
+		match s.find("..") {
+			None => parse(s).map(|f| FrameRange(f..=f)),
+			Some(i) => Ok(FrameRange(parse(&s[..i])?..=parse(s[i + 2..].trim_start_matches('='))?)),
+		}
+	}
+}
 
+custom_error!{ CliError
+	Scene {path: String, source: SceneParserError} = "unable to load scene '{path}': {source}",
+	Save {path: String, source: io::Error} = "unable to save image '{path}': {source}",
+	SaveScene {path: String, source: SceneParserError} = "unable to save scene '{path}': {source}",
+	Checkpoint {path: String, source: CheckpointError} = "checkpoint '{path}': {source}",
+	Render {source: RenderError} = "unable to render: {source}",
+	ToneMap {name: String} = "unknown tone mapping '{name}', expected one of: normalize, clamp, reinhard",
+	Size {width: u32, height: u32} = "image size must be at least 1x1, got {width}x{height}",
+	Resume = "--resume need --checkpoint to know which render to continue",
+	Frames {start: u32, end: u32} = "frame range {start}..{end} is empty, first frame must not be after the last",
+}
 
+fn main() {
+	let opt = Opt::from_args();
+	if let Err(e) = run(&opt) {
+		eprintln!("error: {}", e);
+		process::exit(1);
+	}
+}
 
-	/*let k = &mut u;
-	let l: Unit<Vector3<f64>> = (q2 * *k);
-	println!("{}", l.into_inner());
+fn run(opt: &Opt) -> Result<(), CliError> {
+	if let Some(Command::Convert {input, output}) = &opt.command {
+		return convert(input, output);
+	}
+	if opt.resume && opt.checkpoint.is_none() {
+		return Err(CliError::Resume);
+	}
+	if let Some(FrameRange(frames)) = &opt.frames {
+		if frames.is_empty() {
+			return Err(CliError::Frames {start: *frames.start(), end: *frames.end()});
+		}
+	}
 
-	*k = l;
-	println!("{}", k.into_inner());*/
+	let (scene, report) = rtracer::load_scene_data_with_report(&opt.scene)
+		.map_err(|source| CliError::Scene {path: opt.scene.display().to_string(), source})?;
 
-	// let mut u: Unit<Vector3<f64>> = Vector3::y_axis();
-	(0..8).scan(Vector3::y_axis(), |q: &mut Unit<Vector3<f64>>, _| {
-		*q = q2 * *q;
-		Some(*q)
-	}).for_each(|x| println!("{}", x.into_inner()));
+	for diagnostic in report.diagnostics() {
+		eprintln!("{}", diagnostic);
+	}
+
+	if opt.dry_run {
+		let flat = scene.scene.flatten();
+		println!(
+			"{}: ok ({} objects, {} lights, {} warnings)",
+			opt.scene.display(),
+			flat.obj_count(),
+			flat.light_count(),
+			report.diagnostics().len()
+		);
+		return Ok(());
+	}
+
+	let settings = render_settings(opt, scene.resolution)?;
+	if opt.progressive {
+		return render_progressive_to_file(opt, &scene, &settings);
+	}
+	let frames = opt.frames.as_ref().map(|f| f.0.clone()).or_else(|| scene.animation.frame_range());
+
+	match frames {
+		None => render_to_file(&scene, &settings, &opt.output),
+		Some(frames) => {
+			for frame in frames {
+				render_to_file(&scene.at_frame(frame), &settings, &frame_path(&opt.output, frame))?;
+			}
+			Ok(())
+		},
+	}
 }
 
-fn setup() -> SceneData {
+fn render_settings(opt: &Opt, resolution: Option<(u32, u32)>) -> Result<RenderSettings, CliError> {
+	// size given on command line win, a single side keep aspect ratio of scene
+	let (width, height) = match (opt.width, opt.height, resolution) {
+		(Some(w), Some(h), _) => (w, h),
+		(Some(w), None, Some((sw, sh))) => (w, (w as u64 * sh as u64 / sw as u64) as u32),
+		(None, Some(h), Some((sw, sh))) => ((h as u64 * sw as u64 / sh as u64) as u32, h),
+		(Some(w), None, None) => (w, w),
+		(None, Some(h), None) => (h, h),
+		(None, None, resolution) => resolution.unwrap_or((250, 250)),
+	};
+	// side derived from a thin scene resolution can round down to 0 too
+	if width == 0 || height == 0 {
+		return Err(CliError::Size {width, height});
+	}
+	let mut settings = RenderSettings::new(width, height);
+	settings.spp = opt.spp;
+	settings.seed = opt.seed;
+	settings.sampler = opt.sampler;
+	settings.threads = opt.threads;
+	settings.crop = opt.crop;
+	settings.crop_full_frame = opt.crop_full_frame;
+	settings.integrator = match opt.integrator {
+		Integrator::AmbientOcclusion {..} => Integrator::AmbientOcclusion {radius: opt.ao_radius, samples: opt.ao_samples},
+		integrator => integrator,
+	};
+	settings.mis_heuristic = opt.mis_heuristic;
+	settings.light_selection = opt.light_selection;
+	settings.aovs = opt.aov.clone();
+	if opt.adaptive {
+		settings.adaptive = Some(Adaptive {min_spp: opt.min_spp, max_spp: opt.max_spp, threshold: opt.adaptive_threshold});
+		if !settings.aovs.contains(&Aov::SampleCount) {
+			settings.aovs.push(Aov::SampleCount);
+		}
+	}
+	if opt.denoise {
+		settings.denoise = Some(Denoise {iterations: opt.denoise_iterations, keep_noisy: opt.keep_noisy, ..Denoise::default()});
+	}
+	settings.tone_map = match opt.tone_map.as_str() {
+		"normalize" => ToneMap::Normalize(opt.vmin.0, opt.vmax.0),
+		"clamp" => ToneMap::Clamp(opt.exposure),
+		"reinhard" => ToneMap::Reinhard(opt.exposure),
+		name => return Err(CliError::ToneMap {name: name.to_string()}),
+	};
+	Ok(settings)
+}
 
-	use crate::rtracer::material;
+fn convert(input: &Path, output: &Path) -> Result<(), CliError> {
+	let (scene, report) = rtracer::load_scene_data_with_report(input)
+		.map_err(|source| CliError::Scene {path: input.display().to_string(), source})?;
+	for diagnostic in report.diagnostics() {
+		eprintln!("{}", diagnostic);
+	}
+	rtracer::save_scene_data(output, &scene)
+		.map_err(|source| CliError::SaveScene {path: output.display().to_string(), source})?;
+	println!("Converted {} to {}", input.display(), output.display());
+	Ok(())
+}
 
-	let camera = rtracer::Camera::new(
-		Point3::new(0.0, 0.0, 0.0),
-		Rotation3::from_euler_angles(0.0, 0.0, 0.0)
-	);
+// render.png -> render_0001.png
+fn frame_path(output: &Path, frame: u32) -> PathBuf {
+	let stem = output.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+	let file_name = match output.extension() {
+		Some(ext) => format!("{}_{:04}.{}", stem, frame, ext.to_string_lossy()),
+		None => format!("{}_{:04}", stem, frame),
+	};
+	output.with_file_name(file_name)
+}
 
-	let sphere =
-        SceneObject::new(
-            Sphere {pos: Point3::new(3.0, 2.0, 0.0), radius: 0.5},
-            // material::Diffuse::new([0.5, 1.0, 0.5].into()),
-            material::PerfectReflective::new([0.5, 0.5, 1.0].into())
-        );
-	let sphere2 =
-		SceneObject::new(
-			Sphere {pos: Point3::new(3.0, 0.0, 0.0), radius: 1.0},
-			material::Diffuse::new([1.0, 0.5, 0.5].into()),
-            //material::PerfectReflective,
-		);
-	let floor =
-		SceneObject::new(
-			InfinitePlane {
-				pos: Point3::new(1.0, 1.0, -1.0),
-				norm: Unit::new_normalize(Vector3::new(0.0, 0.0, 1.0))
-			},
-			// material::Reflective::new(0.5, 3)
-		        material::Diffuse::new([0.5, 0.5, 1.0].into())
-        );/*
-	let disc = SceneObject::new(Disc::new(
-		Point3::new(1.0, 1.0, -1.0),
-		Unit::new_normalize(Vector3::new(0.0, 0.0, 1.0)),
-		5.0));
-	let light = PointLight::new(Point3::new(0.0, 1.0, 0.5), Color3::new(1.0, 1.0, 1.0));
-	let light2 = PointLight::new(Point3::new(0.0, -1.0, 0.5), Color3::new(1.0, 1.0, 1.0));
-	let sun = DirectionalLight::new(
-		Unit::new_normalize(Vector3::new(0.0, -1.0, -1.0)),
-		Color3::new(0.1, 0.1, 0.1)
-	);*/
-	let area = AreaLight::new(
-		Vector3::new(0.0, 1.0, 1.0),
-		UnitQuaternion::from_euler_angles(0.0, 0.0, -FRAC_PI_4),
-		0.5,
-		None
-	);
-
-	let scene = rtracer::Scene::from_maybe_component(
-		Some(vec![sphere, floor, sphere2]),
-		Some(vec![
-			// uncomment to enable light
-			// light.into(),
-			// light2.into(),
-			// sun.into(),
-			area.into(),
-		]),
-		Some(Color3::new(0.05, 0.07, 0.1))
-	);
-
-	SceneData {scene, camera}
-}
-
-fn test_render(scene_data: &rtracer::SceneData) {
-	// render
-	const IMAGE_SIZE: u32 = 250;
-	const VIEWPORT_SIZE: u32 = 2;
-	const UNIT_PER_PIXEL: f32 = VIEWPORT_SIZE as f32 / IMAGE_SIZE as f32;
+// render.png -> render.depth.pfm
+fn aov_path(output: &Path, aov: Aov) -> PathBuf {
+	let stem = output.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+	output.with_file_name(format!("{}.{}.pfm", stem, aov.name()))
+}
+
+// render.png -> render.noisy.png
+fn noisy_path(output: &Path) -> PathBuf {
+	let stem = output.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+	match output.extension() {
+		Some(ext) => output.with_file_name(format!("{}.noisy.{}", stem, ext.to_string_lossy())),
+		None => output.with_file_name(format!("{}.noisy", stem)),
+	}
+}
 
+fn render_to_file(scene_data: &SceneData, settings: &RenderSettings, path: &Path) -> Result<(), CliError> {
 	let (scene, camera) = (&scene_data.scene, &scene_data.camera);
 
-	print!("Start Rendering...");
 	let start_time = Instant::now();
-	let rendered_image: RenderImage = render(scene, camera, IMAGE_SIZE, UNIT_PER_PIXEL);
+	let observer = print_progress;
+	let output = render_controlled(scene, camera, settings, &RenderControl {observer: Some(&observer), cancel: None})?;
 	let duration = start_time.elapsed();
 	println!("\nRendering Finish In {:.2}s", duration.as_secs_f32());
-	
-	// save
-	const FILE_NAME: &str = "render.png";
-	rendered_image.save(FILE_NAME).expect("Unable to save Image");
-	println!("Saving to {}", FILE_NAME);
+
+	save_output(&output, path)
+}
+
+// animation isn't rendered progressively, only the scene as loaded
+fn render_progressive_to_file(opt: &Opt, scene_data: &SceneData, settings: &RenderSettings) -> Result<(), CliError> {
+	let (scene, camera, path) = (&scene_data.scene, &scene_data.camera, &opt.output);
+	let progressive = Progressive {
+		target_spp: opt.spp,
+		target_error: opt.target_error,
+		time_budget: opt.time_budget.map(Duration::from_secs_f32),
+	};
+	let checkpoint_error = |source| CliError::Checkpoint {
+		path: opt.checkpoint.as_ref().map(|p| p.display().to_string()).unwrap_or_default(),
+		source
+	};
+	let resume = match &opt.checkpoint {
+		Some(checkpoint) if opt.resume => {
+			let checkpoint = Checkpoint::load(checkpoint).map_err(checkpoint_error)?;
+			println!("Resuming from {} spp", checkpoint.spp());
+			Some(checkpoint)
+		},
+		_ => None,
+	};
+
+	let start_time = Instant::now();
+	let observer = print_progress;
+	// first failure stop saving, it is reported once rendering is over
+	let mut result = Ok(());
+	let (_, checkpoint) = render_progressive(scene, camera, settings, &progressive, resume, &RenderControl {observer: Some(&observer), cancel: None}, |output, checkpoint| {
+		if result.is_err() {
+			return;
+		}
+		println!("\nPass {} spp done in {:.2}s", checkpoint.spp(), start_time.elapsed().as_secs_f32());
+		result = save_output(output, path)
+			.and_then(|_| match &opt.checkpoint {
+				Some(checkpoint_path) => checkpoint.save(checkpoint_path).map_err(checkpoint_error),
+				None => Ok(()),
+			});
+	}).map_err(|e| match e {
+		CheckpointError::Render {source} => CliError::Render {source},
+		e => checkpoint_error(e),
+	})?;
+	result?;
+	println!("Rendering Finish At {} spp In {:.2}s", checkpoint.spp(), start_time.elapsed().as_secs_f32());
+	Ok(())
 }
 
+// one line progress bar, rewritten in place
+fn print_progress(progress: &Progress) {
+	let eta = progress.eta().map_or(String::new(), |eta| format!(", eta {:.1}s", eta.as_secs_f32()));
+	print!("\rRendering {:5.1}% ({} rays{})   ", progress.fraction() * 100.0, progress.rays, eta);
+	let _ = io::stdout().flush();
+}
+
+fn save_output(output: &RenderOutput, path: &Path) -> Result<(), CliError> {
+	// save
+	output.image.save(path)
+		.map_err(|source| CliError::Save {path: path.display().to_string(), source})?;
+	println!("Saving to {}", path.display());
+	if let Some(noisy) = &output.noisy {
+		let noisy_path = noisy_path(path);
+		noisy.save(&noisy_path)
+			.map_err(|source| CliError::Save {path: noisy_path.display().to_string(), source})?;
+		println!("Saving noisy image to {}", noisy_path.display());
+	}
+	for (aov, image) in &output.aovs {
+		let aov_path = aov_path(path, *aov);
+		rtracer::aov::save_pfm(image, &aov_path)
+			.map_err(|source| CliError::Save {path: aov_path.display().to_string(), source})?;
+		println!("Saving {} to {}", aov.name(), aov_path.display());
+	}
+	Ok(())
+}
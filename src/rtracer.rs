@@ -1,38 +1,66 @@
 use nalgebra::base::Vector3;
 
 pub use camera::Camera;
+pub use group::Group;
 pub use hitinfo::HitInfo;
-pub use material::Materials;
-pub use parser::SceneData;
+pub use light::{Light, Lights};
+pub use light_selection::LightSelection;
+pub use material::{Material, MaterialRef, Materials};
+pub use mis::MisHeuristic;
+pub use progress::{CancelToken, Progress, ProgressObserver, RenderControl};
+pub use progressive::{Checkpoint, CheckpointError, Progressive, render_progressive};
+pub use parser::{CURRENT_VERSION, load_scene_data, load_scene_data_with_report, save_scene_data, SceneData, SceneParserError};
 pub use parser::serde_interface;
 pub use raycast_info::RayCastInfo;
+pub use adaptive::Adaptive;
+pub use aov::Aov;
+pub use denoise::Denoise;
+pub use renderer::{CropWindow, Integrator, render, render_controlled, render_with_aovs, RenderError, RenderImage, RenderOutput, RenderSettings, ToneMap};
+pub use sampler::{Sampler, SamplerKind};
 pub use scene::Scene;
 pub use scene_object::SceneObject;
 pub use shape::geometric;
+pub use shape::geometric::Shapes;
 pub use shape::Shape;
 
 mod scene;
 pub mod light;
 mod camera;
 mod scene_object;
+mod mesh;
+mod group;
 mod shape;
 mod hitinfo;
 mod raycast_info;
+pub mod motion;
+pub mod animation;
+pub mod validation;
 pub mod material;
+pub mod texture;
+pub mod import;
+pub mod export;
 pub mod renderer;
+pub mod sampler;
+pub mod mis;
+pub mod stats;
+pub mod integrator;
+pub mod aov;
+pub mod denoise;
+pub mod adaptive;
+pub mod progress;
+pub mod progressive;
+pub mod light_selection;
 pub mod parser;
 pub mod helper;
 
 pub type Color3 = Vector3<f32>;
 
 // number of sample use in monte carlo ray tracing of area light
-// number of ray casted = AREALIGHT_MONTECARLO_SAMPLE
-const AREALIGHT_MONTECARLO_SAMPLE: u32 = 121;
-
-// square root of number of grid (sample) in finite differential ray tracing of area light
-// number of ray casted = (2*AREALIGHT_FINITEDIFF_LENGTH + 1)^2
-const AREALIGHT_FINITEDIFF_LENGTH: u32 = 3;
+// with mis each of them is one light sample + one bsdf sample
+const AREALIGHT_MONTECARLO_SAMPLE: u32 = 49;
 
+// least sample per pixel when camera shutter interval is not empty, each one get its own shutter time
+const MOTION_BLUR_SAMPLE: u32 = 16;
 
 const REFLECTION_DEPTH_LIMIT: usize = 2;
 /*
@@ -0,0 +1,143 @@
+/*
+adaptive sampling, pixel stop taking sample once its estimate is good enough
+
+mean and variance of luminance is tracked per pixel (Welford), relative standard error of the mean
+sqrt(var / n) / mean tell how far the pixel likely is from converged value.
+check is done every min_spp sample so one lucky streak of similar sample doesn't stop it too early
+*/
+use serde::{Deserialize, Serialize};
+
+use super::Color3;
+use super::light_selection::luminance;
+
+// keep error of black pixel finite, every sample 0 is converged anyway
+const DARK_LUMINANCE: f32 = 1e-3;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Adaptive {
+	pub min_spp: u32,
+	pub max_spp: u32,
+	// relative standard error pixel must reach to stop, 0.05 = within ~5% of converged value
+	pub threshold: f32,
+}
+
+impl Default for Adaptive {
+	fn default() -> Self {
+		Adaptive {min_spp: 8, max_spp: 256, threshold: 0.05}
+	}
+}
+
+impl Adaptive {
+	// variance need at least 2 sample
+	pub fn min_spp(&self) -> u32 {
+		self.min_spp.max(2)
+	}
+
+	pub fn max_spp(&self) -> u32 {
+		self.max_spp.max(self.min_spp())
+	}
+
+	pub fn is_done(&self, estimate: &PixelEstimate) -> bool {
+		let n = estimate.count();
+		n >= self.max_spp() || (n >= self.min_spp() && n.checked_rem(self.min_spp()) == Some(0) && estimate.error() < self.threshold)
+	}
+}
+
+// running sum of light and statistic of luminance of one pixel
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PixelEstimate {
+	sum: Color3,
+	count: u32,
+	mean: f32,
+	m2: f32,
+}
+
+impl Default for PixelEstimate {
+	fn default() -> Self {
+		PixelEstimate {sum: Color3::zeros(), count: 0, mean: 0.0, m2: 0.0}
+	}
+}
+
+impl PixelEstimate {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	pub fn add(&mut self, light: Color3) {
+		self.sum += light;
+		self.count += 1;
+		let x = luminance(&light);
+		let delta = x - self.mean;
+		self.mean += delta / self.count as f32;
+		self.m2 += delta * (x - self.mean);
+	}
+
+	pub fn count(&self) -> u32 {
+		self.count
+	}
+
+	pub fn mean(&self) -> Color3 {
+		if self.count == 0 { Color3::zeros() } else { self.sum / self.count as f32 }
+	}
+
+	// relative standard error of mean luminance
+	pub fn error(&self) -> f32 {
+		if self.count < 2 {
+			return f32::INFINITY;
+		}
+		let variance = self.m2 / (self.count - 1) as f32;
+		(variance / self.count as f32).sqrt() / self.mean.abs().max(DARK_LUMINANCE)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use nalgebra::{Point3, UnitQuaternion, Vector3};
+
+	use crate::rtracer::{Aov, Camera, render_with_aovs, RenderSettings, Scene, SceneObject};
+	use crate::rtracer::geometric::InfinitePlane;
+	use crate::rtracer::light::AreaLight;
+	use crate::rtracer::light_selection::LightSelection;
+	use crate::rtracer::material::Diffuse;
+
+	use super::*;
+
+	#[test]
+	fn estimate_error_test() {
+		let mut flat = PixelEstimate::new();
+		let mut noisy = PixelEstimate::new();
+		for i in 0..16 {
+			flat.add(Color3::repeat(0.5));
+			noisy.add(Color3::repeat((i % 2) as f32));
+		}
+		assert_eq!(flat.error(), 0.0);
+		assert_eq!(flat.mean(), Color3::repeat(0.5));
+		assert!(noisy.error() > 0.1);
+		let adaptive = Adaptive {min_spp: 4, max_spp: 64, threshold: 0.05};
+		assert!(adaptive.is_done(&flat) && !adaptive.is_done(&noisy));
+	}
+
+	// soft shadow get more sample than the sky, and every count stay within min and max
+	#[test]
+	fn adaptive_sample_count_test() {
+		let mut scene = Scene::new();
+		scene.add_obj(SceneObject::new(
+			InfinitePlane {pos: Point3::new(0.0, 0.0, -1.0), norm: Vector3::z_axis()},
+			Diffuse::new(Color3::repeat(0.8))
+		));
+		let rotation = UnitQuaternion::from_axis_angle(&Vector3::y_axis(), std::f32::consts::FRAC_PI_2);
+		scene.add_light(AreaLight::new(Vector3::new(3.0, 0.0, 1.0), rotation, 0.5, Some(Color3::repeat(4.0))).into());
+		let camera = Camera::new(Point3::origin(), nalgebra::Rotation3::identity());
+
+		let mut settings = RenderSettings::new(16, 12);
+		settings.seed = Some(3);
+		settings.light_selection = LightSelection::Uniform;
+		settings.adaptive = Some(Adaptive {min_spp: 4, max_spp: 32, threshold: 0.05});
+		settings.aovs = vec![Aov::SampleCount];
+		let output = render_with_aovs(&scene, &camera, &settings).unwrap();
+
+		let counts: Vec<f32> = output.aovs[0].1.pixels().map(|p| p[0]).collect();
+		assert!(counts.iter().all(|&c| (4.0..=32.0).contains(&c)));
+		assert!(counts.contains(&4.0) && counts.contains(&32.0), "{:?}", counts);
+	}
+}
@@ -0,0 +1,389 @@
+use std::ops::RangeInclusive;
+
+use nalgebra::{Point3, Rotation3, Similarity3, Translation3, UnitQuaternion, Vector3};
+use serde::{Deserialize, Serialize};
+
+use super::{Camera, Color3, Scene};
+use super::motion::Motion;
+use super::validation::{field, index, Report};
+
+// value that can be blended between keyframe
+pub trait Animatable: Copy {
+	fn lerp(&self, other: &Self, t: f32) -> Self;
+}
+
+impl Animatable for f32 {
+	fn lerp(&self, other: &Self, t: f32) -> Self {
+		self + (other - self) * t
+	}
+}
+
+impl Animatable for Vector3<f32> {
+	fn lerp(&self, other: &Self, t: f32) -> Self {
+		self + (other - self) * t
+	}
+}
+
+impl Animatable for Point3<f32> {
+	fn lerp(&self, other: &Self, t: f32) -> Self {
+		self + (other - self) * t
+	}
+}
+
+// how value move from a keyframe to the next one
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub enum Interpolation<T> {
+	Linear,
+	// cubic bezier with two control value between this keyframe and the next
+	Bezier(T, T),
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Keyframe<T> {
+	pub frame: f32,
+	pub value: T,
+	#[serde(default = "linear")]
+	pub interpolation: Interpolation<T>,
+}
+
+fn linear<T>() -> Interpolation<T> {
+	Interpolation::Linear
+}
+
+// keyframe sorted by frame
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Track<T> {
+	pub keys: Vec<Keyframe<T>>,
+}
+
+impl<T: Animatable> Track<T> {
+	pub fn new(mut keys: Vec<Keyframe<T>>) -> Self {
+		keys.sort_by(|a, b| a.frame.partial_cmp(&b.frame).unwrap_or(std::cmp::Ordering::Equal));
+		Track {keys}
+	}
+
+	// value at frame, hold first/last value outside of keyframe range
+	pub fn sample(&self, frame: f32) -> Option<T> {
+		let first = self.keys.first()?;
+		if frame <= first.frame {
+			return Some(first.value);
+		}
+
+		let next_index = match self.keys.iter().position(|k| k.frame > frame) {
+			Some(i) => i,
+			None => return self.keys.last().map(|k| k.value),
+		};
+
+		let (a, b) = (&self.keys[next_index - 1], &self.keys[next_index]);
+		let t = (frame - a.frame) / (b.frame - a.frame);
+
+		Some(match a.interpolation {
+			Interpolation::Linear => a.value.lerp(&b.value, t),
+			Interpolation::Bezier(c1, c2) => Self::_bezier(a.value, c1, c2, b.value, t),
+		})
+	}
+
+	// de casteljau, so it only need lerp
+	fn _bezier(p0: T, p1: T, p2: T, p3: T, t: f32) -> T {
+		let (q0, q1, q2) = (p0.lerp(&p1, t), p1.lerp(&p2, t), p2.lerp(&p3, t));
+		let (r0, r1) = (q0.lerp(&q1, t), q1.lerp(&q2, t));
+		r0.lerp(&r1, t)
+	}
+
+	fn frame_range(&self) -> Option<(f32, f32)> {
+		Some((self.keys.first()?.frame, self.keys.last()?.frame))
+	}
+}
+
+impl<T> Track<T> {
+	fn validate(&self, path: &str, report: &mut Report) {
+		if self.keys.is_empty() {
+			report.warning(path, "track has no keyframe");
+		}
+		for (i, key) in self.keys.iter().enumerate() {
+			if !key.frame.is_finite() {
+				report.error(&field(&index(&field(path, "keys"), i), "frame"), "must be finite");
+			}
+		}
+		// non finite frame is already reported above
+		for (i, (a, b)) in self.keys.iter().zip(self.keys.iter().skip(1)).enumerate() {
+			if a.frame.is_finite() && b.frame.is_finite() && a.frame >= b.frame {
+				report.error(
+					&field(&index(&field(path, "keys"), i + 1), "frame"),
+					format!("keyframe must be sorted by frame, got {} after {}", b.frame, a.frame)
+				);
+			}
+		}
+	}
+}
+
+fn validate_track<T>(track: &Option<Track<T>>, path: &str, name: &str, report: &mut Report) {
+	if let Some(t) = track {
+		t.validate(&field(path, name), report);
+	}
+}
+
+fn sample_or<T: Animatable>(track: &Option<Track<T>>, frame: f32, default: T) -> T {
+	track.as_ref().and_then(|t| t.sample(frame)).unwrap_or(default)
+}
+
+// rotation track is euler angle (roll, pitch, yaw) in radian
+fn euler_to_quaternion(euler: Vector3<f32>) -> UnitQuaternion<f32> {
+	UnitQuaternion::from_euler_angles(euler.x, euler.y, euler.z)
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CameraTrack {
+	#[serde(default)]
+	pub position: Option<Track<Point3<f32>>>,
+	#[serde(default)]
+	pub rotation: Option<Track<Vector3<f32>>>,
+}
+
+// transform track of object at index object in scene
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ObjectTrack {
+	pub object: usize,
+	#[serde(default)]
+	pub translation: Option<Track<Vector3<f32>>>,
+	#[serde(default)]
+	pub rotation: Option<Track<Vector3<f32>>>,
+	#[serde(default)]
+	pub scale: Option<Track<f32>>,
+}
+
+impl ObjectTrack {
+	fn transform_at(&self, frame: f32) -> Similarity3<f32> {
+		Similarity3::from_parts(
+			Translation3::from(sample_or(&self.translation, frame, Vector3::zeros())),
+			euler_to_quaternion(sample_or(&self.rotation, frame, Vector3::zeros())),
+			sample_or(&self.scale, frame, 1.0)
+		)
+	}
+}
+
+// emitted light of light at index light in scene = color * intensity
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LightTrack {
+	pub light: usize,
+	#[serde(default)]
+	pub color: Option<Track<Color3>>,
+	#[serde(default)]
+	pub intensity: Option<Track<f32>>,
+}
+
+// material parameter of object at index object in scene
+#[derive(Serialize, Deserialize, Clone)]
+pub struct MaterialTrack {
+	pub object: usize,
+	#[serde(default)]
+	pub color: Option<Track<Color3>>,
+	#[serde(default)]
+	pub roughness: Option<Track<f32>>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct Animation {
+	#[serde(default)]
+	pub camera: Option<CameraTrack>,
+	#[serde(default)]
+	pub objects: Vec<ObjectTrack>,
+	#[serde(default)]
+	pub lights: Vec<LightTrack>,
+	#[serde(default)]
+	pub materials: Vec<MaterialTrack>,
+}
+
+impl Animation {
+	pub fn is_empty(&self) -> bool {
+		self.camera.is_none() && self.objects.is_empty() && self.lights.is_empty() && self.materials.is_empty()
+	}
+
+	// range of frame covered by every keyframe
+	pub fn frame_range(&self) -> Option<RangeInclusive<u32>> {
+		let camera_ranges = self.camera.iter()
+			.flat_map(|c| vec![
+				c.position.as_ref().and_then(Track::frame_range),
+				c.rotation.as_ref().and_then(Track::frame_range)
+			]);
+		let object_ranges = self.objects.iter()
+			.flat_map(|o| vec![
+				o.translation.as_ref().and_then(Track::frame_range),
+				o.rotation.as_ref().and_then(Track::frame_range),
+				o.scale.as_ref().and_then(Track::frame_range)
+			]);
+		let light_ranges = self.lights.iter()
+			.flat_map(|l| vec![
+				l.color.as_ref().and_then(Track::frame_range),
+				l.intensity.as_ref().and_then(Track::frame_range)
+			]);
+		let material_ranges = self.materials.iter()
+			.flat_map(|m| vec![
+				m.color.as_ref().and_then(Track::frame_range),
+				m.roughness.as_ref().and_then(Track::frame_range)
+			]);
+
+		camera_ranges.chain(object_ranges).chain(light_ranges).chain(material_ranges)
+			.flatten()
+			.fold(None, |acc: Option<(f32, f32)>, (s, e)| match acc {
+				None => Some((s, e)),
+				Some((a, b)) => Some((a.min(s), b.max(e))),
+			})
+			.map(|(s, e)| (s.floor().max(0.0) as u32)..=(e.ceil().max(0.0) as u32))
+	}
+
+	// move every track after object and light before it, used when scene are merged
+	pub fn append(&mut self, mut other: Animation, object_offset: usize, light_offset: usize) {
+		for track in other.objects.iter_mut() {
+			track.object += object_offset;
+		}
+		for track in other.lights.iter_mut() {
+			track.light += light_offset;
+		}
+		for track in other.materials.iter_mut() {
+			track.object += object_offset;
+		}
+		if self.camera.is_none() {
+			self.camera = other.camera;
+		}
+		self.objects.append(&mut other.objects);
+		self.lights.append(&mut other.lights);
+		self.materials.append(&mut other.materials);
+	}
+
+	// check that track are well formed and point to existing object and light
+	// scene is the one animation is applied to, with include merged and material resolved
+	pub fn validate(&self, path: &str, scene: &Scene, report: &mut Report) {
+		let (object_count, light_count) = (scene.obj_count(), scene.light_count());
+		if let Some(c) = &self.camera {
+			let camera_path = field(path, "camera");
+			validate_track(&c.position, &camera_path, "position", report);
+			validate_track(&c.rotation, &camera_path, "rotation", report);
+		}
+
+		let check_index = |report: &mut Report, path: &str, i: usize, count: usize, what: &str| {
+			if i >= count {
+				report.error(path, format!("there is no {} {} (scene has {})", what, i, count));
+			}
+		};
+
+		for (i, o) in self.objects.iter().enumerate() {
+			let p = index(&field(path, "objects"), i);
+			check_index(report, &field(&p, "object"), o.object, object_count, "object");
+			validate_track(&o.translation, &p, "translation", report);
+			validate_track(&o.rotation, &p, "rotation", report);
+			validate_track(&o.scale, &p, "scale", report);
+		}
+		for (i, l) in self.lights.iter().enumerate() {
+			let p = index(&field(path, "lights"), i);
+			check_index(report, &field(&p, "light"), l.light, light_count, "light");
+			validate_track(&l.color, &p, "color", report);
+			validate_track(&l.intensity, &p, "intensity", report);
+		}
+		for (i, m) in self.materials.iter().enumerate() {
+			let p = index(&field(path, "materials"), i);
+			check_index(report, &field(&p, "object"), m.object, object_count, "object");
+			validate_track(&m.color, &p, "color", report);
+			validate_track(&m.roughness, &p, "roughness", report);
+			// apply skip parameter the material doesn't have, unresolved material is reported on the object
+			if let Some(material) = scene.iter_obj().nth(m.object).and_then(|obj| obj.material.get()) {
+				if m.color.is_some() && material.color().is_none() {
+					report.warning(&field(&p, "color"), format!("material of object {} has no color, track is ignored", m.object));
+				}
+				if m.roughness.is_some() && material.roughness().is_none() {
+					report.warning(&field(&p, "roughness"), format!("material of object {} has no roughness, track is ignored", m.object));
+				}
+			}
+		}
+	}
+
+	// pose scene and camera at frame
+	// transform move linearly from frame to frame + 1 during camera shutter interval
+	pub fn apply(&self, frame: f32, scene: &mut Scene, camera: &mut Camera) {
+		if let Some(track) = &self.camera {
+			let pos = sample_or(&track.position, frame, camera.pos);
+			let next_pos = sample_or(&track.position, frame + 1.0, pos);
+			camera.pos = pos;
+
+			// rotation from current frame to next frame
+			let mut rot = UnitQuaternion::identity();
+			if let Some(euler) = track.rotation.as_ref().and_then(|t| t.sample(frame)) {
+				camera.set_rotation(Rotation3::from_euler_angles(euler.x, euler.y, euler.z));
+				let next_euler = sample_or(&track.rotation, frame + 1.0, euler);
+				rot = euler_to_quaternion(next_euler) * euler_to_quaternion(euler).inverse();
+			}
+
+			if camera.has_shutter_interval() {
+				// relative transform that move camera from current pose to next pose
+				let end = Similarity3::from_parts(
+					Translation3::from(next_pos.coords - rot * pos.coords), rot, 1.0
+				);
+				camera.motion = Some(Motion::new(Similarity3::identity(), end));
+			}
+		}
+
+		// track move the object in the space include and group placed it in, on top of its own motion
+		for track in &self.objects {
+			if let Some(obj) = scene.obj_mut(track.object) {
+				let own = obj.motion.unwrap_or_else(|| Motion::fixed(Similarity3::identity()));
+				obj.motion = Some(Motion::new(track.transform_at(frame) * own.start, track.transform_at(frame + 1.0) * own.end));
+			}
+		}
+
+		for track in &self.lights {
+			if let Some(light) = scene.light_mut(track.light) {
+				let color = light.emit_mut();
+				let base = track.color.as_ref().and_then(|t| t.sample(frame)).unwrap_or(*color);
+				*color = base * sample_or(&track.intensity, frame, 1.0);
+			}
+		}
+
+		for track in &self.materials {
+			if let Some(material) = scene.obj_mut(track.object).and_then(|obj| obj.material.make_mut()) {
+				if let (Some(color), Some(c)) = (track.color.as_ref().and_then(|t| t.sample(frame)), material.color_mut()) {
+					*c = color;
+				}
+				if let (Some(roughness), Some(r)) = (track.roughness.as_ref().and_then(|t| t.sample(frame)), material.roughness_mut()) {
+					*r = roughness;
+				}
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use assert_approx_eq::assert_approx_eq;
+
+	use super::*;
+
+	// every key is checked for nan, only finite neighbor for order
+	#[test]
+	fn track_validate_test() {
+		let key = |frame| Keyframe {frame, value: 0.0, interpolation: Interpolation::Linear};
+		let validate = |keys| {
+			let mut report = Report::new();
+			Track {keys}.validate("track", &mut report);
+			report.diagnostics().iter().map(|d| format!("{}: {}", d.path, d.message)).collect::<Vec<_>>()
+		};
+		assert_eq!(validate(vec![key(f32::NAN)]), vec!["track.keys[0].frame: must be finite"]);
+		assert_eq!(validate(vec![key(0.0), key(f32::NAN)]), vec!["track.keys[1].frame: must be finite"]);
+		assert_eq!(validate(vec![key(1.0), key(0.0)]), vec!["track.keys[1].frame: keyframe must be sorted by frame, got 0 after 1"]);
+	}
+
+	#[test]
+	fn track_sample_test() {
+		let track = Track::new(vec![
+			Keyframe {frame: 10.0, value: 1.0, interpolation: Interpolation::Linear},
+			Keyframe {frame: 0.0, value: 0.0, interpolation: Interpolation::Bezier(0.0, 1.0)},
+			Keyframe {frame: 20.0, value: 3.0, interpolation: Interpolation::Linear},
+		]);
+
+		assert_approx_eq!(track.sample(-5.0).unwrap(), 0.0);
+		assert_approx_eq!(track.sample(5.0).unwrap(), 0.5);
+		assert_approx_eq!(track.sample(15.0).unwrap(), 2.0);
+		assert_approx_eq!(track.sample(25.0).unwrap(), 3.0);
+		// bezier ease in and out
+		assert!(track.sample(2.0).unwrap() < 0.2);
+	}
+}
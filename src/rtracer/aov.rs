@@ -0,0 +1,301 @@
+/*
+arbitrary output variable, extra pass written next to the beauty image for compositing
+
+every pass is taken at the first hit of camera ray, after the beauty sample so beauty is the same with or without it
+lighting pass split light of the hit material by lobe, whitted beauty is summed from the very same split,
+so the 4 of them add up to beauty minus background
+id and depth pass keep the first sample of pixel (average of id is meaningless), sample count is summed,
+everything else is averaged like beauty
+*/
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use image::{ImageBuffer, Rgb};
+use nalgebra::{Point3, Unit, Vector3};
+use serde::{Deserialize, Serialize};
+
+use super::{Color3, HitInfo, RayCastInfo, Scene, SceneObject};
+use super::light::Light;
+use super::light_selection::luminance;
+use super::material::{LightSplit, Material, MaterialRef};
+use super::renderer::raycast_return_ref;
+use super::sampler::Sampler;
+
+pub type AovImage = ImageBuffer<Rgb<f32>, Vec<f32>>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Aov {
+	// distance along camera ray, infinite on miss
+	Depth,
+	// world space shading normal
+	Normal,
+	Albedo,
+	// 1 + index of object in flattened scene, 0 on miss
+	ObjectId,
+	// 1 + index of material, object sharing a named material share the id
+	MaterialId,
+	DirectDiffuse,
+	IndirectDiffuse,
+	DirectSpecular,
+	IndirectSpecular,
+	// fraction of unoccluded light that is blocked, 1 = fully in shadow
+	Shadow,
+	SampleCount,
+}
+
+impl FromStr for Aov {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		Aov::ALL.iter()
+			.find(|aov| aov.name() == s)
+			.copied()
+			.ok_or_else(|| format!(
+				"unknown aov '{}', expected one of: {}", s, Aov::ALL.iter().map(|aov| aov.name()).collect::<Vec<_>>().join(", ")
+			))
+	}
+}
+
+impl Aov {
+	pub const ALL: [Aov; 11] = [
+		Aov::Depth, Aov::Normal, Aov::Albedo, Aov::ObjectId, Aov::MaterialId,
+		Aov::DirectDiffuse, Aov::IndirectDiffuse, Aov::DirectSpecular, Aov::IndirectSpecular,
+		Aov::Shadow, Aov::SampleCount,
+	];
+
+	// also used as suffix of output file
+	pub fn name(self) -> &'static str {
+		match self {
+			Aov::Depth => "depth",
+			Aov::Normal => "normal",
+			Aov::Albedo => "albedo",
+			Aov::ObjectId => "object-id",
+			Aov::MaterialId => "material-id",
+			Aov::DirectDiffuse => "direct-diffuse",
+			Aov::IndirectDiffuse => "indirect-diffuse",
+			Aov::DirectSpecular => "direct-specular",
+			Aov::IndirectSpecular => "indirect-specular",
+			Aov::Shadow => "shadow",
+			Aov::SampleCount => "sample-count",
+		}
+	}
+
+	pub fn is_lighting(self) -> bool {
+		matches!(self, Aov::DirectDiffuse | Aov::IndirectDiffuse | Aov::DirectSpecular | Aov::IndirectSpecular)
+	}
+}
+
+// what the render loop need to fill the requested pass, built once per render
+pub struct AovContext<'a> {
+	scene: &'a Scene,
+	aovs: Vec<Aov>,
+	// id of material of each object
+	material_ids: Vec<u32>,
+}
+
+impl<'a> AovContext<'a> {
+	pub fn new(scene: &'a Scene, aovs: &[Aov]) -> Self {
+		// named material is shared by name, inline material by the Arc it live in
+		let mut named: HashMap<&str, u32> = HashMap::new();
+		let mut inline: HashMap<*const (), u32> = HashMap::new();
+		let mut next = 0;
+		let material_ids = scene.iter_obj()
+			.map(|obj| match &obj.material {
+				MaterialRef::Named(name, _) => *named.entry(name.as_str()).or_insert_with(|| { next += 1; next }),
+				MaterialRef::Inline(material) =>
+					*inline.entry(Arc::as_ptr(material) as *const ()).or_insert_with(|| { next += 1; next }),
+			})
+			.collect();
+		AovContext {scene, aovs: aovs.to_vec(), material_ids}
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.aovs.is_empty()
+	}
+
+	pub fn aovs(&self) -> &[Aov] {
+		&self.aovs
+	}
+
+	// lighting pass need the material to split its light
+	pub fn needs_split(&self) -> bool {
+		self.aovs.iter().any(|aov| aov.is_lighting())
+	}
+
+	pub fn empty_pixel(&self) -> Vec<Color3> {
+		vec![Color3::zeros(); self.aovs.len()]
+	}
+
+	// value of every pass for one camera ray, added into pixel
+	// split is the one beauty was made of, other integrator leave it to be traced here
+	pub fn sample(
+		&self,
+		pixel: &mut [Color3],
+		sample_index: u32,
+		split: Option<LightSplit>,
+		(origin, dir): (Point3<f32>, Unit<Vector3<f32>>),
+		info: RayCastInfo,
+		sampler: &mut impl Sampler) {
+		let scene = self.scene;
+		let split = match split {
+			Some(split) => split,
+			None if self.needs_split() => trace_split(scene, origin, dir, info, sampler).1,
+			None => LightSplit::zeros(),
+		};
+		let hit = _raycast_index(scene, origin, dir, info.time());
+
+		for (value, &aov) in pixel.iter_mut().zip(&self.aovs) {
+			match aov {
+				Aov::Depth | Aov::ObjectId | Aov::MaterialId => if sample_index == 0 {
+					*value = self._first_hit_value(aov, &hit);
+				},
+				Aov::SampleCount => *value += Color3::repeat(1.0),
+				Aov::Normal => if let Some((hit, _, _)) = &hit {
+					*value += hit.normal.into_inner();
+				},
+				Aov::Albedo => if let Some((hit, obj, _)) = &hit {
+					*value += obj.material.get().map_or(Color3::zeros(), |m| m.albedo(hit));
+				},
+				Aov::DirectDiffuse => *value += split.direct_diffuse,
+				Aov::IndirectDiffuse => *value += split.indirect_diffuse,
+				Aov::DirectSpecular => *value += split.direct_specular,
+				Aov::IndirectSpecular => *value += split.indirect_specular,
+				Aov::Shadow => if let Some((hit, _, _)) = &hit {
+					*value += Color3::repeat(_shadow(scene, hit, info.time(), sampler));
+				},
+			}
+		}
+	}
+
+	// average what was summed over the spp sample pixel took, pixel without sample stay black
+	pub fn finish(&self, mut pixel: Vec<Color3>, spp: u32) -> Vec<Color3> {
+		if spp == 0 {
+			return pixel;
+		}
+		for (value, &aov) in pixel.iter_mut().zip(&self.aovs) {
+			match aov {
+				Aov::Depth | Aov::ObjectId | Aov::MaterialId | Aov::SampleCount => {},
+				_ => *value /= spp as f32,
+			}
+		}
+		pixel
+	}
+
+	fn _first_hit_value(&self, aov: Aov, hit: &Option<(HitInfo, &SceneObject, usize)>) -> Color3 {
+		match (aov, hit) {
+			(Aov::Depth, Some((hit, _, _))) => Color3::repeat(hit.dist),
+			(Aov::Depth, None) => Color3::repeat(f32::INFINITY),
+			(Aov::ObjectId, Some((_, _, index))) => Color3::repeat((index + 1) as f32),
+			(Aov::MaterialId, Some((_, _, index))) => Color3::repeat(self.material_ids[*index] as f32),
+			_ => Color3::zeros(),
+		}
+	}
+}
+
+// whitted radiance of camera ray and the split of it at first hit, background isn't in the split
+pub fn trace_split(
+	scene: &Scene,
+	origin: Point3<f32>,
+	dir: Unit<Vector3<f32>>,
+	info: RayCastInfo,
+	sampler: &mut impl Sampler)
+	-> (Color3, LightSplit) {
+	// same step as raycast_compute_light, so beauty doesn't change when pass is requested
+	let mut info = info;
+	info.increment_ray_number();
+	match raycast_return_ref(scene, origin, dir, info.time()) {
+		Some((hit, obj)) => {
+			let split = obj.material.get()
+				.map_or_else(LightSplit::zeros, |m| m.compute_light_split(scene, &hit, obj, info, sampler));
+			(split.total(), split)
+		},
+		None => (scene.get_skylight(), LightSplit::zeros()),
+	}
+}
+
+// like raycast_return_ref, also give index of object
+fn _raycast_index(scene: &Scene, origin: Point3<f32>, dir: Unit<Vector3<f32>>, time: f32)
+				  -> Option<(HitInfo, &SceneObject, usize)> {
+	let (hit, obj) = raycast_return_ref(scene, origin, dir, time)?;
+	let index = scene.iter_obj().position(|o| std::ptr::eq(o, obj))?;
+	Some((hit, obj, index))
+}
+
+// one sample of every light, blocked over unblocked luminance reaching the surface
+fn _shadow(scene: &Scene, hit: &HitInfo, time: f32, sampler: &mut impl Sampler) -> f32 {
+	let (mut unoccluded, mut lit) = (0.0, 0.0);
+	for light in scene.iter_light() {
+		let sample = light.sample(hit.intersection, sampler.get_2d());
+		let cos = hit.normal.dot(&sample.dir);
+		let pdf = sample.pdf.unwrap_or(1.0);
+		if cos <= 0.0 || pdf <= 0.0 {
+			continue;
+		}
+		let light = luminance(&sample.light) * cos / pdf;
+		unoccluded += light;
+		if sample.visible(scene, hit.intersection, time) {
+			lit += light;
+		}
+	}
+	if unoccluded > 0.0 { 1.0 - lit / unoccluded } else { 0.0 }
+}
+
+// portable float map, little endian, row from bottom to top
+// float pass (negative normal, infinite depth) survive as is, most compositor read it
+pub fn save_pfm(image: &AovImage, path: impl AsRef<Path>) -> io::Result<()> {
+	let mut file = BufWriter::new(File::create(path)?);
+	write!(file, "PF\n{} {}\n-1.0\n", image.width(), image.height())?;
+	for y in (0..image.height()).rev() {
+		for x in 0..image.width() {
+			for channel in image.get_pixel(x, y).data.iter() {
+				file.write_all(&channel.to_le_bytes())?;
+			}
+		}
+	}
+	file.flush()
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::rtracer::{Camera, RenderSettings, render_with_aovs};
+	use crate::rtracer::geometric::Sphere;
+	use crate::rtracer::light::PointLight;
+	use crate::rtracer::material::{Diffuse, Reflective};
+
+	use super::*;
+
+	// lighting pass sum to beauty, id and count are exact
+	#[test]
+	fn aov_pass_test() {
+		let mut scene = Scene::new();
+		scene.add_obj(SceneObject::new(Sphere {pos: Point3::new(3.0, -0.6, 0.0), radius: 0.5}, Diffuse::new(Color3::repeat(0.8))));
+		scene.add_obj(SceneObject::new(Sphere {pos: Point3::new(3.0, 0.6, 0.0), radius: 0.5}, Reflective::new(0.3, 2)));
+		scene.add_light(PointLight::new(Point3::new(1.0, 0.0, 2.0), Color3::repeat(2.0)).into());
+		let camera = Camera::new(Point3::origin(), nalgebra::Rotation3::identity());
+
+		let mut settings = RenderSettings::new(16, 8);
+		settings.spp = 2;
+		settings.seed = Some(7);
+		settings.aovs = vec![Aov::DirectDiffuse, Aov::DirectSpecular, Aov::IndirectSpecular, Aov::ObjectId, Aov::SampleCount];
+		let output = render_with_aovs(&scene, &camera, &settings).unwrap();
+		let (raw, passes) = (&output.radiance, &output.aovs);
+		assert_eq!(passes.len(), 5);
+
+		let (w, h) = raw.dimensions();
+		let mut seen = [false; 3];
+		for (x, y) in (0..h).flat_map(|y| (0..w).map(move |x| (x, y))) {
+			let sum: f32 = passes[..3].iter().map(|(_, image)| image.get_pixel(x, y)[0]).sum();
+			let beauty = raw.get_pixel(x, y)[0];
+			assert!((sum - beauty).abs() < 1e-4, "({}, {}): {} vs {}", x, y, sum, beauty);
+			let id = passes[3].1.get_pixel(x, y)[0];
+			seen[id as usize] = true;
+			assert_eq!(passes[4].1.get_pixel(x, y)[0], 2.0);
+		}
+		assert_eq!(seen, [true; 3]);
+		assert_eq!("direct-specular".parse::<Aov>(), Ok(Aov::DirectSpecular));
+	}
+}
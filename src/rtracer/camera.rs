@@ -2,6 +2,8 @@ use nalgebra::{Point3, Rotation3, Unit, Vector3};
 use serde::{Deserialize, Serialize};
 use serde;
 
+use crate::rtracer::motion::Motion;
+use crate::rtracer::validation::{field, Report, Validate};
 use crate::rtracer::serde_interface::CameraSerdeInterface;
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -12,6 +14,14 @@ pub struct Camera {
 	forward: Vector3<f32>,
 	right: Vector3<f32>,
 	up: Vector3<f32>,
+	// shutter interval, ray time is sampled in [shutter_open, shutter_close]
+	#[serde(default)]
+	pub shutter_open: f32,
+	#[serde(default)]
+	pub shutter_close: f32,
+	// camera movement during shutter interval, apply on top of pos and rotation
+	#[serde(default)]
+	pub motion: Option<Motion>,
 }
 
 impl Camera {
@@ -21,18 +31,116 @@ impl Camera {
 			forward: rot * Vector3::new(1.0, 0.0, 0.0),
 			right: rot * Vector3::new(0.0, 1.0, 0.0),
 			up: rot * Vector3::new(0.0, 0.0, 1.0),
+			shutter_open: 0.0,
+			shutter_close: 0.0,
+			motion: None,
 		}
 	}
-	pub fn ray_at_pixel_position(&self, px:u32, py:u32, unit_per_pixel: f32, half_width: u32, half_height: u32)
+
+	// axis are normalized so field of view is 90 degree until set_fov, axis may form a mirrored frame
+	pub fn from_axes(pos: Point3<f32>, forward: Vector3<f32>, right: Vector3<f32>, up: Vector3<f32>) -> Camera {
+		Camera {
+			pos,
+			forward: forward.normalize(),
+			right: right.normalize(),
+			up: up.normalize(),
+			..Camera::new(pos, Rotation3::identity())
+		}
+	}
+
+	// rotate camera from base axis, keep position, field of view, shutter and motion
+	pub fn set_rotation(&mut self, rot: Rotation3<f32>) {
+		let posed = Camera::new(self.pos, rot);
+		self.forward = posed.forward * self.forward.norm();
+		self.right = posed.right;
+		self.up = posed.up;
+	}
+
+	// horizontal field of view in radian, assume default viewport size of 2 unit
+	// length of forward is the distance from camera to viewport, 1 is 90 degree
+	pub fn set_fov(&mut self, fov: f32) {
+		self.forward = self.forward.normalize() / (fov / 2.0).tan();
+	}
+
+	pub fn with_shutter(mut self, open: f32, close: f32) -> Camera {
+		self.shutter_open = open;
+		self.shutter_close = close;
+		self
+	}
+
+	pub fn with_motion(mut self, motion: Motion) -> Camera {
+		self.motion = Some(motion);
+		self
+	}
+
+	// pixel position can be fractional for sub-pixel sample
+	pub fn ray_at_pixel_position(&self, px: f32, py: f32, unit_per_pixel: f32, half_width: u32, half_height: u32)
 		-> Unit<Vector3<f32>> {
-		let i = unit_per_pixel * (px as i32 - half_width as i32) as f32;
-		let j = unit_per_pixel * (py as i32 - half_height as i32) as f32;
+		let i = unit_per_pixel * (px - half_width as f32);
+		let j = unit_per_pixel * (py - half_height as f32);
 		
 		let dir = self.forward + i * self.right - j * self.up;
 		
 		Unit::new_normalize(dir)
 	}
+
+	// origin and direction of ray at pixel position, camera is moved by motion at given time
+	pub fn ray_at_time(&self, px: f32, py: f32, unit_per_pixel: f32, half_width: u32, half_height: u32, time: f32)
+		-> (Point3<f32>, Unit<Vector3<f32>>) {
+		let dir = self.ray_at_pixel_position(px, py, unit_per_pixel, half_width, half_height);
+
+		match &self.motion {
+			None => (self.pos, dir),
+			Some(motion) => {
+				let trans = motion.at(time);
+				(trans * self.pos, Unit::new_normalize(trans.isometry.rotation * dir.into_inner()))
+			}
+		}
+	}
+
+	pub fn has_shutter_interval(&self) -> bool {
+		self.shutter_close > self.shutter_open
+	}
+
+	// map u in [0, 1) to time in shutter interval
+	pub fn shutter_time(&self, u: f32) -> f32 {
+		self.shutter_open + u * (self.shutter_close - self.shutter_open)
+	}
+
 	pub fn get_rotation(&self) -> Rotation3<f32> {
 		Rotation3::face_towards(&self.forward, &self.up)
 	}
 }
+
+impl Validate for Camera {
+	fn validate(&self, path: &str, report: &mut Report) {
+		report.check_point(&field(path, "pos"), &self.pos);
+
+		let axes = [("forward", &self.forward), ("right", &self.right), ("up", &self.up)];
+		let before = report.error_count();
+		for (name, axis) in axes.iter() {
+			report.check_direction(&field(path, name), axis);
+		}
+
+		// only look at orientation when every axis is usable
+		if report.error_count() == before {
+			let (f, r, u) = (self.forward.normalize(), self.right.normalize(), self.up.normalize());
+			if f.cross(&r).norm() < 1e-3 || f.cross(&u).norm() < 1e-3 || r.cross(&u).norm() < 1e-3 {
+				report.error(path, "camera axis are parallel, image would be degenerate");
+			}
+			else if f.dot(&r).abs() > 1e-3 || f.dot(&u).abs() > 1e-3 || r.dot(&u).abs() > 1e-3 {
+				report.warning(path, "camera axis are not perpendicular, image will be skewed");
+			}
+		}
+
+		report.check_finite(&field(path, "shutter_open"), self.shutter_open);
+		report.check_finite(&field(path, "shutter_close"), self.shutter_close);
+		if self.shutter_close < self.shutter_open {
+			report.error(
+				&field(path, "shutter_close"),
+				format!("must not be before shutter_open ({} < {})", self.shutter_close, self.shutter_open)
+			);
+		}
+		self.motion.validate(&field(path, "motion"), report);
+	}
+}
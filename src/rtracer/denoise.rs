@@ -0,0 +1,216 @@
+/*
+edge avoiding a-trous wavelet filter (Dammertz et al. 2010), the spatial part of SVGF
+
+each pass blur with 5x5 B3 spline kernel whose tap are 2^i pixel apart, so few pass cover a wide radius.
+tap is weighted down when color, normal or albedo differ from center pixel, so edge and texture survive.
+color difference is measured against local standard deviation of luminance, estimated from neighborhood
+and filtered along with light, so noisy area is blurred more than clean one whatever the exposure.
+albedo is divided out before filtering and multiplied back after, texture detail isn't blurred with noise
+*/
+use image::{ImageBuffer, Rgb};
+use rayon::prelude::*;
+
+use super::Color3;
+use super::aov::AovImage;
+use super::light_selection::luminance;
+
+const KERNEL: [f32; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+// albedo below this is treated as no albedo (background), light isn't divided by it
+const MIN_ALBEDO: f32 = 1e-3;
+
+// half size of window variance is first estimated over
+const VARIANCE_RADIUS: i32 = 2;
+
+// auxiliary buffer steering the filter, same for every pass
+struct Guide {
+	albedos: Vec<Color3>,
+	normals: Vec<Color3>,
+	// pixel without sample (cancelled render) is neither filtered nor used as tap
+	sampled: Vec<bool>,
+	width: i32,
+	height: i32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Denoise {
+	// number of a-trous pass, radius covered is 2^(iterations + 1)
+	pub iterations: u32,
+	// tolerance of each guide, larger blur across bigger difference
+	// color tolerance is in standard deviation of local luminance
+	pub sigma_color: f32,
+	pub sigma_normal: f32,
+	pub sigma_albedo: f32,
+	// keep the image before filtering in RenderOutput::noisy
+	pub keep_noisy: bool,
+}
+
+impl Default for Denoise {
+	fn default() -> Self {
+		Denoise {iterations: 5, sigma_color: 4.0, sigma_normal: 0.1, sigma_albedo: 0.1, keep_noisy: false}
+	}
+}
+
+impl Denoise {
+	pub fn apply(&self, radiance: &AovImage, albedo: &AovImage, normal: &AovImage) -> AovImage {
+		let (width, height) = radiance.dimensions();
+		self.apply_sampled(radiance, albedo, normal, &vec![true; (width * height) as usize])
+	}
+
+	// like apply, pixel whose sampled is false is left as is
+	pub fn apply_sampled(&self, radiance: &AovImage, albedo: &AovImage, normal: &AovImage, sampled: &[bool]) -> AovImage {
+		let (width, height) = radiance.dimensions();
+		let at = |image: &AovImage, x: u32, y: u32| {
+			let p = image.get_pixel(x, y);
+			Color3::new(p[0], p[1], p[2])
+		};
+		let guide = Guide {
+			albedos: albedo.enumerate_pixels().map(|(x, y, _)| at(albedo, x, y)).collect(),
+			normals: normal.enumerate_pixels().map(|(x, y, _)| at(normal, x, y)).collect(),
+			sampled: sampled.to_vec(),
+			width: width as i32,
+			height: height as i32,
+		};
+		let mut light: Vec<Color3> = radiance.enumerate_pixels()
+			.zip(&guide.albedos)
+			.map(|((x, y, _), albedo)| at(radiance, x, y).zip_map(albedo, _demodulate))
+			.collect();
+
+		let mut variance = _local_variance(&light, &guide);
+		for i in 0..self.iterations {
+			let (filtered, filtered_variance) = self._pass(&light, &variance, &guide, 1 << i);
+			light = filtered;
+			variance = filtered_variance;
+		}
+
+		ImageBuffer::from_fn(width, height, |x, y| {
+			let index = (y * width + x) as usize;
+			let color = light[index].zip_map(&guide.albedos[index], _remodulate);
+			Rgb([color.x, color.y, color.z])
+		})
+	}
+
+	// one a-trous pass, variance is filtered with squared weight like SVGF
+	fn _pass(&self, light: &[Color3], variance: &[f32], guide: &Guide, step: i32) -> (Vec<Color3>, Vec<f32>) {
+		let (w, h, albedos, normals) = (guide.width, guide.height, &guide.albedos, &guide.normals);
+		(0..h).into_par_iter().flat_map_iter(|y| (0..w).map(move |x| (x, y))).map(|(x, y)| {
+			let center = (y * w + x) as usize;
+			if !guide.sampled[center] {
+				return (light[center], variance[center]);
+			}
+			let (luma, normal, albedo) = (luminance(&light[center]), normals[center], albedos[center]);
+			let deviation = self.sigma_color * _blurred_variance(variance, guide, x, y).sqrt() + 1e-6;
+			let (mut sum, mut sum_variance, mut total) = (Color3::zeros(), 0.0, 0.0);
+			for (ky, ry) in KERNEL.iter().enumerate() {
+				for (kx, rx) in KERNEL.iter().enumerate() {
+					let (qx, qy) = (x + (kx as i32 - 2) * step, y + (ky as i32 - 2) * step);
+					if qx < 0 || qy < 0 || qx >= w || qy >= h || !guide.sampled[(qy * w + qx) as usize] {
+						continue;
+					}
+					let q = (qy * w + qx) as usize;
+					let weight = rx * ry
+						* (-(luma - luminance(&light[q])).abs() / deviation).exp()
+						* _gaussian((normal - normals[q]).norm_squared(), self.sigma_normal)
+						* _gaussian((albedo - albedos[q]).norm_squared(), self.sigma_albedo);
+					sum += light[q] * weight;
+					sum_variance += variance[q] * weight * weight;
+					total += weight;
+				}
+			}
+			// center tap always weigh something, total is never 0
+			(sum / total, sum_variance / (total * total))
+		}).unzip()
+	}
+}
+
+// variance of luminance over a window around each pixel, only across similar normal
+fn _local_variance(light: &[Color3], guide: &Guide) -> Vec<f32> {
+	let (w, h) = (guide.width, guide.height);
+	(0..h).into_par_iter().flat_map_iter(|y| (0..w).map(move |x| (x, y))).map(|(x, y)| {
+		let center = (y * w + x) as usize;
+		if !guide.sampled[center] {
+			return 0.0;
+		}
+		let normal = guide.normals[center];
+		let (mut sum, mut sum_sq, mut count) = (0.0, 0.0, 0.0);
+		for qy in (y - VARIANCE_RADIUS).max(0)..=(y + VARIANCE_RADIUS).min(h - 1) {
+			for qx in (x - VARIANCE_RADIUS).max(0)..=(x + VARIANCE_RADIUS).min(w - 1) {
+				let q = (qy * w + qx) as usize;
+				if !guide.sampled[q] || (guide.normals[q] - normal).norm_squared() > 0.1 {
+					continue;
+				}
+				let luma = luminance(&light[q]);
+				sum += luma;
+				sum_sq += luma * luma;
+				count += 1.0;
+			}
+		}
+		let mean = sum / count;
+		(sum_sq / count - mean * mean).max(0.0)
+	}).collect()
+}
+
+// 3x3 gaussian of variance, single pixel estimate is too unstable to steer the filter
+fn _blurred_variance(variance: &[f32], guide: &Guide, x: i32, y: i32) -> f32 {
+	let (w, h) = (guide.width, guide.height);
+	let (mut sum, mut total) = (0.0, 0.0);
+	for dy in -1..=1 {
+		for dx in -1..=1 {
+			let (qx, qy) = (x + dx, y + dy);
+			if qx < 0 || qy < 0 || qx >= w || qy >= h || !guide.sampled[(qy * w + qx) as usize] {
+				continue;
+			}
+			let weight = if dx == 0 { 0.5 } else { 0.25 } * if dy == 0 { 0.5 } else { 0.25 };
+			sum += variance[(qy * w + qx) as usize] * weight;
+			total += weight;
+		}
+	}
+	// center is sampled whenever this is asked, total is never 0
+	sum / total
+}
+
+fn _gaussian(dist_sq: f32, sigma: f32) -> f32 {
+	if sigma <= 0.0 { if dist_sq > 0.0 { 0.0 } else { 1.0 } } else { (-dist_sq / (sigma * sigma)).exp() }
+}
+
+fn _demodulate(light: f32, albedo: f32) -> f32 {
+	if albedo > MIN_ALBEDO { light / albedo } else { light }
+}
+
+fn _remodulate(light: f32, albedo: f32) -> f32 {
+	if albedo > MIN_ALBEDO { light * albedo } else { light }
+}
+
+#[cfg(test)]
+mod tests {
+	use rand::prelude::{Rng, SeedableRng, SmallRng};
+
+	use super::*;
+
+	fn constant(width: u32, height: u32, mut f: impl FnMut(u32, u32) -> f32) -> AovImage {
+		ImageBuffer::from_fn(width, height, |x, y| { let v = f(x, y); Rgb([v, v, v]) })
+	}
+
+	fn rmse(a: &AovImage, b: &AovImage) -> f32 {
+		let sum: f32 = a.pixels().zip(b.pixels()).map(|(p, q)| (p[0] - q[0]).powi(2)).sum();
+		(sum / (a.width() * a.height()) as f32).sqrt()
+	}
+
+	// noise on flat area is removed, step between two surface of different normal stay sharp
+	#[test]
+	fn denoise_keep_edge_test() {
+		let (width, height) = (32, 16);
+		let clean = constant(width, height, |x, _| if x < 16 { 0.2 } else { 0.8 });
+		let mut rng = SmallRng::seed_from_u64(1);
+		let noisy = constant(width, height, |x, y| clean.get_pixel(x, y)[0] * rng.gen_range(0.5, 1.5));
+		let albedo = constant(width, height, |_, _| 1.0);
+		let normal: AovImage = ImageBuffer::from_fn(width, height, |x, _| if x < 16 { Rgb([0.0, 0.0, 1.0]) } else { Rgb([1.0, 0.0, 0.0]) });
+
+		let denoised = Denoise::default().apply(&noisy, &albedo, &normal);
+		assert!(rmse(&denoised, &clean) < rmse(&noisy, &clean) / 3.0, "{} vs {}", rmse(&denoised, &clean), rmse(&noisy, &clean));
+		for y in 0..height {
+			assert!((denoised.get_pixel(15, y)[0] - 0.2).abs() < 0.05);
+			assert!((denoised.get_pixel(16, y)[0] - 0.8).abs() < 0.1);
+		}
+	}
+}
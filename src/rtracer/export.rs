@@ -0,0 +1,2 @@
+// writer of scene in other format, save_scene_data pick one by file extension
+pub mod obj;
@@ -0,0 +1,287 @@
+use std::collections::HashMap;
+use std::f32::consts::PI;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use nalgebra::{Point3, Similarity3, Unit, Vector2, Vector3};
+
+use crate::rtracer::{MaterialRef, Materials, SceneData};
+use crate::rtracer::geometric::{MeshData, Shapes};
+use crate::rtracer::parser::SceneParserError;
+
+const SPHERE_SEGMENTS: usize = 32;
+const SPHERE_RINGS: usize = 16;
+const DISC_SEGMENTS: usize = 32;
+
+// write geometry to path and material next to it with .mtl extension
+pub(crate) fn save_obj(path: &Path, scene_data: &SceneData) -> Result<(), SceneParserError> {
+	let mtl_path = path.with_extension("mtl");
+	let mtl_name = mtl_path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+	let (obj, mtl) = write_obj(scene_data, &mtl_name);
+	fs::write(path, obj)?;
+	fs::write(mtl_path, mtl)?;
+	Ok(())
+}
+
+/*
+every object is tessellated into triangle in world space, posed at shutter open
+infinite plane become a quad large enough to hold the rest of the scene
+light and camera has no place in obj, only geometry and material are written
+*/
+pub fn write_obj(scene_data: &SceneData, mtl_name: &str) -> (String, String) {
+	let scene = scene_data.scene.flatten();
+	let time = scene_data.camera.shutter_open;
+
+	let mut obj = String::new();
+	let mut mtl = String::new();
+	writeln!(obj, "mtllib {}", mtl_name).unwrap();
+
+	let poses: Vec<Similarity3<f32>> = scene.iter_obj()
+		.map(|o| o.transform_at(time))
+		.collect();
+	let meshes: Vec<MeshData> = scene.iter_obj().zip(poses.iter())
+		.map(|(o, pose)| {
+			let mut mesh = _tessellate_finite(&o.shape);
+			_transform(&mut mesh, pose);
+			mesh
+		})
+		.collect();
+	let (lo, hi) = _bounds(meshes.iter().flat_map(|m| m.positions.iter()).chain(Some(&scene_data.camera.pos)));
+	let (center, size) = ((lo + hi.coords) / 2.0, (hi - lo).norm().max(1.0));
+
+	let mut material_names: HashMap<*const Materials, String> = HashMap::new();
+	// inline material get a generated name not taken by the material library
+	let mut generated = 0;
+	let mut offset = (1, 1, 1);
+	for (i, ((obj_data, pose), mut mesh)) in scene.iter_obj().zip(poses.iter()).zip(meshes).enumerate() {
+		if let Shapes::InfinitePlane(plane) = &obj_data.shape {
+			let norm = Unit::new_normalize(pose.isometry.rotation * plane.norm.into_inner());
+			mesh = _quad(pose * plane.pos, norm, center, size);
+		}
+
+		let material = match &obj_data.material {
+			MaterialRef::Named(name, _) => name.clone(),
+			MaterialRef::Inline(m) => {
+				material_names.entry(Arc::as_ptr(m)).or_insert_with(|| {
+					let name = loop {
+						let name = format!("material{}", generated);
+						generated += 1;
+						if !scene_data.materials.contains_key(&name) {
+							break name;
+						}
+					};
+					_write_material(&mut mtl, &name, m);
+					name
+				}).clone()
+			},
+		};
+
+		writeln!(obj, "o object{}", i).unwrap();
+		writeln!(obj, "usemtl {}", material).unwrap();
+		_write_mesh(&mut obj, &mesh, &mut offset);
+	}
+
+	for (name, material) in scene_data.materials.iter() {
+		_write_material(&mut mtl, name, material);
+	}
+	(obj, mtl)
+}
+
+fn _write_material(mtl: &mut String, name: &str, material: &Materials) {
+	writeln!(mtl, "newmtl {}", name).unwrap();
+	match material {
+		Materials::Diffuse(_) => {
+			let c = material.color().unwrap_or_else(|| Vector3::repeat(1.0));
+			writeln!(mtl, "Kd {} {} {}", c.x, c.y, c.z).unwrap();
+			if let Some(texture) = material.texture() {
+				writeln!(mtl, "map_Kd {}", texture.path()).unwrap();
+			}
+			writeln!(mtl, "illum 1").unwrap();
+		},
+		Materials::PerfectReflective(_) => {
+			let c = material.color().unwrap_or_else(|| Vector3::repeat(1.0));
+			writeln!(mtl, "Kd 0 0 0\nKs {} {} {}\nNs 1000\nillum 3", c.x, c.y, c.z).unwrap();
+		},
+		// blinn-phong exponent of the same roughness
+		Materials::Reflective(_) => {
+			let roughness = material.roughness().unwrap_or(0.0).max(1e-3);
+			let exponent = (2.0 / (roughness * roughness) - 2.0).clamp(0.0, 1000.0);
+			writeln!(mtl, "Kd 0 0 0\nKs 1 1 1\nNs {}\nillum 3", exponent).unwrap();
+		},
+	}
+	writeln!(mtl).unwrap();
+}
+
+// index in obj start from 1 and count across every object
+fn _write_mesh(obj: &mut String, mesh: &MeshData, offset: &mut (usize, usize, usize)) {
+	for p in mesh.positions.iter() {
+		writeln!(obj, "v {} {} {}", p.x, p.y, p.z).unwrap();
+	}
+	for uv in mesh.uvs.iter() {
+		writeln!(obj, "vt {} {}", uv.x, uv.y).unwrap();
+	}
+	for n in mesh.normals.iter() {
+		writeln!(obj, "vn {} {} {}", n.x, n.y, n.z).unwrap();
+	}
+
+	let (v, vt, vn) = *offset;
+	let (has_uv, has_normal) = (!mesh.uvs.is_empty(), !mesh.normals.is_empty());
+	for triangle in mesh.triangles.iter() {
+		let corners: Vec<String> = triangle.iter()
+			.map(|&i| match (has_uv, has_normal) {
+				(true, true) => format!("{}/{}/{}", v + i as usize, vt + i as usize, vn + i as usize),
+				(true, false) => format!("{}/{}", v + i as usize, vt + i as usize),
+				(false, true) => format!("{}//{}", v + i as usize, vn + i as usize),
+				(false, false) => format!("{}", v + i as usize),
+			})
+			.collect();
+		writeln!(obj, "f {}", corners.join(" ")).unwrap();
+	}
+	*offset = (v + mesh.positions.len(), vt + mesh.uvs.len(), vn + mesh.normals.len());
+}
+
+fn _empty_mesh() -> MeshData {
+	MeshData {positions: Vec::new(), normals: Vec::new(), uvs: Vec::new(), colors: Vec::new(), triangles: Vec::new()}
+}
+
+// object space triangle of every bounded shape, infinite plane is sized later
+fn _tessellate_finite(shape: &Shapes) -> MeshData {
+	match shape {
+		Shapes::Sphere(s) => _sphere(s.pos, s.radius),
+		Shapes::Disc(d) => _disc(d.pos, d.norm, d.r_sq.sqrt()),
+		Shapes::Mesh(m) => {
+			let mut mesh = m.data().clone();
+			mesh.colors.clear();
+			mesh
+		},
+		Shapes::InfinitePlane(_) => _empty_mesh(),
+	}
+}
+
+fn _transform(mesh: &mut MeshData, trans: &Similarity3<f32>) {
+	for p in mesh.positions.iter_mut() {
+		*p = trans * *p;
+	}
+	for n in mesh.normals.iter_mut() {
+		*n = trans.isometry.rotation * *n;
+	}
+}
+
+fn _bounds<'a>(points: impl Iterator<Item = &'a Point3<f32>>) -> (Point3<f32>, Point3<f32>) {
+	let mut lo = Point3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY);
+	let mut hi = Point3::new(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY);
+	for p in points {
+		for k in 0..3 {
+			lo[k] = lo[k].min(p[k]);
+			hi[k] = hi[k].max(p[k]);
+		}
+	}
+	(lo, hi)
+}
+
+// two axis perpendicular to norm, tangent cross bitangent give norm
+fn _tangents(norm: &Unit<Vector3<f32>>) -> (Vector3<f32>, Vector3<f32>) {
+	let helper = if norm.x.abs() < 0.9 { Vector3::x() } else { Vector3::y() };
+	let tangent = helper.cross(norm).normalize();
+	(tangent, norm.cross(&tangent))
+}
+
+// uv sphere with z as pole
+fn _sphere(pos: Point3<f32>, radius: f32) -> MeshData {
+	let mut mesh = _empty_mesh();
+	for i in 0..=SPHERE_RINGS {
+		let theta = PI * i as f32 / SPHERE_RINGS as f32;
+		for j in 0..=SPHERE_SEGMENTS {
+			let phi = 2.0 * PI * j as f32 / SPHERE_SEGMENTS as f32;
+			let n = Vector3::new(theta.sin() * phi.cos(), theta.sin() * phi.sin(), theta.cos());
+			mesh.positions.push(pos + n * radius);
+			mesh.normals.push(n);
+			mesh.uvs.push(Vector2::new(j as f32 / SPHERE_SEGMENTS as f32, 1.0 - i as f32 / SPHERE_RINGS as f32));
+		}
+	}
+	let row = SPHERE_SEGMENTS as u32 + 1;
+	for i in 0..SPHERE_RINGS as u32 {
+		for j in 0..SPHERE_SEGMENTS as u32 {
+			let (a, b) = (i * row + j, (i + 1) * row + j);
+			mesh.triangles.push([a, b, b + 1]);
+			mesh.triangles.push([a, b + 1, a + 1]);
+		}
+	}
+	mesh
+}
+
+fn _disc(pos: Point3<f32>, norm: Unit<Vector3<f32>>, radius: f32) -> MeshData {
+	let (tangent, bitangent) = _tangents(&norm);
+	let mut mesh = _empty_mesh();
+	mesh.positions.push(pos);
+	for k in 0..DISC_SEGMENTS {
+		let angle = 2.0 * PI * k as f32 / DISC_SEGMENTS as f32;
+		mesh.positions.push(pos + (tangent * angle.cos() + bitangent * angle.sin()) * radius);
+	}
+	mesh.normals = vec![norm.into_inner(); mesh.positions.len()];
+	for k in 0..DISC_SEGMENTS as u32 {
+		mesh.triangles.push([0, k + 1, (k + 1) % DISC_SEGMENTS as u32 + 1]);
+	}
+	mesh
+}
+
+// square of half size around center projected on the plane
+fn _quad(pos: Point3<f32>, norm: Unit<Vector3<f32>>, center: Point3<f32>, half_size: f32) -> MeshData {
+	let (tangent, bitangent) = _tangents(&norm);
+	let middle = center - norm.as_ref() * norm.dot(&(center - pos));
+	let mut mesh = _empty_mesh();
+	for (s, t) in &[(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)] {
+		mesh.positions.push(middle + (tangent * *s + bitangent * *t) * half_size);
+		mesh.uvs.push(Vector2::new((s + 1.0) / 2.0, (t + 1.0) / 2.0));
+	}
+	mesh.normals = vec![norm.into_inner(); 4];
+	mesh.triangles = vec![[0, 1, 2], [0, 2, 3]];
+	mesh
+}
+
+#[cfg(test)]
+mod tests {
+	use nalgebra::Rotation3;
+
+	use crate::rtracer::{Camera, Color3, Scene, SceneObject};
+	use crate::rtracer::geometric::{InfinitePlane, Sphere};
+	use crate::rtracer::material::{Diffuse, PerfectReflective};
+
+	use super::*;
+
+	#[test]
+	fn write_obj_test() {
+		let mut scene = Scene::new();
+		let red: MaterialRef = Diffuse::new(Color3::new(1.0, 0.0, 0.0)).into();
+		let sphere = SceneObject::with_material_ref(Sphere {pos: Point3::new(3.0, 0.0, 0.0), radius: 1.0}, red.clone());
+		scene.add_obj(sphere.clone());
+		scene.add_obj(sphere);
+		scene.add_obj(SceneObject::new(
+			InfinitePlane {pos: Point3::new(0.0, 0.0, -1.0), norm: Vector3::z_axis()},
+			PerfectReflective::new(Color3::new(0.5, 0.5, 0.5))
+		));
+		let data = SceneData::new(scene, Camera::new(Point3::origin(), Rotation3::identity()));
+
+		let (obj, mtl) = write_obj(&data, "scene.mtl");
+		let sphere_vertices = (SPHERE_RINGS + 1) * (SPHERE_SEGMENTS + 1);
+		assert_eq!(obj.lines().filter(|l| l.starts_with("v ")).count(), 2 * sphere_vertices + 4);
+		assert_eq!(obj.lines().filter(|l| l.starts_with("f ")).count(), 4 * SPHERE_RINGS * SPHERE_SEGMENTS + 2);
+		// shared material is written once, second sphere index start after the first
+		assert_eq!(mtl.matches("newmtl").count(), 2);
+		assert!(obj.contains("usemtl material0") && obj.contains("usemtl material1"));
+		assert!(obj.contains(&format!("f {0}/{0}/{0} ", sphere_vertices + 1)));
+
+		// plane quad lie on the plane
+		let plane_vertices: Vec<&str> = obj.lines().filter(|l| l.starts_with("v ")).skip(2 * sphere_vertices).collect();
+		assert!(plane_vertices.iter().all(|l| l.ends_with(" -1")), "{:?}", plane_vertices);
+
+		// generated name skip the one of library material
+		let mut data = data;
+		data.materials.insert("material0".to_string(), Arc::new(Diffuse::new(Color3::repeat(1.0)).into()));
+		let (obj, mtl) = write_obj(&data, "scene.mtl");
+		assert_eq!(mtl.matches("newmtl material0\n").count(), 1);
+		assert!(obj.contains("usemtl material1") && obj.contains("usemtl material2") && !obj.contains("usemtl material0"));
+	}
+}
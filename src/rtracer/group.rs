@@ -0,0 +1,198 @@
+use std::slice::{Iter, IterMut};
+
+use nalgebra::Similarity3;
+use serde::{Deserialize, Serialize};
+
+use super::{light, SceneObject};
+use super::validation::{field, index, Report, Validate};
+
+// named node of scene graph, every object, light and child group is placed under its transform
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Group {
+	pub name: String,
+	#[serde(default = "Similarity3::identity")]
+	pub transform: Similarity3<f32>,
+	#[serde(default)]
+	objects: Vec<SceneObject>,
+	#[serde(default)]
+	lights: Vec<light::Lights>,
+	#[serde(default)]
+	children: Vec<Group>,
+}
+
+impl Group {
+	pub fn new(name: impl Into<String>, transform: Similarity3<f32>) -> Self {
+		Group {
+			name: name.into(),
+			transform,
+			objects: Vec::new(),
+			lights: Vec::new(),
+			children: Vec::new(),
+		}
+	}
+
+	pub fn add_obj(&mut self, obj: impl Into<SceneObject>) {
+		self.objects.push(obj.into());
+	}
+
+	pub fn add_light(&mut self, light: light::Lights) {
+		self.lights.push(light);
+	}
+
+	pub fn add_child(&mut self, group: Group) {
+		self.children.push(group);
+	}
+
+	pub fn iter_obj(&self) -> Iter<'_, SceneObject> {
+		self.objects.iter()
+	}
+
+	pub fn iter_light(&self) -> Iter<'_, light::Lights> {
+		self.lights.iter()
+	}
+
+	pub fn iter_children(&self) -> Iter<'_, Group> {
+		self.children.iter()
+	}
+
+	pub fn iter_children_mut(&mut self) -> IterMut<'_, Group> {
+		self.children.iter_mut()
+	}
+
+	// depth first search for group with name, self included
+	pub fn find(&self, name: &str) -> Option<&Group> {
+		if self.name == name {
+			return Some(self);
+		}
+		self.children.iter().find_map(|child| child.find(name))
+	}
+
+	pub fn find_mut(&mut self, name: &str) -> Option<&mut Group> {
+		if self.name == name {
+			return Some(self);
+		}
+		self.children.iter_mut().find_map(|child| child.find_mut(name))
+	}
+
+	// transform of named group relative to parent of self
+	pub fn find_transform(&self, name: &str) -> Option<Similarity3<f32>> {
+		if self.name == name {
+			return Some(self.transform);
+		}
+		self.children.iter()
+			.find_map(|child| child.find_transform(name))
+			.map(|trans| self.transform * trans)
+	}
+
+	// visit every object in this group and its children
+	pub fn for_each_obj_mut(&mut self, f: &mut impl FnMut(&mut SceneObject)) {
+		for obj in self.objects.iter_mut() {
+			f(obj);
+		}
+		for child in self.children.iter_mut() {
+			child.for_each_obj_mut(f);
+		}
+	}
+
+	// push every object and light into flat list with transform baked in, parent is the world transform of parent group
+	pub fn flatten_into(
+		&self,
+		parent: &Similarity3<f32>,
+		objects: &mut Vec<SceneObject>,
+		lights: &mut Vec<light::Lights>
+	) {
+		let world = parent * self.transform;
+		for obj in self.objects.iter() {
+			let mut obj = obj.clone();
+			obj.transform(&world);
+			objects.push(obj);
+		}
+		for light in self.lights.iter() {
+			let mut light = light.clone();
+			light.transform(&world);
+			lights.push(light);
+		}
+		for child in self.children.iter() {
+			child.flatten_into(&world, objects, lights);
+		}
+	}
+
+	pub fn light_count(&self) -> usize {
+		self.lights.len() + self.children.iter().map(Group::light_count).sum::<usize>()
+	}
+}
+
+impl Validate for Group {
+	fn validate(&self, path: &str, report: &mut Report) {
+		if self.name.is_empty() {
+			report.error(&field(path, "name"), "must not be empty");
+		}
+		report.check_transform(&field(path, "transform"), &self.transform);
+		let objects_path = field(path, "objects");
+		for (i, obj) in self.objects.iter().enumerate() {
+			obj.validate(&index(&objects_path, i), report);
+		}
+		let lights_path = field(path, "lights");
+		for (i, light) in self.lights.iter().enumerate() {
+			light.validate(&index(&lights_path, i), report);
+		}
+		let children_path = field(path, "children");
+		for (i, child) in self.children.iter().enumerate() {
+			child.validate(&index(&children_path, i), report);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use nalgebra::{Point3, Vector3};
+	use ron::de;
+
+	use assert_approx_eq::assert_approx_eq;
+
+	use crate::rtracer::{Color3, Scene};
+	use crate::rtracer::geometric::Sphere;
+	use crate::rtracer::material::Diffuse;
+
+	use super::*;
+
+	#[test]
+	fn group_flatten_test() {
+		let mut cup = Group::new("cup", Similarity3::new(Vector3::new(0.0, 0.0, 1.0), Vector3::zeros(), 1.0));
+		cup.add_obj(SceneObject::new(
+			Sphere {pos: Point3::origin(), radius: 0.5},
+			Diffuse::new(Color3::new(1.0, 1.0, 1.0))
+		));
+		let mut table = Group::new("table", Similarity3::new(Vector3::new(5.0, 0.0, 0.0), Vector3::zeros(), 2.0));
+		table.add_child(cup);
+		let mut scene = Scene::new();
+		scene.add_group(table);
+
+		// moving the table move the cup on it
+		scene.group_mut("table").unwrap().transform.append_translation_mut(&Vector3::new(0.0, 1.0, 0.0).into());
+		assert!(scene.group("cup").is_some());
+		let world = scene.group_world_transform("cup").unwrap();
+		assert_approx_eq!((world * Point3::origin() - Point3::new(5.0, 1.0, 2.0)).norm(), 0.0);
+
+		let flat = scene.flatten();
+		assert_eq!(flat.obj_count(), 1);
+		let hit = flat.iter_obj().next().unwrap()
+			.intersect(Point3::new(5.0, 1.0, 10.0), -Vector3::z_axis(), 0.0)
+			.unwrap();
+		assert_approx_eq!(hit.intersection.z, 3.0);
+	}
+
+	#[test]
+	fn group_parse_test() {
+		let scene: Scene = de::from_str(r#"(
+			groups: [(
+				name: "table",
+				objects: [(material: Diffuse((color: [1, 1, 1])), shape: Sphere((pos: [0, 0, 0], radius: 1)))],
+				children: [(name: "lamp", lights: [PointLight((pos: [0, 0, 2], light: [1, 1, 1]))])],
+			)],
+		)"#).unwrap();
+		assert_eq!(scene.group("table").unwrap().iter_children().count(), 1);
+		assert_eq!(scene.total_light_count(), 1);
+		assert!(scene.obj_count() == 0 && scene.flatten().obj_count() == 1);
+	}
+}
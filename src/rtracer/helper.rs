@@ -14,6 +14,37 @@ pub fn calculate_reflect_ray(incoming_ray: &Unit<Vector3<f32>>, normal: &Unit<Ve
     debug_normalize(ray)
 }
 
+// uniform point in unit ball from 3 sample in [0, 1)
+// direction from uniform point on sphere surface, cube root radius so volume is covered evenly
+pub fn sample_unit_ball((u, v): (f32, f32), w: f32) -> Vector3<f32> {
+    let z = 1.0 - 2.0 * u;
+    let ring = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * std::f32::consts::PI * v;
+    Vector3::new(ring * phi.cos(), ring * phi.sin(), z) * w.cbrt()
+}
+
+// cosine weighted direction in hemisphere around normal, pdf = cos / pi
+pub fn sample_cosine_hemisphere((u, v): (f32, f32), normal: &Unit<Vector3<f32>>) -> Unit<Vector3<f32>> {
+    let r = u.sqrt();
+    let phi = 2.0 * std::f32::consts::PI * v;
+    let (tangent, bitangent) = orthonormal_basis(normal);
+    Unit::new_normalize(
+        tangent * (r * phi.cos()) + bitangent * (r * phi.sin()) + normal.into_inner() * (1.0 - u).max(0.0).sqrt()
+    )
+}
+
+// two unit vector perpendicular to n and each other
+// "Building an Orthonormal Basis, Revisited", Duff et al. 2017
+pub fn orthonormal_basis(n: &Unit<Vector3<f32>>) -> (Vector3<f32>, Vector3<f32>) {
+    let sign = 1.0f32.copysign(n.z);
+    let a = -1.0 / (sign + n.z);
+    let b = n.x * n.y * a;
+    (
+        Vector3::new(1.0 + sign * n.x * n.x * a, sign * b, -sign * n.x),
+        Vector3::new(b, sign + n.y * n.y * a, -n.y),
+    )
+}
+
 pub fn debug_normalize(v: Vector3<f32>) -> Unit<Vector3<f32>> {
     if cfg!(debug_assertions) {
         let (unit_vec, magnitude) = Unit::new_and_get(v);
@@ -1,4 +1,6 @@
-use nalgebra::{Point3, Unit, Vector3};
+use nalgebra::{Point3, Unit, Vector2, Vector3};
+
+use super::Color3;
 
 // TODO: include more info such as material/ objectId, etc..
 pub struct HitInfo {
@@ -6,4 +8,8 @@ pub struct HitInfo {
 	pub dist: f32,
 	pub intersection: Point3<f32>,
 	pub normal: Unit<Vector3<f32>>,
+	// texture coordinate, only shape with uv (mesh) has it
+	pub uv: Option<Vector2<f32>>,
+	// albedo interpolated from vertex color of mesh
+	pub vertex_color: Option<Color3>,
 }
\ No newline at end of file
@@ -0,0 +1,17 @@
+use crate::rtracer::{Color3, Scene, SceneObject};
+use crate::rtracer::geometric::{Mesh, MeshData};
+use crate::rtracer::material::Diffuse;
+use crate::rtracer::parser::SceneDocument;
+
+// importer of scene written in other format, load_scene_data pick one by file extension
+pub mod gltf;
+pub mod pbrt;
+pub mod ply;
+pub mod stl;
+
+// mesh file hold a single white object, include of the file replace material and place it
+pub(crate) fn mesh_document(mesh: MeshData) -> SceneDocument {
+	let mut scene = Scene::new();
+	scene.add_obj(SceneObject::new(Mesh::new(mesh), Diffuse::new(Color3::new(1.0, 1.0, 1.0))));
+	SceneDocument {scene: Some(scene), ..SceneDocument::default()}
+}
@@ -0,0 +1,419 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::sync::Arc;
+
+use ::gltf::{Document, Node, Primitive};
+use ::gltf::buffer::Data as BufferData;
+use ::gltf::camera::Projection;
+use ::gltf::image::{Data as ImageData, Format};
+use ::gltf::khr_lights_punctual::{Kind, Light as GltfLight};
+use ::gltf::material::AlphaMode;
+use ::gltf::mesh::Mode;
+use nalgebra::{Matrix3, Point3, Quaternion, Rotation3, Similarity3, Translation3, Unit, UnitQuaternion, Vector2, Vector3};
+
+use crate::rtracer::{Camera, Color3, Group, Lights, MaterialRef, Materials, Scene, SceneObject};
+use crate::rtracer::geometric::{Mesh, MeshData};
+use crate::rtracer::light::{DirectionalLight, PointLight, SpotLight};
+use crate::rtracer::material::{Diffuse, PerfectReflective, Reflective};
+use crate::rtracer::parser::{SceneDocument, SceneParserError};
+use crate::rtracer::texture::{Texture, TextureImage};
+use crate::rtracer::validation::{field, index, Report};
+
+// metallic material smoother than this is a perfect mirror
+const MIRROR_ROUGHNESS: f32 = 0.05;
+// ray per hit of rough metallic material
+const REFLECTIVE_ITERATION: usize = 16;
+
+/*
+gltf is y up and right handed, renderer is z up with +y on the right of +x
+so every position and direction is mirrored by swapping y and z,
+node transform is conjugated by that mirror which keep it a similarity
+*/
+
+fn _mirror(v: [f32; 3]) -> Vector3<f32> {
+	Vector3::new(v[0], v[2], v[1])
+}
+
+// mirrored rotation turn around mirrored axis by negated angle, gltf quaternion is [x, y, z, w]
+fn _mirror_rotation(q: [f32; 4]) -> UnitQuaternion<f32> {
+	UnitQuaternion::from_quaternion(Quaternion::new(q[3], -q[0], -q[2], -q[1]))
+}
+
+// -z of gltf, where camera look and directional/spot light shine
+fn _local_forward() -> Unit<Vector3<f32>> {
+	Unit::new_unchecked(_mirror([0.0, 0.0, -1.0]))
+}
+
+// node hierarchy become groups, mesh primitive become mesh object using library material
+// first perspective camera of scene is used, warning list everything that couldn't be imported
+pub(crate) fn load_gltf(path: &Path, report: &mut Report) -> Result<SceneDocument, SceneParserError> {
+	let (document, buffers, images) = ::gltf::import(path)?;
+	let mut importer = Importer {
+		// texture path must still work when scene is saved somewhere else
+		file: path.canonicalize().unwrap_or_else(|_| path.to_path_buf()).display().to_string(),
+		buffers: &buffers,
+		images: &images,
+		report,
+		camera: None,
+		library: BTreeMap::new(),
+		materials: Vec::new(),
+		textures: HashMap::new(),
+		meshes: HashMap::new(),
+	};
+	importer.import_materials(&document);
+
+	let mut scene = Scene::new();
+	match document.default_scene().or_else(|| document.scenes().next()) {
+		Some(gltf_scene) => {
+			for node in gltf_scene.nodes() {
+				scene.add_group(importer.import_node(&node, &Similarity3::identity()));
+			}
+		},
+		None => importer.report.warning("scenes", "file has no scene, nothing is imported"),
+	}
+	if document.animations().next().is_some() {
+		importer.report.warning("animations", "animation is not imported");
+	}
+	if document.skins().next().is_some() {
+		importer.report.warning("skins", "skinning is not supported, mesh is imported in bind pose");
+	}
+
+	Ok(SceneDocument {
+		scene: Some(scene),
+		camera: importer.camera,
+		materials: importer.library,
+		..SceneDocument::default()
+	})
+}
+
+// every image embedded in or referenced by gltf file, used for "model.glb#2" texture path
+pub(crate) fn load_images(path: &Path) -> Result<Vec<TextureImage>, String> {
+	let (_, _, images) = ::gltf::import(path).map_err(|e| e.to_string())?;
+	images.iter().map(_texture_image).collect()
+}
+
+fn _texture_image(data: &ImageData) -> Result<TextureImage, String> {
+	let (channels, wide, bgr) = match data.format {
+		Format::R8 => (1, false, false),
+		Format::R8G8 => (2, false, false),
+		Format::R8G8B8 => (3, false, false),
+		Format::R8G8B8A8 => (4, false, false),
+		Format::B8G8R8 => (3, false, true),
+		Format::B8G8R8A8 => (4, false, true),
+		Format::R16 => (1, true, false),
+		Format::R16G16 => (2, true, false),
+		Format::R16G16B16 => (3, true, false),
+		Format::R16G16B16A16 => (4, true, false),
+	};
+
+	// 16 bit channel keep only the high byte
+	let mut bytes: Vec<u8> = if wide {
+		data.pixels.chunks_exact(2).map(|b| (u16::from_ne_bytes([b[0], b[1]]) >> 8) as u8).collect()
+	}
+	else {
+		data.pixels.clone()
+	};
+	if bgr {
+		bytes.chunks_exact_mut(channels).for_each(|p| p.swap(0, 2));
+	}
+	TextureImage::from_srgb8(data.width, data.height, channels, &bytes)
+}
+
+struct Importer<'a> {
+	file: String,
+	buffers: &'a [BufferData],
+	images: &'a [ImageData],
+	report: &'a mut Report,
+	camera: Option<Camera>,
+	library: BTreeMap<String, Arc<Materials>>,
+	// library name and uv set of base color texture, by material index
+	materials: Vec<(String, u32)>,
+	textures: HashMap<usize, Texture>,
+	// primitive shared by many node is only read once
+	meshes: HashMap<(usize, usize), Mesh>,
+}
+
+impl<'a> Importer<'a> {
+	fn import_materials(&mut self, document: &Document) {
+		for material in document.materials() {
+			let i = material.index().unwrap_or(0);
+			let path = index("materials", i);
+			let mut name = material.name().map_or_else(|| format!("material{}", i), str::to_string);
+			if self.library.contains_key(&name) {
+				name = format!("{}{}", name, i);
+			}
+
+			let pbr = material.pbr_metallic_roughness();
+			let [r, g, b, alpha] = pbr.base_color_factor();
+			let color = Color3::new(r, g, b);
+			if alpha < 1.0 || material.alpha_mode() != AlphaMode::Opaque {
+				self.report.warning(&path, "transparency is not supported, material is opaque");
+			}
+			if material.emissive_factor().iter().any(|x| *x > 0.0) {
+				self.report.warning(&path, "emission is not supported");
+			}
+
+			// closest material: metal reflect, everything else is diffuse
+			let mut uv_set = 0;
+			let converted: Materials = if pbr.metallic_factor() >= 0.5 {
+				if pbr.base_color_texture().is_some() {
+					self.report.warning(&path, "base color texture of metallic material is ignored");
+				}
+				if pbr.roughness_factor() < MIRROR_ROUGHNESS {
+					PerfectReflective::new(color).into()
+				}
+				else {
+					Reflective::new(pbr.roughness_factor(), REFLECTIVE_ITERATION).into()
+				}
+			}
+			else {
+				match pbr.base_color_texture() {
+					Some(info) => {
+						uv_set = info.tex_coord();
+						match self.texture(info.texture().source().index()) {
+							Some(texture) => Diffuse::with_texture(color, texture).into(),
+							None => Diffuse::new(color).into(),
+						}
+					},
+					None => Diffuse::new(color).into(),
+				}
+			};
+
+			self.library.insert(name.clone(), Arc::new(converted));
+			self.materials.push((name, uv_set));
+		}
+	}
+
+	// malformed image is reported and the material is left untextured
+	fn texture(&mut self, image_index: usize) -> Option<Texture> {
+		if let Some(texture) = self.textures.get(&image_index) {
+			return Some(texture.clone());
+		}
+		match _texture_image(&self.images[image_index]) {
+			Ok(image) => {
+				let texture = Texture::new(format!("{}#{}", self.file, image_index), image);
+				self.textures.insert(image_index, texture.clone());
+				Some(texture)
+			},
+			Err(message) => {
+				self.report.warning(&index("images", image_index), format!("{}, texture is ignored", message));
+				None
+			},
+		}
+	}
+
+	// primitive without material use gltf default material, white diffuse
+	fn material(&mut self, primitive: &Primitive) -> (MaterialRef, u32) {
+		let (name, uv_set) = match primitive.material().index() {
+			Some(i) => self.materials[i].clone(),
+			None => ("default".to_string(), 0),
+		};
+		let material = self.library.entry(name.clone())
+			.or_insert_with(|| Arc::new(Diffuse::new(Color3::new(1.0, 1.0, 1.0)).into()))
+			.clone();
+		(MaterialRef::Named(name, Some(material)), uv_set)
+	}
+
+	// parent is world transform of parent node, used to place camera
+	fn import_node(&mut self, node: &Node, parent: &Similarity3<f32>) -> Group {
+		let path = index("nodes", node.index());
+		let (translation, rotation, scale) = node.transform().decomposed();
+		let scaling = (scale[0] * scale[1] * scale[2]).abs().cbrt();
+		if scale.iter().any(|s| (s.abs() - scaling).abs() > 1e-4 * scaling) {
+			self.report.warning(&field(&path, "scale"), format!("non-uniform scale is not supported, using {}", scaling));
+		}
+		if scale.iter().any(|s| *s < 0.0) {
+			self.report.warning(&field(&path, "scale"), "negative scale is not supported, mesh is not mirrored");
+		}
+
+		let transform = Similarity3::from_parts(
+			Translation3::from(_mirror(translation)),
+			_mirror_rotation(rotation),
+			scaling
+		);
+		let world = parent * transform;
+		let name = node.name().map_or_else(|| format!("node{}", node.index()), str::to_string);
+		let mut group = Group::new(name, transform);
+
+		if let Some(mesh) = node.mesh() {
+			for primitive in mesh.primitives() {
+				if let Some(obj) = self.import_primitive(&primitive, mesh.index()) {
+					group.add_obj(obj);
+				}
+			}
+		}
+		if let Some(light) = node.light() {
+			group.add_light(_light(&light));
+		}
+		if let Some(camera) = node.camera() {
+			self.import_camera(&camera.projection(), &world, &field(&path, "camera"));
+		}
+		for child in node.children() {
+			group.add_child(self.import_node(&child, &world));
+		}
+		group
+	}
+
+	fn import_primitive(&mut self, primitive: &Primitive, mesh_index: usize) -> Option<SceneObject> {
+		let path = index(&field(&index("meshes", mesh_index), "primitives"), primitive.index());
+		if primitive.mode() != Mode::Triangles {
+			self.report.warning(&field(&path, "mode"), "only triangle primitive is supported, primitive is skipped");
+			return None;
+		}
+
+		let (material, uv_set) = self.material(primitive);
+		let key = (mesh_index, primitive.index());
+		if let Some(mesh) = self.meshes.get(&key) {
+			return Some(SceneObject::with_material_ref(mesh.clone(), material));
+		}
+
+		let buffers = self.buffers;
+		let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(|data| &data.0[..]));
+		let positions: Vec<Point3<f32>> = match reader.read_positions() {
+			Some(positions) => positions.map(|p| Point3::from(_mirror(p))).collect(),
+			None => {
+				self.report.warning(&path, "primitive has no position, primitive is skipped");
+				return None;
+			},
+		};
+		let normals = reader.read_normals()
+			.map(|normals| normals.map(_mirror).collect())
+			.unwrap_or_default();
+		let colors = reader.read_colors(0)
+			.map(|colors| colors.into_rgb_f32().map(Color3::from).collect())
+			.unwrap_or_default();
+		let uvs = reader.read_tex_coords(uv_set)
+			.map(|uvs| uvs.into_f32().map(|[u, v]| Vector2::new(u, v)).collect())
+			.unwrap_or_default();
+		let indices: Vec<u32> = match reader.read_indices() {
+			Some(indices) => indices.into_u32().collect(),
+			None => (0..positions.len() as u32).collect(),
+		};
+		let triangles = indices.chunks_exact(3).map(|t| [t[0], t[1], t[2]]).collect();
+
+		let mesh = Mesh::new(MeshData {positions, normals, uvs, colors, triangles});
+		self.meshes.insert(key, mesh.clone());
+		Some(SceneObject::with_material_ref(mesh, material))
+	}
+
+	fn import_camera(&mut self, projection: &Projection, world: &Similarity3<f32>, path: &str) {
+		let perspective = match projection {
+			Projection::Perspective(p) => p,
+			Projection::Orthographic(_) => {
+				self.report.warning(path, "orthographic camera is not supported, camera is skipped");
+				return;
+			},
+		};
+		if self.camera.is_some() {
+			self.report.warning(path, "scene already has a camera, only the first one is used");
+			return;
+		}
+
+		// forward, right and up axis of gltf camera after mirroring
+		let axes = Matrix3::from_columns(&[_local_forward().into_inner(), Vector3::x(), Vector3::z()]);
+		let rotation = world.isometry.rotation.to_rotation_matrix() * Rotation3::from_matrix_unchecked(axes);
+		let mut camera = Camera::new(world * Point3::origin(), rotation);
+
+		let aspect = perspective.aspect_ratio().unwrap_or_else(|| {
+			self.report.warning(path, "camera has no aspect ratio, yfov is used as horizontal field of view");
+			1.0
+		});
+		camera.set_fov(2.0 * (aspect * (perspective.yfov() / 2.0).tan()).atan());
+		self.camera = Some(camera);
+	}
+}
+
+// light sit at origin of its node, gltf intensity (candela or lux) scale color
+fn _light(light: &GltfLight) -> Lights {
+	let color = Color3::from(light.color()) * light.intensity();
+	match light.kind() {
+		Kind::Point => PointLight::new(Point3::origin(), color).into(),
+		Kind::Directional => DirectionalLight::new(_local_forward(), color).into(),
+		Kind::Spot {inner_cone_angle, outer_cone_angle} =>
+			SpotLight::new(Point3::origin(), _local_forward(), color, inner_cone_angle, outer_cone_angle).into(),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::fs;
+
+	use assert_approx_eq::assert_approx_eq;
+
+	use crate::rtracer::{load_scene_data, render, RenderSettings, ToneMap};
+
+	use super::*;
+
+	// textured quad facing the camera, lamp in between, red left half and green right half
+	const QUAD_GLTF: &str = r#"{
+		"asset": {"version": "2.0"},
+		"extensionsUsed": ["KHR_lights_punctual"],
+		"extensions": {"KHR_lights_punctual": {"lights": [{"type": "point", "color": [1, 1, 1], "intensity": 4}]}},
+		"scene": 0,
+		"scenes": [{"nodes": [0, 2, 3]}],
+		"nodes": [
+			{"name": "table", "translation": [0, 0, -3], "children": [1]},
+			{"name": "top", "mesh": 0},
+			{"name": "lamp", "translation": [0, 0, -1], "extensions": {"KHR_lights_punctual": {"light": 0}}},
+			{"name": "eye", "camera": 0}
+		],
+		"cameras": [{"type": "perspective", "perspective": {"yfov": 0.8, "aspectRatio": 1.0, "znear": 0.1}}],
+		"meshes": [{"primitives": [{"attributes": {"POSITION": 0, "NORMAL": 1, "TEXCOORD_0": 2}, "indices": 3, "material": 0}]}],
+		"materials": [{"name": "wood", "pbrMetallicRoughness": {"baseColorTexture": {"index": 0}, "metallicFactor": 0}}],
+		"textures": [{"source": 0}],
+		"images": [{"uri": "quad.png"}],
+		"buffers": [{"uri": "quad.bin", "byteLength": 140}],
+		"bufferViews": [
+			{"buffer": 0, "byteOffset": 0, "byteLength": 48},
+			{"buffer": 0, "byteOffset": 48, "byteLength": 48},
+			{"buffer": 0, "byteOffset": 96, "byteLength": 32},
+			{"buffer": 0, "byteOffset": 128, "byteLength": 12}
+		],
+		"accessors": [
+			{"bufferView": 0, "componentType": 5126, "count": 4, "type": "VEC3", "min": [-1, -1, 0], "max": [1, 1, 0]},
+			{"bufferView": 1, "componentType": 5126, "count": 4, "type": "VEC3"},
+			{"bufferView": 2, "componentType": 5126, "count": 4, "type": "VEC2"},
+			{"bufferView": 3, "componentType": 5123, "count": 6, "type": "SCALAR"}
+		]
+	}"#;
+
+	fn write_quad(dir: &Path) {
+		let floats: &[f32] = &[
+			-1.0, -1.0, 0.0, 1.0, -1.0, 0.0, 1.0, 1.0, 0.0, -1.0, 1.0, 0.0,
+			0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0,
+			0.0, 1.0, 1.0, 1.0, 1.0, 0.0, 0.0, 0.0,
+		];
+		let mut bin: Vec<u8> = floats.iter().flat_map(|x| x.to_le_bytes().to_vec()).collect();
+		bin.extend([0u16, 1, 2, 0, 2, 3].iter().flat_map(|i| i.to_le_bytes().to_vec()));
+
+		fs::create_dir_all(dir).unwrap();
+		fs::write(dir.join("quad.bin"), bin).unwrap();
+		fs::write(dir.join("quad.gltf"), QUAD_GLTF).unwrap();
+		image::RgbImage::from_raw(2, 1, vec![255, 0, 0, 0, 255, 0]).unwrap()
+			.save(dir.join("quad.png")).unwrap();
+	}
+
+	#[test]
+	fn gltf_import_test() {
+		let dir = std::env::temp_dir().join("rtracer_gltf_test");
+		write_quad(&dir);
+		let data = load_scene_data(dir.join("quad.gltf")).unwrap();
+		fs::remove_dir_all(&dir).unwrap();
+
+		// y up is mirrored to z up
+		let top = data.scene.group_world_transform("top").unwrap();
+		assert_approx_eq!((top * Point3::origin() - Point3::new(0.0, -3.0, 0.0)).norm(), 0.0);
+		assert_eq!(data.scene.flatten().light_count(), 1);
+		assert!(data.materials["wood"].texture().unwrap().path().ends_with("quad.gltf#0"));
+
+		let mut settings = RenderSettings::new(16, 16);
+		settings.seed = Some(1);
+		settings.tone_map = ToneMap::Clamp(1.0);
+		let img = render(&data.scene, &data.camera, &settings).unwrap();
+
+		// image is not mirrored, left of quad is on the left of image (texture is bilinear filtered)
+		let (left, right) = (img.get_pixel(4, 8), img.get_pixel(12, 8));
+		assert!(left[0] > 2 * left[1], "left is red, got {:?}", left);
+		assert!(right[1] > 2 * right[0], "right is green, got {:?}", right);
+	}
+}
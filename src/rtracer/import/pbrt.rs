@@ -0,0 +1,676 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use nalgebra::{Matrix4, Point3, Rotation3, Unit, Vector2, Vector3};
+
+use crate::rtracer::{Camera, Color3, Lights, MaterialRef, Materials, Scene, SceneObject};
+use crate::rtracer::geometric::{Mesh, MeshData, Shapes, Sphere};
+use crate::rtracer::light::{DirectionalLight, PointLight, SpotLight};
+use crate::rtracer::material::{Diffuse, PerfectReflective};
+use crate::rtracer::parser::{SceneDocument, SceneParserError};
+use crate::rtracer::validation::Report;
+
+use super::ply::read_ply;
+
+/*
+subset of pbrt-v3 scene description, enough to compare simple scene with the reference renderer
+pbrt world is left handed like ours, so coordinate are taken as they are
+every directive or parameter that isn't understood is skipped with a warning at its line
+*/
+pub(crate) fn load_pbrt(path: &Path, report: &mut Report) -> Result<SceneDocument, SceneParserError> {
+	let text = fs::read_to_string(path)?;
+	let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+	let tokens = _tokenize(&text)?;
+	let mut importer = Importer::new(base_dir, report);
+	importer.run(&tokens)?;
+	Ok(importer.finish())
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+	Ident(String),
+	Str(String),
+	Num(f32),
+	Open,
+	Close,
+}
+
+fn _error(line: usize, message: impl Into<String>) -> SceneParserError {
+	SceneParserError::PbrtError {line, message: message.into()}
+}
+
+// every token with the line it start at
+fn _tokenize(text: &str) -> Result<Vec<(usize, Token)>, SceneParserError> {
+	let mut tokens = Vec::new();
+	let mut chars = text.chars().peekable();
+	let mut line = 1;
+	while let Some(&c) = chars.peek() {
+		match c {
+			'\n' => {
+				line += 1;
+				chars.next();
+			},
+			'#' => while matches!(chars.peek(), Some(c) if *c != '\n') {
+				chars.next();
+			},
+			'[' | ']' => {
+				chars.next();
+				tokens.push((line, if c == '[' { Token::Open } else { Token::Close }));
+			},
+			'"' => {
+				chars.next();
+				let mut s = String::new();
+				loop {
+					match chars.next() {
+						Some('"') => break,
+						Some('\n') | None => return Err(_error(line, "unterminated string")),
+						Some(c) => s.push(c),
+					}
+				}
+				tokens.push((line, Token::Str(s)));
+			},
+			c if c.is_whitespace() => {
+				chars.next();
+			},
+			_ => {
+				let mut word = String::new();
+				while let Some(&c) = chars.peek() {
+					if c.is_whitespace() || "[]\"#".contains(c) {
+						break;
+					}
+					word.push(c);
+					chars.next();
+				}
+				let token = if word.starts_with(|c: char| c.is_ascii_alphabetic()) {
+					Token::Ident(word)
+				}
+				else {
+					Token::Num(word.parse().map_err(|_| _error(line, format!("'{}' is not a number", word)))?)
+				};
+				tokens.push((line, token));
+			},
+		}
+	}
+	Ok(tokens)
+}
+
+#[derive(Clone, Debug)]
+enum Value {
+	Num(f32),
+	Str(String),
+}
+
+// typed parameter like "float radius" [1], every parameter is marked when read so leftover can be reported
+struct Param {
+	ty: String,
+	name: String,
+	values: Vec<Value>,
+	used: Cell<bool>,
+}
+
+struct Params(Vec<Param>);
+
+impl Params {
+	fn find(&self, types: &[&str], name: &str) -> Option<&Param> {
+		let param = self.0.iter().find(|p| p.name == name && types.contains(&p.ty.as_str()))?;
+		param.used.set(true);
+		Some(param)
+	}
+
+	fn numbers(&self, types: &[&str], name: &str) -> Option<Vec<f32>> {
+		self.find(types, name).map(|p| p.values.iter()
+			.filter_map(|v| match v {
+				Value::Num(x) => Some(*x),
+				Value::Str(_) => None,
+			})
+			.collect())
+	}
+
+	fn float(&self, name: &str, default: f32) -> f32 {
+		self.numbers(&["float"], name).and_then(|v| v.first().cloned()).unwrap_or(default)
+	}
+
+	fn point(&self, name: &str, default: Point3<f32>) -> Point3<f32> {
+		match self.numbers(&["point", "point3"], name) {
+			Some(v) if v.len() >= 3 => Point3::new(v[0], v[1], v[2]),
+			_ => default,
+		}
+	}
+
+	// only rgb is understood, spectrum and blackbody are left for the unused warning
+	fn rgb(&self, name: &str, default: Color3) -> Color3 {
+		match self.numbers(&["rgb", "color"], name) {
+			Some(v) if v.len() >= 3 => Color3::new(v[0], v[1], v[2]),
+			_ => default,
+		}
+	}
+
+	fn string(&self, name: &str) -> Option<String> {
+		self.find(&["string", "texture"], name).and_then(|p| match p.values.first() {
+			Some(Value::Str(s)) => Some(s.clone()),
+			_ => None,
+		})
+	}
+
+	fn mark_used(&self, name: &str) {
+		for param in self.0.iter().filter(|p| p.name == name) {
+			param.used.set(true);
+		}
+	}
+
+	// whole directive is skipped with one warning, no need to list every parameter
+	fn mark_all_used(&self) {
+		for param in self.0.iter() {
+			param.used.set(true);
+		}
+	}
+}
+
+// argument of one directive, positional value first then parameter list
+struct Directive {
+	line: usize,
+	name: String,
+	args: Vec<Value>,
+	params: Params,
+}
+
+impl Directive {
+	fn number_args(&self, count: usize) -> Result<Vec<f32>, SceneParserError> {
+		let numbers: Vec<f32> = self.args.iter()
+			.filter_map(|v| match v {
+				Value::Num(x) => Some(*x),
+				Value::Str(_) => None,
+			})
+			.collect();
+		if numbers.len() != count {
+			return Err(_error(self.line, format!("{} expect {} numbers, got {}", self.name, count, numbers.len())));
+		}
+		Ok(numbers)
+	}
+
+	fn string_arg(&self, i: usize) -> Result<&str, SceneParserError> {
+		match self.args.get(i) {
+			Some(Value::Str(s)) => Ok(s),
+			_ => Err(_error(self.line, format!("{} expect a string argument", self.name))),
+		}
+	}
+}
+
+// split token stream into directive, parameter declaration is a string with type and name
+fn _directives(tokens: &[(usize, Token)]) -> Result<Vec<Directive>, SceneParserError> {
+	let mut directives = Vec::new();
+	let mut i = 0;
+	while i < tokens.len() {
+		let (line, name) = match &tokens[i] {
+			(line, Token::Ident(name)) => (*line, name.clone()),
+			(line, token) => return Err(_error(*line, format!("expected a directive, got {:?}", token))),
+		};
+		i += 1;
+
+		// ActiveTransform take a bare word
+		if name == "ActiveTransform" {
+			i += 1;
+		}
+
+		let mut values: Vec<(Vec<Value>, bool)> = Vec::new();
+		while let Some((line, token)) = tokens.get(i) {
+			match token {
+				Token::Ident(_) => break,
+				Token::Num(x) => values.push((vec![Value::Num(*x)], false)),
+				Token::Str(s) => values.push((vec![Value::Str(s.clone())], false)),
+				Token::Close => return Err(_error(*line, "unexpected ']'")),
+				Token::Open => {
+					let mut list = Vec::new();
+					loop {
+						i += 1;
+						match tokens.get(i) {
+							Some((_, Token::Num(x))) => list.push(Value::Num(*x)),
+							Some((_, Token::Str(s))) => list.push(Value::Str(s.clone())),
+							Some((_, Token::Close)) => break,
+							_ => return Err(_error(*line, "unterminated '['")),
+						}
+					}
+					values.push((list, true));
+				},
+			}
+			i += 1;
+		}
+
+		// parameter start at first string that hold both type and name
+		let split = values.iter()
+			.position(|(v, is_list)| !is_list && matches!(v.first(), Some(Value::Str(s)) if s.split_whitespace().count() == 2))
+			.unwrap_or(values.len());
+		let mut rest = values.split_off(split).into_iter();
+		let args = values.into_iter().flat_map(|(v, _)| v).collect();
+
+		let mut params = Vec::new();
+		while let Some((declaration, _)) = rest.next() {
+			let declaration = match declaration.first() {
+				Some(Value::Str(s)) => s.clone(),
+				_ => return Err(_error(line, format!("{} has a value without parameter declaration", name))),
+			};
+			let mut words = declaration.split_whitespace();
+			let (ty, param_name) = match (words.next(), words.next()) {
+				(Some(ty), Some(param_name)) => (ty.to_string(), param_name.to_string()),
+				_ => return Err(_error(line, format!("'{}' is not a parameter declaration", declaration))),
+			};
+			let (values, _) = rest.next()
+				.ok_or_else(|| _error(line, format!("parameter '{}' has no value", declaration)))?;
+			params.push(Param {ty, name: param_name, values, used: Cell::new(false)});
+		}
+
+		directives.push(Directive {line, name, args, params: Params(params)});
+	}
+	Ok(directives)
+}
+
+#[derive(Clone)]
+struct GraphicsState {
+	ctm: Matrix4<f32>,
+	material: MaterialRef,
+}
+
+struct Importer<'a> {
+	base_dir: &'a Path,
+	report: &'a mut Report,
+	state: GraphicsState,
+	// saved state and whether it was pushed by AttributeBegin (TransformBegin only save ctm)
+	stack: Vec<(GraphicsState, bool)>,
+	coordinate_systems: HashMap<String, Matrix4<f32>>,
+	// camera to world and pbrt field of view (of the shorter image axis)
+	camera: Option<(Matrix4<f32>, f32)>,
+	resolution: (u32, u32),
+	scene: Scene,
+	materials: HashMap<String, Arc<Materials>>,
+	in_object: bool,
+}
+
+impl<'a> Importer<'a> {
+	fn new(base_dir: &'a Path, report: &'a mut Report) -> Self {
+		Importer {
+			base_dir,
+			report,
+			state: GraphicsState {
+				ctm: Matrix4::identity(),
+				material: MaterialRef::Inline(Arc::new(Diffuse::new(Color3::repeat(0.5)).into())),
+			},
+			stack: Vec::new(),
+			coordinate_systems: HashMap::new(),
+			camera: None,
+			resolution: (640, 480),
+			scene: Scene::new(),
+			materials: HashMap::new(),
+			in_object: false,
+		}
+	}
+
+	fn warning(&mut self, line: usize, message: impl Into<String>) {
+		self.report.warning(&line.to_string(), message);
+	}
+
+	fn run(&mut self, tokens: &[(usize, Token)]) -> Result<(), SceneParserError> {
+		for directive in _directives(tokens)? {
+			self.directive(&directive)?;
+			for param in directive.params.0.iter().filter(|p| !p.used.get()) {
+				let message = format!("parameter '{} {}' of {} is not supported, skipped", param.ty, param.name, directive.name);
+				self.warning(directive.line, message);
+			}
+		}
+		if !self.stack.is_empty() {
+			self.report.warning("end", format!("{} AttributeBegin or TransformBegin without End", self.stack.len()));
+		}
+		Ok(())
+	}
+
+	fn directive(&mut self, d: &Directive) -> Result<(), SceneParserError> {
+		let ctm = self.state.ctm;
+		match d.name.as_str() {
+			"Identity" => self.state.ctm = Matrix4::identity(),
+			"Translate" => {
+				let v = d.number_args(3)?;
+				self.state.ctm = ctm * Matrix4::new_translation(&Vector3::new(v[0], v[1], v[2]));
+			},
+			"Scale" => {
+				let v = d.number_args(3)?;
+				self.state.ctm = ctm * Matrix4::new_nonuniform_scaling(&Vector3::new(v[0], v[1], v[2]));
+			},
+			"Rotate" => {
+				let v = d.number_args(4)?;
+				let axis = Unit::try_new(Vector3::new(v[1], v[2], v[3]), 1e-12)
+					.ok_or_else(|| _error(d.line, "rotation axis is zero"))?;
+				self.state.ctm = ctm * Rotation3::from_axis_angle(&axis, v[0].to_radians()).to_homogeneous();
+			},
+			"LookAt" => {
+				let v = d.number_args(9)?;
+				let world_to_camera = _look_at(
+					Point3::new(v[0], v[1], v[2]), Point3::new(v[3], v[4], v[5]), Vector3::new(v[6], v[7], v[8])
+				).ok_or_else(|| _error(d.line, "LookAt up vector is parallel to view direction"))?;
+				self.state.ctm = ctm * world_to_camera;
+			},
+			// matrix is written column by column
+			"Transform" | "ConcatTransform" => {
+				let m = Matrix4::from_column_slice(&d.number_args(16)?);
+				self.state.ctm = if d.name == "Transform" { m } else { ctm * m };
+			},
+			"CoordinateSystem" => {
+				let name = d.string_arg(0)?.to_string();
+				self.coordinate_systems.insert(name, ctm);
+			},
+			"CoordSysTransform" => match self.coordinate_systems.get(d.string_arg(0)?) {
+				Some(m) => self.state.ctm = *m,
+				None => self.warning(d.line, format!("unknown coordinate system '{}'", d.string_arg(0)?)),
+			},
+			"WorldBegin" => {
+				self.state.ctm = Matrix4::identity();
+				self.coordinate_systems.insert("world".to_string(), Matrix4::identity());
+			},
+			"WorldEnd" => (),
+			"AttributeBegin" | "TransformBegin" => self.stack.push((self.state.clone(), d.name == "AttributeBegin")),
+			"AttributeEnd" | "TransformEnd" => match self.stack.pop() {
+				Some((state, true)) => self.state = state,
+				Some((state, false)) => self.state.ctm = state.ctm,
+				None => self.warning(d.line, format!("{} without matching Begin", d.name)),
+			},
+			"Camera" => self.import_camera(d)?,
+			"Film" => {
+				let x = d.params.numbers(&["integer"], "xresolution").and_then(|v| v.first().cloned());
+				let y = d.params.numbers(&["integer"], "yresolution").and_then(|v| v.first().cloned());
+				self.resolution = (x.map_or(640, |x| x as u32), y.map_or(480, |y| y as u32));
+				// output is chosen by the renderer
+				d.params.mark_used("filename");
+			},
+			"Material" => self.state.material = MaterialRef::Inline(Arc::new(self.material(d, d.string_arg(0)?))),
+			"MakeNamedMaterial" => {
+				let ty = d.params.string("type").unwrap_or_else(|| "matte".to_string());
+				let material = self.material(d, &ty);
+				self.materials.insert(d.string_arg(0)?.to_string(), Arc::new(material));
+			},
+			"NamedMaterial" => self.state.material = MaterialRef::named(d.string_arg(0)?),
+			"Shape" => self.import_shape(d)?,
+			"LightSource" => self.import_light(d)?,
+			"ObjectBegin" => {
+				self.warning(d.line, "object instancing is not supported, shapes of the object are skipped");
+				self.in_object = true;
+			},
+			"ObjectEnd" => self.in_object = false,
+			"ObjectInstance" => self.warning(d.line, "object instancing is not supported, skipped"),
+			"Sampler" | "Integrator" | "PixelFilter" | "Accelerator" => {
+				self.warning(d.line, format!("{} is chosen by render settings, skipped", d.name));
+				d.params.mark_all_used();
+			},
+			_ => {
+				self.warning(d.line, format!("directive {} is not supported, skipped", d.name));
+				d.params.mark_all_used();
+			},
+		}
+		Ok(())
+	}
+
+	fn import_camera(&mut self, d: &Directive) -> Result<(), SceneParserError> {
+		let camera_to_world = self.state.ctm.try_inverse()
+			.ok_or_else(|| _error(d.line, "camera transform is not invertible"))?;
+		self.coordinate_systems.insert("camera".to_string(), camera_to_world);
+		match d.string_arg(0)? {
+			"perspective" => self.camera = Some((camera_to_world, d.params.float("fov", 90.0))),
+			ty => {
+				self.warning(d.line, format!("camera '{}' is not supported, using perspective", ty));
+				self.camera = Some((camera_to_world, 90.0));
+				d.params.mark_all_used();
+			},
+		}
+		Ok(())
+	}
+
+	fn material(&mut self, d: &Directive, ty: &str) -> Materials {
+		let params = &d.params;
+		match ty {
+			"matte" => {
+				params.mark_used("sigma");
+				Diffuse::new(params.rgb("Kd", Color3::repeat(0.5))).into()
+			},
+			"mirror" => PerfectReflective::new(params.rgb("Kr", Color3::repeat(0.9))).into(),
+			"glass" => {
+				self.warning(d.line, "refraction is not supported, glass is imported as mirror");
+				params.mark_used("Kt");
+				params.mark_used("eta");
+				PerfectReflective::new(params.rgb("Kr", Color3::repeat(1.0))).into()
+			},
+			_ => {
+				self.warning(d.line, format!("material '{}' is not supported, imported as matte", ty));
+				let material = Diffuse::new(params.rgb("Kd", Color3::repeat(0.5))).into();
+				params.mark_all_used();
+				material
+			},
+		}
+	}
+
+	fn import_shape(&mut self, d: &Directive) -> Result<(), SceneParserError> {
+		if self.in_object {
+			d.params.mark_all_used();
+			return Ok(());
+		}
+
+		let ctm = self.state.ctm;
+		let shape: Shapes = match d.string_arg(0)? {
+			"sphere" => {
+				let scale = Vector3::new(
+					ctm.column(0).xyz().norm(), ctm.column(1).xyz().norm(), ctm.column(2).xyz().norm()
+				);
+				// nalgebra min() fold from zero, so compare component by hand
+				let (lo, hi) = (scale.x.min(scale.y).min(scale.z), scale.x.max(scale.y).max(scale.z));
+				if hi - lo > 1e-4 * hi {
+					self.warning(d.line, "sphere under non-uniform scale is imported as a sphere of average radius");
+				}
+				let radius = d.params.float("radius", 1.0) * scale.mean();
+				Sphere {pos: ctm.transform_point(&Point3::origin()), radius}.into()
+			},
+			"trianglemesh" => {
+				let positions = d.params.numbers(&["point", "point3"], "P")
+					.ok_or_else(|| _error(d.line, "trianglemesh doesn't have 'point P'"))?;
+				let indices = match d.params.numbers(&["integer"], "indices") {
+					Some(indices) => indices,
+					None if positions.len() == 9 => vec![0.0, 1.0, 2.0],
+					None => return Err(_error(d.line, "trianglemesh doesn't have 'integer indices'")),
+				};
+				let mut mesh = MeshData {
+					positions: positions.chunks_exact(3).map(|p| Point3::new(p[0], p[1], p[2])).collect(),
+					normals: d.params.numbers(&["normal", "normal3"], "N").unwrap_or_default()
+						.chunks_exact(3).map(|n| Vector3::new(n[0], n[1], n[2])).collect(),
+					uvs: d.params.numbers(&["float", "point2"], "uv")
+						.or_else(|| d.params.numbers(&["float", "point2"], "st"))
+						.unwrap_or_default()
+						.chunks_exact(2).map(|uv| Vector2::new(uv[0], uv[1])).collect(),
+					colors: Vec::new(),
+					triangles: indices.chunks_exact(3).map(|t| [t[0] as u32, t[1] as u32, t[2] as u32]).collect(),
+				};
+				_transform_mesh(&mut mesh, &ctm);
+				Mesh::new(mesh).into()
+			},
+			"plymesh" => {
+				let file = d.params.string("filename")
+					.ok_or_else(|| _error(d.line, "plymesh doesn't have 'string filename'"))?;
+				let bytes = fs::read(self.base_dir.join(&file))
+					.map_err(|e| _error(d.line, format!("could not read '{}': {}", file, e)))?;
+				let mut ply_report = Report::new();
+				let mut mesh = read_ply(&bytes, &mut ply_report)
+					.map_err(|message| _error(d.line, format!("invalid ply '{}': {}", file, message)))?;
+				self.report.append_from_file(&file, ply_report);
+				_transform_mesh(&mut mesh, &ctm);
+				Mesh::new(mesh).into()
+			},
+			ty => {
+				self.warning(d.line, format!("shape '{}' is not supported, skipped", ty));
+				d.params.mark_all_used();
+				return Ok(());
+			},
+		};
+		self.scene.add_obj(SceneObject::with_material_ref(shape, self.state.material.clone()));
+		Ok(())
+	}
+
+	fn import_light(&mut self, d: &Directive) -> Result<(), SceneParserError> {
+		let ctm = self.state.ctm;
+		let params = &d.params;
+		let scale = params.rgb("scale", Color3::repeat(1.0));
+		let light: Lights = match d.string_arg(0)? {
+			"point" => PointLight::new(
+				ctm.transform_point(&params.point("from", Point3::origin())),
+				params.rgb("I", Color3::repeat(1.0)).component_mul(&scale)
+			).into(),
+			"spot" => {
+				let from = params.point("from", Point3::origin());
+				let to = params.point("to", Point3::new(0.0, 0.0, 1.0));
+				let outer = params.float("coneangle", 30.0);
+				let inner = outer - params.float("conedeltaangle", 5.0);
+				SpotLight::new(
+					ctm.transform_point(&from),
+					Unit::new_normalize(ctm.transform_vector(&(to - from))),
+					params.rgb("I", Color3::repeat(1.0)).component_mul(&scale),
+					inner.max(0.0).to_radians(),
+					outer.to_radians()
+				).into()
+			},
+			"distant" => {
+				let from = params.point("from", Point3::origin());
+				let to = params.point("to", Point3::new(0.0, 0.0, 1.0));
+				DirectionalLight::new(
+					Unit::new_normalize(ctm.transform_vector(&(to - from))),
+					params.rgb("L", Color3::repeat(1.0)).component_mul(&scale)
+				).into()
+			},
+			// constant environment become skylight
+			"infinite" => {
+				params.mark_used("samples");
+				let skylight = self.scene.get_skylight() + params.rgb("L", Color3::repeat(1.0)).component_mul(&scale);
+				self.scene.set_skylight(skylight);
+				return Ok(());
+			},
+			ty => {
+				self.warning(d.line, format!("light '{}' is not supported, skipped", ty));
+				params.mark_all_used();
+				return Ok(());
+			},
+		};
+		self.scene.add_light(light);
+		Ok(())
+	}
+
+	fn finish(self) -> SceneDocument {
+		let (width, height) = self.resolution;
+		// pbrt field of view is for the shorter axis, ours is horizontal
+		let (camera_to_world, fov) = self.camera.unwrap_or((Matrix4::identity(), 90.0));
+		let aspect = width as f32 / height as f32;
+		let half_tan = (fov.to_radians() / 2.0).tan();
+		let horizontal_fov = 2.0 * if aspect >= 1.0 { (half_tan * aspect).atan() } else { half_tan.atan() };
+
+		let mut camera = Camera::from_axes(
+			camera_to_world.transform_point(&Point3::origin()),
+			camera_to_world.transform_vector(&Vector3::z()),
+			camera_to_world.transform_vector(&Vector3::x()),
+			camera_to_world.transform_vector(&Vector3::y()),
+		);
+		camera.set_fov(horizontal_fov);
+
+		SceneDocument {
+			scene: Some(self.scene),
+			camera: Some(camera),
+			materials: self.materials.into_iter().collect(),
+			resolution: Some(self.resolution),
+			..SceneDocument::default()
+		}
+	}
+}
+
+// world to camera matrix of pbrt LookAt, None if up is parallel to view direction
+fn _look_at(eye: Point3<f32>, look: Point3<f32>, up: Vector3<f32>) -> Option<Matrix4<f32>> {
+	let dir = (look - eye).try_normalize(1e-12)?;
+	let right = up.try_normalize(1e-12)?.cross(&dir).try_normalize(1e-12)?;
+	let new_up = dir.cross(&right);
+	let camera_to_world = Matrix4::new(
+		right.x, new_up.x, dir.x, eye.x,
+		right.y, new_up.y, dir.y, eye.y,
+		right.z, new_up.z, dir.z, eye.z,
+		0.0, 0.0, 0.0, 1.0,
+	);
+	camera_to_world.try_inverse()
+}
+
+// mesh is baked into world space so any affine transform work
+fn _transform_mesh(mesh: &mut MeshData, m: &Matrix4<f32>) {
+	for p in mesh.positions.iter_mut() {
+		*p = m.transform_point(p);
+	}
+	let normal_matrix = m.fixed_slice::<nalgebra::U3, nalgebra::U3>(0, 0).try_inverse().map(|inv| inv.transpose());
+	if let Some(normal_matrix) = normal_matrix {
+		for n in mesh.normals.iter_mut() {
+			*n = (normal_matrix * *n).try_normalize(1e-12).unwrap_or(*n);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use assert_approx_eq::assert_approx_eq;
+
+	use crate::rtracer::load_scene_data_with_report;
+
+	use super::*;
+
+	#[test]
+	fn pbrt_import_test() {
+		let dir = std::env::temp_dir().join("rtracer_pbrt_test");
+		fs::create_dir_all(&dir).unwrap();
+		fs::write(dir.join("scene.pbrt"), r#"
+			LookAt 0 0 -5  0 0 0  0 1 0
+			Camera "perspective" "float fov" [45]
+			Film "image" "integer xresolution" [200] "integer yresolution" [100] "string filename" "out.exr"
+			Sampler "halton" "integer pixelsamples" 16
+
+			WorldBegin
+			LightSource "point" "rgb I" [10 10 10] "point from" [0 4 0]
+			LightSource "infinite" "rgb L" [0.1 0.1 0.1]
+			AttributeBegin
+				Material "matte" "rgb Kd" [0.8 0.2 0.2] "float roughness" 0.1
+				Translate 1 0 0
+				Scale 2 2 2
+				Shape "sphere" "float radius" 0.5
+			AttributeEnd
+			AttributeBegin
+				Material "glass"
+				Shape "trianglemesh" "integer indices" [0 1 2] "point P" [-1 -1 2  1 -1 2  0 1 2]
+			AttributeEnd
+			Shape "disk"
+			WorldEnd
+		"#).unwrap();
+
+		let (data, report) = load_scene_data_with_report(dir.join("scene.pbrt")).unwrap();
+		fs::remove_dir_all(&dir).unwrap();
+		assert_eq!(data.resolution, Some((200, 100)));
+		assert_eq!(data.scene.obj_count(), 2);
+		assert_eq!(data.scene.light_count(), 1);
+		assert_approx_eq!(data.scene.get_skylight().x, 0.1);
+
+		// sphere of radius 1 at x = 1, seen from the camera looking along +z
+		let sphere = data.scene.iter_obj().next().unwrap();
+		let hit = sphere.intersect(Point3::new(1.0, 0.0, -5.0), Vector3::z_axis(), 0.0).unwrap();
+		assert_approx_eq!(hit.dist, 4.0);
+		assert!(matches!(sphere.material.get(), Some(Materials::Diffuse(_))));
+
+		let lines: Vec<&str> = report.diagnostics().iter().map(|d| d.path.as_str()).collect();
+		// sampler, roughness, glass and disk
+		assert_eq!(report.diagnostics().len(), 4, "{}", report);
+		assert!(lines.iter().all(|path| path.ends_with(".pbrt:5") || path.ends_with(".pbrt:11")
+			|| path.ends_with(".pbrt:17") || path.ends_with(".pbrt:20")), "{:?}", lines);
+	}
+
+	#[test]
+	fn pbrt_syntax_error_test() {
+		let tokens = _tokenize("Shape \"sphere\n").map(|_| ());
+		assert!(matches!(tokens, Err(SceneParserError::PbrtError {line: 1, ..})));
+		let directives = _directives(&_tokenize("Translate 1 2\nShape \"sphere\" \"float radius\"").unwrap());
+		assert!(matches!(directives, Err(SceneParserError::PbrtError {line: 2, ..})));
+	}
+}
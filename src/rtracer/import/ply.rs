@@ -0,0 +1,341 @@
+use std::fs;
+use std::path::Path;
+
+use nalgebra::{Point3, Vector2, Vector3};
+
+use crate::rtracer::Color3;
+use crate::rtracer::geometric::MeshData;
+use crate::rtracer::parser::{SceneDocument, SceneParserError};
+use crate::rtracer::validation::{field, Report};
+
+use super::mesh_document;
+
+pub(crate) fn load_ply(path: &Path, report: &mut Report) -> Result<SceneDocument, SceneParserError> {
+	let bytes = fs::read(path)?;
+	let mesh = read_ply(&bytes, report).map_err(|message| SceneParserError::MeshError {message})?;
+	Ok(mesh_document(mesh))
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Encoding {
+	Ascii,
+	LittleEndian,
+	BigEndian,
+}
+
+#[derive(Clone, Copy)]
+enum Scalar {
+	I8, U8, I16, U16, I32, U32, F32, F64,
+}
+
+struct Property {
+	name: String,
+	// list has type of its length first
+	list: Option<Scalar>,
+	scalar: Scalar,
+}
+
+struct Element {
+	name: String,
+	count: usize,
+	properties: Vec<Property>,
+}
+
+/*
+ascii and binary ply, vertex element give position (x, y, z), normal (nx, ny, nz),
+color (red, green, blue) and uv (u, v or s, t), face element give vertex_indices
+polygon face is split into triangle fan, other element and property is skipped with a warning
+*/
+pub fn read_ply(bytes: &[u8], report: &mut Report) -> Result<MeshData, String> {
+	let (encoding, elements, body) = _read_header(bytes)?;
+	let mut reader = Reader {encoding, bytes: body, at: 0};
+
+	let mut mesh = MeshData {
+		positions: Vec::new(),
+		normals: Vec::new(),
+		uvs: Vec::new(),
+		colors: Vec::new(),
+		triangles: Vec::new(),
+	};
+	for element in elements.iter() {
+		match element.name.as_str() {
+			"vertex" => _read_vertices(&mut reader, element, &mut mesh, report)?,
+			"face" => _read_faces(&mut reader, element, &mut mesh)?,
+			_ => {
+				report.warning(&element.name, "element is not supported, skipped");
+				for _ in 0..element.count {
+					for property in element.properties.iter() {
+						reader.property(property)?;
+					}
+				}
+			},
+		}
+	}
+	Ok(mesh)
+}
+
+fn _read_header(bytes: &[u8]) -> Result<(Encoding, Vec<Element>, &[u8]), String> {
+	const END: &[u8] = b"end_header";
+	let end = bytes.windows(END.len()).position(|w| w == END).ok_or("missing end_header")?;
+	let body_start = bytes[end..].iter().position(|b| *b == b'\n').map_or(bytes.len(), |i| end + i + 1);
+	let header = std::str::from_utf8(&bytes[..end]).map_err(|_| "header is not valid text")?;
+
+	let mut lines = header.lines().map(str::trim);
+	if lines.next() != Some("ply") {
+		return Err("file doesn't start with 'ply'".to_string());
+	}
+
+	let mut encoding = None;
+	let mut elements: Vec<Element> = Vec::new();
+	for line in lines {
+		let words: Vec<&str> = line.split_whitespace().collect();
+		match words.as_slice() {
+			["format", format, _] => encoding = Some(match *format {
+				"ascii" => Encoding::Ascii,
+				"binary_little_endian" => Encoding::LittleEndian,
+				"binary_big_endian" => Encoding::BigEndian,
+				_ => return Err(format!("unknown format '{}'", format)),
+			}),
+			["element", name, count] => elements.push(Element {
+				name: name.to_string(),
+				count: count.parse().map_err(|_| format!("'{}' is not an element count", count))?,
+				properties: Vec::new(),
+			}),
+			["property", "list", count_type, item_type, name] => _last(&mut elements)?.properties.push(Property {
+				name: name.to_string(),
+				list: Some(_scalar(count_type)?),
+				scalar: _scalar(item_type)?,
+			}),
+			["property", scalar, name] => _last(&mut elements)?.properties.push(Property {
+				name: name.to_string(),
+				list: None,
+				scalar: _scalar(scalar)?,
+			}),
+			_ => (),
+		}
+	}
+
+	let encoding = encoding.ok_or("missing format line")?;
+	Ok((encoding, elements, &bytes[body_start..]))
+}
+
+fn _last(elements: &mut [Element]) -> Result<&mut Element, String> {
+	elements.last_mut().ok_or_else(|| "property before any element".to_string())
+}
+
+fn _scalar(name: &str) -> Result<Scalar, String> {
+	Ok(match name {
+		"char" | "int8" => Scalar::I8,
+		"uchar" | "uint8" => Scalar::U8,
+		"short" | "int16" => Scalar::I16,
+		"ushort" | "uint16" => Scalar::U16,
+		"int" | "int32" => Scalar::I32,
+		"uint" | "uint32" => Scalar::U32,
+		"float" | "float32" => Scalar::F32,
+		"double" | "float64" => Scalar::F64,
+		_ => return Err(format!("unknown property type '{}'", name)),
+	})
+}
+
+fn _read_vertices(reader: &mut Reader, element: &Element, mesh: &mut MeshData, report: &mut Report) -> Result<(), String> {
+	let find = |names: &[&str]| element.properties.iter().position(|p| names.contains(&p.name.as_str()));
+	let position = [find(&["x"]), find(&["y"]), find(&["z"])];
+	let normal = [find(&["nx"]), find(&["ny"]), find(&["nz"])];
+	let color = [find(&["red", "r"]), find(&["green", "g"]), find(&["blue", "b"])];
+	let uv = [find(&["u", "s", "texture_u", "texture_s"]), find(&["v", "t", "texture_v", "texture_t"])];
+
+	let has = |indices: &[Option<usize>]| indices.iter().all(Option::is_some);
+	if !has(&position) {
+		return Err("vertex element doesn't have x, y and z".to_string());
+	}
+	for property in element.properties.iter() {
+		let used = [&position[..], &normal[..], &color[..], &uv[..]].iter()
+			.any(|group| has(group) && group.iter().any(|i| element.properties[i.unwrap()].name == property.name));
+		if !used {
+			report.warning(&field("vertex", &property.name), "property is not supported, skipped");
+		}
+	}
+	// 8 bit color is sRGB like texture, float color is already linear
+	let color_scale = color[0].map(|i| match element.properties[i].scalar {
+		Scalar::F32 | Scalar::F64 => None,
+		Scalar::U16 => Some(65535.0),
+		_ => Some(255.0),
+	});
+
+	let mut values = vec![0.0; element.properties.len()];
+	for _ in 0..element.count {
+		for (i, property) in element.properties.iter().enumerate() {
+			values[i] = reader.property(property)?.first().cloned().unwrap_or(0.0);
+		}
+		let get = |i: Option<usize>| values[i.unwrap()] as f32;
+
+		mesh.positions.push(Point3::new(get(position[0]), get(position[1]), get(position[2])));
+		if has(&normal) {
+			mesh.normals.push(Vector3::new(get(normal[0]), get(normal[1]), get(normal[2])));
+		}
+		if has(&color) {
+			let c = Color3::new(get(color[0]), get(color[1]), get(color[2]));
+			mesh.colors.push(match color_scale {
+				Some(Some(scale)) => (c / scale).map(|x| x.powf(2.2)),
+				_ => c,
+			});
+		}
+		if has(&uv) {
+			mesh.uvs.push(Vector2::new(get(uv[0]), get(uv[1])));
+		}
+	}
+	Ok(())
+}
+
+fn _read_faces(reader: &mut Reader, element: &Element, mesh: &mut MeshData) -> Result<(), String> {
+	let indices = element.properties.iter()
+		.position(|p| p.list.is_some() && (p.name == "vertex_indices" || p.name == "vertex_index"))
+		.ok_or("face element doesn't have vertex_indices")?;
+
+	for _ in 0..element.count {
+		for (i, property) in element.properties.iter().enumerate() {
+			let values = reader.property(property)?;
+			if i == indices {
+				for k in 1..values.len().saturating_sub(1) {
+					mesh.triangles.push([values[0] as u32, values[k] as u32, values[k + 1] as u32]);
+				}
+			}
+		}
+	}
+	Ok(())
+}
+
+struct Reader<'a> {
+	encoding: Encoding,
+	bytes: &'a [u8],
+	at: usize,
+}
+
+impl<'a> Reader<'a> {
+	// every value of property, list has many
+	fn property(&mut self, property: &Property) -> Result<Vec<f64>, String> {
+		match property.list {
+			None => Ok(vec![self.scalar(property.scalar)?]),
+			Some(count_type) => {
+				let count = self.scalar(count_type)? as usize;
+				(0..count).map(|_| self.scalar(property.scalar)).collect()
+			},
+		}
+	}
+
+	fn scalar(&mut self, scalar: Scalar) -> Result<f64, String> {
+		if self.encoding == Encoding::Ascii {
+			return self.ascii();
+		}
+
+		let size = match scalar {
+			Scalar::I8 | Scalar::U8 => 1,
+			Scalar::I16 | Scalar::U16 => 2,
+			Scalar::I32 | Scalar::U32 | Scalar::F32 => 4,
+			Scalar::F64 => 8,
+		};
+		let raw = self.bytes.get(self.at..self.at + size).ok_or("unexpected end of file")?;
+		self.at += size;
+		let mut buf = [0u8; 8];
+		buf[..size].copy_from_slice(raw);
+		if self.encoding == Encoding::BigEndian {
+			buf[..size].reverse();
+		}
+
+		Ok(match scalar {
+			Scalar::I8 => buf[0] as i8 as f64,
+			Scalar::U8 => buf[0] as f64,
+			Scalar::I16 => i16::from_le_bytes([buf[0], buf[1]]) as f64,
+			Scalar::U16 => u16::from_le_bytes([buf[0], buf[1]]) as f64,
+			Scalar::I32 => i32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as f64,
+			Scalar::U32 => u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as f64,
+			Scalar::F32 => f32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as f64,
+			Scalar::F64 => f64::from_le_bytes(buf),
+		})
+	}
+
+	fn ascii(&mut self) -> Result<f64, String> {
+		let rest = &self.bytes[self.at..];
+		let start = rest.iter().position(|b| !b.is_ascii_whitespace()).ok_or("unexpected end of file")?;
+		let len = rest[start..].iter().position(|b| b.is_ascii_whitespace()).unwrap_or(rest.len() - start);
+		self.at += start + len;
+		let token = String::from_utf8_lossy(&rest[start..start + len]);
+		token.parse().map_err(|_| format!("'{}' is not a number", token))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use assert_approx_eq::assert_approx_eq;
+
+	use crate::rtracer::load_scene_data;
+
+	use super::*;
+
+	const QUAD_HEADER: &str = "ply
+format {} 1.0
+comment red and blue corner
+element vertex 4
+property float x
+property float y
+property float z
+property uchar red
+property uchar green
+property uchar blue
+property float confidence
+element face 1
+property list uchar int vertex_indices
+end_header
+";
+
+	#[test]
+	fn read_ply_test() {
+		let ascii = QUAD_HEADER.replace("{}", "ascii") + "0 0 0 255 0 0 1\n1 0 0 255 0 0 1\n1 1 0 0 0 255 1\n0 1 0 0 0 255 1\n4 0 1 2 3\n";
+		let mut report = Report::new();
+		let mesh = read_ply(ascii.as_bytes(), &mut report).unwrap();
+		assert_eq!(mesh.triangles, vec![[0, 1, 2], [0, 2, 3]]);
+		assert_approx_eq!(mesh.colors[2].z, 1.0);
+		assert_eq!(report.diagnostics()[0].path, "vertex.confidence");
+
+		let mut binary = QUAD_HEADER.replace("{}", "binary_big_endian").into_bytes();
+		for (x, y, c) in &[(0.0f32, 0.0f32, 255u8), (1.0, 0.0, 255), (1.0, 1.0, 0), (0.0, 1.0, 0)] {
+			for v in &[*x, *y, 0.0] {
+				binary.extend_from_slice(&v.to_be_bytes());
+			}
+			binary.extend_from_slice(&[*c, 0, 255 - c]);
+			binary.extend_from_slice(&1.0f32.to_be_bytes());
+		}
+		binary.push(4);
+		for i in 0..4i32 {
+			binary.extend_from_slice(&i.to_be_bytes());
+		}
+		let binary_mesh = read_ply(&binary, &mut Report::new()).unwrap();
+		assert_eq!(binary_mesh.triangles, mesh.triangles);
+		assert_approx_eq!(binary_mesh.positions[2].y, 1.0);
+		assert_approx_eq!(binary_mesh.colors[0].x, 1.0);
+	}
+
+	#[test]
+	fn include_ply_test() {
+		let dir = std::env::temp_dir().join("rtracer_ply_test");
+		fs::create_dir_all(&dir).unwrap();
+		let ply = QUAD_HEADER.replace("{}", "ascii") + "0 0 0 255 0 0 1\n1 0 0 255 0 0 1\n1 1 0 0 0 255 1\n0 1 0 0 0 255 1\n4 0 1 2 3\n";
+		fs::write(dir.join("quad.ply"), ply).unwrap();
+		fs::write(dir.join("main.ron"), r#"(
+			include: [(
+				path: "quad.ply",
+				transform: Some((isometry: (rotation: [0, 0, 0, 1], translation: [3, 0, 0]), scaling: 2)),
+				material: Some(Diffuse((color: [0.5, 0.5, 0.5]))),
+			)],
+			scene: (lights: [PointLight((pos: [0, 0, 3], light: [1, 1, 1]))]),
+			camera: (pos: [0, 0, 0], forward: [1, 0, 0], right: [0, 1, 0], up: [0, 0, 1]),
+		)"#).unwrap();
+
+		let data = load_scene_data(dir.join("main.ron")).unwrap();
+		fs::remove_dir_all(&dir).unwrap();
+		let quad = data.scene.iter_obj().next().unwrap();
+		let hit = quad.intersect(Point3::new(4.0, 1.0, 5.0), -Vector3::z_axis(), 0.0).unwrap();
+		assert_approx_eq!(hit.dist, 5.0);
+		assert!(hit.vertex_color.is_some());
+	}
+}
@@ -0,0 +1,162 @@
+use std::fs;
+use std::path::Path;
+use std::str::SplitWhitespace;
+
+use nalgebra::{Point3, Vector3};
+
+use crate::rtracer::geometric::MeshData;
+use crate::rtracer::parser::{SceneDocument, SceneParserError};
+
+use super::mesh_document;
+
+// size of header and triangle count of binary stl, then 50 bytes per triangle
+const BINARY_HEADER: usize = 84;
+const BINARY_TRIANGLE: usize = 50;
+
+pub(crate) fn load_stl(path: &Path) -> Result<SceneDocument, SceneParserError> {
+	let bytes = fs::read(path)?;
+	let mesh = read_stl(&bytes).map_err(|message| SceneParserError::MeshError {message})?;
+	Ok(mesh_document(mesh))
+}
+
+// ascii or binary stl, every triangle has its own vertex with facet normal as vertex normal
+pub fn read_stl(bytes: &[u8]) -> Result<MeshData, String> {
+	// binary file may also start with "solid", so trust the size first
+	let is_binary = bytes.len() >= BINARY_HEADER
+		&& BINARY_HEADER + BINARY_TRIANGLE * _u32_at(bytes, 80) as usize == bytes.len();
+	let facets = if is_binary || !bytes.starts_with(b"solid") {
+		_read_binary(bytes)?
+	}
+	else {
+		_read_ascii(bytes)?
+	};
+
+	let mut mesh = MeshData {
+		positions: Vec::new(),
+		normals: Vec::new(),
+		uvs: Vec::new(),
+		colors: Vec::new(),
+		triangles: Vec::new(),
+	};
+	for (normal, [a, b, c]) in facets {
+		// some exporter write zero normal, use winding instead
+		let normal = match normal.try_normalize(1e-12) {
+			Some(n) => n,
+			None => (b - a).cross(&(c - a)).try_normalize(1e-12).unwrap_or_else(Vector3::z),
+		};
+		let i = mesh.positions.len() as u32;
+		mesh.positions.extend_from_slice(&[a, b, c]);
+		mesh.normals.extend_from_slice(&[normal, normal, normal]);
+		mesh.triangles.push([i, i + 1, i + 2]);
+	}
+	Ok(mesh)
+}
+
+type Facet = (Vector3<f32>, [Point3<f32>; 3]);
+
+fn _u32_at(bytes: &[u8], at: usize) -> u32 {
+	u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+fn _f32_at(bytes: &[u8], at: usize) -> f32 {
+	f32::from_bits(_u32_at(bytes, at))
+}
+
+fn _read_binary(bytes: &[u8]) -> Result<Vec<Facet>, String> {
+	if bytes.len() < BINARY_HEADER {
+		return Err("file is too short to be a binary stl".to_string());
+	}
+	let count = _u32_at(bytes, 80) as usize;
+	if bytes.len() < BINARY_HEADER + BINARY_TRIANGLE * count {
+		return Err(format!("binary stl declare {} triangles but file is truncated", count));
+	}
+
+	Ok((0..count)
+		.map(|i| {
+			let at = BINARY_HEADER + BINARY_TRIANGLE * i;
+			let vec = |k: usize| Vector3::new(
+				_f32_at(bytes, at + 12 * k),
+				_f32_at(bytes, at + 12 * k + 4),
+				_f32_at(bytes, at + 12 * k + 8)
+			);
+			(vec(0), [Point3::from(vec(1)), Point3::from(vec(2)), Point3::from(vec(3))])
+		})
+		.collect())
+}
+
+fn _read_ascii(bytes: &[u8]) -> Result<Vec<Facet>, String> {
+	let text = std::str::from_utf8(bytes).map_err(|_| "ascii stl is not valid text".to_string())?;
+	let mut tokens = text.split_whitespace();
+	let mut facets = Vec::new();
+	let mut normal = Vector3::zeros();
+	let mut vertices = Vec::new();
+	while let Some(token) = tokens.next() {
+		match token {
+			"normal" => normal = _read_vector(&mut tokens)?,
+			"vertex" => vertices.push(Point3::from(_read_vector(&mut tokens)?)),
+			"endfacet" => {
+				if vertices.len() != 3 {
+					return Err(format!("facet {} has {} vertices, expected 3", facets.len(), vertices.len()));
+				}
+				facets.push((normal, [vertices[0], vertices[1], vertices[2]]));
+				vertices.clear();
+			},
+			_ => (),
+		}
+	}
+	Ok(facets)
+}
+
+fn _read_vector(tokens: &mut SplitWhitespace) -> Result<Vector3<f32>, String> {
+	let mut v = Vector3::zeros();
+	for i in 0..3 {
+		let token = tokens.next().ok_or("unexpected end of file")?;
+		v[i] = token.parse().map_err(|_| format!("'{}' is not a number", token))?;
+	}
+	Ok(v)
+}
+
+#[cfg(test)]
+mod tests {
+	use assert_approx_eq::assert_approx_eq;
+
+	use super::*;
+
+	#[test]
+	fn read_stl_test() {
+		let ascii = read_stl(b"solid tri
+			facet normal 0 0 1
+				outer loop
+					vertex 0 0 0
+					vertex 1 0 0
+					vertex 0 1 0
+				endloop
+			endfacet
+			facet normal 0 0 0
+				outer loop
+					vertex 0 0 0
+					vertex 0 1 0
+					vertex 0 0 1
+				endloop
+			endfacet
+		endsolid tri").unwrap();
+		assert_eq!(ascii.triangles, vec![[0, 1, 2], [3, 4, 5]]);
+		assert_approx_eq!(ascii.normals[0].z, 1.0);
+		// zero facet normal is computed from winding
+		assert_approx_eq!(ascii.normals[3].x, 1.0);
+
+		// binary header that start with "solid" too
+		let mut binary = b"solid but binary".to_vec();
+		binary.resize(80, 0);
+		binary.extend_from_slice(&1u32.to_le_bytes());
+		for x in &[0.0f32, 0.0, 1.0, 0.0, 0.0, 0.0, 2.0, 0.0, 0.0, 0.0, 2.0, 0.0] {
+			binary.extend_from_slice(&x.to_le_bytes());
+		}
+		binary.extend_from_slice(&[0, 0]);
+		let binary = read_stl(&binary).unwrap();
+		assert_eq!(binary.positions.len(), 3);
+		assert_approx_eq!(binary.positions[1].x, 2.0);
+
+		assert!(read_stl(b"solid broken facet normal 0 0 1 vertex 0 0").is_err());
+	}
+}
@@ -0,0 +1,133 @@
+/*
+integrator other than whitted, for lookdev and debugging
+they get the camera ray and sampler of the main render loop, so spp, motion blur and tone mapping still apply
+
+ambient occlusion: fraction of hemisphere not blocked within radius
+normal, depth, uv, object id: first hit visualized as color
+heatmap: how many intersection test the camera ray cost (shadow and reflection ray aren't traced)
+*/
+use nalgebra::{Point3, Unit, Vector3};
+
+use super::{Color3, helper, Scene};
+use super::renderer::{raycast, raycast_return_ref};
+use super::sampler::Sampler;
+use super::stats;
+
+// cosine weighted so occlusion near the normal count more, miss is unoccluded
+pub fn ambient_occlusion(
+	scene: &Scene,
+	origin: Point3<f32>,
+	dir: Unit<Vector3<f32>>,
+	time: f32,
+	radius: f32,
+	samples: u32,
+	sampler: &mut impl Sampler)
+	-> Color3 {
+	let hit = match raycast(scene, origin, dir, time) {
+		Some(hit) => hit,
+		None => return Color3::repeat(1.0),
+	};
+	let samples = samples.max(1);
+	// look from the side camera see
+	let normal = if hit.normal.dot(&dir) > 0.0 { -hit.normal } else { hit.normal };
+	let open = (0..samples)
+		.filter(|_| {
+			let ao_dir = helper::sample_cosine_hemisphere(sampler.get_2d(), &normal);
+			!matches!(raycast(scene, hit.intersection, ao_dir, time), Some(occluder) if occluder.dist <= radius)
+		})
+		.count();
+	Color3::repeat(open as f32 / samples as f32)
+}
+
+// shading normal from [-1, 1] to [0, 1]
+pub fn normal(scene: &Scene, origin: Point3<f32>, dir: Unit<Vector3<f32>>, time: f32) -> Color3 {
+	raycast(scene, origin, dir, time)
+		.map_or(Color3::zeros(), |hit| (hit.normal.into_inner() + Color3::repeat(1.0)) / 2.0)
+}
+
+// distance along camera ray, normalize tone map fit it to the image
+pub fn depth(scene: &Scene, origin: Point3<f32>, dir: Unit<Vector3<f32>>, time: f32) -> Color3 {
+	raycast(scene, origin, dir, time).map_or(Color3::zeros(), |hit| Color3::repeat(hit.dist))
+}
+
+// u as red, v as green, shape without uv is black
+pub fn uv(scene: &Scene, origin: Point3<f32>, dir: Unit<Vector3<f32>>, time: f32) -> Color3 {
+	raycast(scene, origin, dir, time)
+		.and_then(|hit| hit.uv)
+		.map_or(Color3::zeros(), |uv| Color3::new(uv.x, uv.y, 0.0))
+}
+
+// random color from position of object in (flattened) scene, stable across frame
+pub fn object_id(scene: &Scene, origin: Point3<f32>, dir: Unit<Vector3<f32>>, time: f32) -> Color3 {
+	raycast_return_ref(scene, origin, dir, time)
+		.and_then(|(_, obj)| scene.iter_obj().position(|o| std::ptr::eq(o, obj)))
+		.map_or(Color3::zeros(), |id| _id_color(id as u64))
+}
+
+fn _id_color(id: u64) -> Color3 {
+	// splitmix64, so neighboring id get unrelated color
+	let mut z = id.wrapping_add(0x9E37_79B9_7F4A_7C15);
+	z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+	z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+	z ^= z >> 31;
+	// keep away from black so background stay distinct
+	let channel = |shift: u64| 0.2 + 0.8 * ((z >> shift) & 0xFF) as f32 / 255.0;
+	Color3::new(channel(0), channel(8), channel(16))
+}
+
+// raw count of test, turned into color by heat_ramp once the whole image is known
+pub fn intersection_count(scene: &Scene, origin: Point3<f32>, dir: Unit<Vector3<f32>>, time: f32) -> Color3 {
+	stats::take_intersection_tests();
+	raycast(scene, origin, dir, time);
+	Color3::repeat(stats::take_intersection_tests() as f32)
+}
+
+// count relative to most expensive pixel, black -> blue -> red -> yellow -> white
+pub fn heat_ramp(count: f32, max: f32) -> Color3 {
+	let t = if max > 0.0 { (count / max).clamp(0.0, 1.0) * 4.0 } else { 0.0 };
+	let ramp = |from: f32| (t - from).clamp(0.0, 1.0);
+	Color3::new(ramp(1.0), ramp(2.0), ramp(0.0) - ramp(1.0) + ramp(3.0))
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::rtracer::SceneObject;
+	use crate::rtracer::geometric::{InfinitePlane, Sphere};
+	use crate::rtracer::material::Diffuse;
+	use crate::rtracer::sampler::SamplerKind;
+
+	use super::*;
+
+	// point in the corner between plane and sphere is darker than open plane
+	#[test]
+	fn ambient_occlusion_test() {
+		let mut scene = Scene::new();
+		scene.add_obj(SceneObject::new(
+			InfinitePlane {pos: Point3::origin(), norm: Vector3::z_axis()},
+			Diffuse::new(Color3::repeat(1.0))
+		));
+		scene.add_obj(SceneObject::new(
+			Sphere {pos: Point3::new(0.0, 0.0, 1.0), radius: 1.0},
+			Diffuse::new(Color3::repeat(1.0))
+		));
+		let mut sampler = SamplerKind::Stratified.build(3, 1);
+		sampler.start_sample(0, 0, 0);
+		let down = -Vector3::z_axis();
+
+		let mut ao_at = |x: f32, radius: f32| ambient_occlusion(
+			&scene, Point3::new(x, 0.0, 5.0), down, 0.0, radius, 64, &mut sampler
+		).x;
+		assert_eq!(ao_at(100.0, 1.0), 1.0);
+		let corner = ao_at(1.1, 1.0);
+		assert!(corner < 0.9, "{}", corner);
+		// radius shorter than distance to sphere see nothing
+		assert_eq!(ao_at(1.1, 1e-3), 1.0);
+	}
+
+	#[test]
+	fn heat_ramp_test() {
+		assert_eq!(heat_ramp(0.0, 10.0), Color3::zeros());
+		assert_eq!(heat_ramp(10.0, 10.0), Color3::repeat(1.0));
+		assert_eq!(heat_ramp(3.0, 0.0), Color3::zeros());
+	}
+}
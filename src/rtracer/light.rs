@@ -1,33 +1,189 @@
-use itertools::Itertools;
 use nalgebra::{Point3, Similarity3, Translation3, Unit, UnitQuaternion, Vector2, Vector3};
-use rand::distributions::{Distribution, Uniform};
-use rand::thread_rng;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde::de::Error;
 
 use enum_dispatch::enum_dispatch;
 
-use super::{AREALIGHT_FINITEDIFF_LENGTH, AREALIGHT_MONTECARLO_SAMPLE};
+use super::AREALIGHT_MONTECARLO_SAMPLE;
 // use super::Color3;
 use super::Color3;
 use super::renderer::raycast;
+use super::light_selection::{LightBounds, luminance};
+use super::sampler::Sampler;
 use super::Scene;
+use super::validation::{field, Report, Validate};
 
 #[enum_dispatch]
 pub trait Light {
 	// intensity of light at position=pos at normal=norm factored in normal attenuation
-	fn direct_light_at(&self, pos: Point3<f32>, norm: Unit<Vector3<f32>>, scene: &Scene) -> Color3;
+	// light that need random sample take it from sampler, so render stay deterministic with seed
+	fn direct_light_at(&self, pos: Point3<f32>, norm: Unit<Vector3<f32>>, scene: &Scene, time: f32, sampler: &mut impl Sampler) -> Color3;
+
+	// pick direction toward light as seen from pos, u is 2d sample in [0, 1)
+	fn sample(&self, pos: Point3<f32>, u: (f32, f32)) -> LightSample;
+
+	// solid angle density that sample pick dir from pos, 0 when dir miss the light
+	fn pdf(&self, _pos: Point3<f32>, _dir: &Unit<Vector3<f32>>) -> f32 {
+		0.0
+	}
+
+	// (radiance, distance) of light seen from pos along dir, None when dir miss the light
+	fn eval(&self, _pos: Point3<f32>, _dir: &Unit<Vector3<f32>>) -> Option<(Color3, f32)> {
+		None
+	}
+
+	// single point or direction, only light sample can reach it
+	fn is_delta(&self) -> bool {
+		true
+	}
+}
+
+// one direction toward light picked by Light::sample
+pub struct LightSample {
+	// from shaded point toward light
+	pub dir: Unit<Vector3<f32>>,
+	// distance to sampled point, infinity for directional light
+	pub dist: f32,
+	// radiance for area light, intensity / dist^2 for delta light
+	pub light: Color3,
+	// solid angle density, None for delta light
+	pub pdf: Option<f32>,
+}
+
+impl LightSample {
+	pub fn new(dir: Unit<Vector3<f32>>, dist: f32, light: Color3, pdf: Option<f32>) -> Self {
+		LightSample {dir, dist, light, pdf}
+	}
+
+	// nothing block the way between pos and sampled point
+	pub fn visible(&self, scene: &Scene, pos: Point3<f32>, time: f32) -> bool {
+		if self.dist.is_infinite() {
+			return raycast(scene, pos, self.dir, time).is_none();
+		}
+		// cast from the light back to pos like point light, so surface at pos doesn't hit itself
+		// 1e-4 is for mitigate float unstable comparision
+		let light_pos = pos + self.dir.into_inner() * self.dist;
+		match raycast(scene, light_pos, -self.dir, time) {
+			None => true,
+			Some(hit) => hit.dist + 1e-4 >= self.dist,
+		}
+	}
 }
 
 #[enum_dispatch(Light)]
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub enum Lights {
 	PointLight,
 	DirectionalLight,
 	AreaLight,
+	SpotLight,
+}
+
+impl Lights {
+	// move light into new coordinate, used when scene is placed under a transform
+	pub fn transform(&mut self, trans: &Similarity3<f32>) {
+		match self {
+			Lights::PointLight(l) => l.pos = trans * l.pos,
+			Lights::DirectionalLight(l) => l.dir = trans.isometry.rotation * l.dir,
+			Lights::AreaLight(l) => l.transformer = trans * l.transformer,
+			Lights::SpotLight(l) => {
+				l.pos = trans * l.pos;
+				l.dir = trans.isometry.rotation * l.dir;
+			},
+		}
+	}
+
+	// emitted light color (color * intensity)
+	pub fn emit_mut(&mut self) -> &mut Color3 {
+		match self {
+			Lights::PointLight(l) => &mut l.light,
+			Lights::DirectionalLight(l) => &mut l.light,
+			Lights::AreaLight(l) => &mut l.light,
+			Lights::SpotLight(l) => &mut l.light,
+		}
+	}
+
+	// total emitted power, directional light is taken over a disc of scene_radius
+	pub fn power(&self, scene_radius: f32) -> Color3 {
+		use std::f32::consts::PI;
+		match self {
+			Lights::PointLight(l) => l.light * (4.0 * PI),
+			Lights::DirectionalLight(l) => l.light * (PI * scene_radius * scene_radius),
+			// lambertian on both side
+			Lights::AreaLight(l) => l.light * (2.0 * PI),
+			// average cosine of the falloff band, same as PBRT
+			Lights::SpotLight(l) => {
+				let (cos_inner, cos_outer) = (l.inner_angle.min(l.outer_angle).cos(), l.outer_angle.cos());
+				l.light * (2.0 * PI * (1.0 - (cos_inner + cos_outer) / 2.0))
+			},
+		}
+	}
+
+	// where light is and where it shine, for light bvh. None for directional light that is everywhere
+	pub fn bounds(&self) -> Option<LightBounds> {
+		let point_bounds = |pos: Point3<f32>, light: &Color3, w, cos_theta_o: f32, cos_theta_e: f32| LightBounds {
+			min: pos, max: pos, phi: luminance(light), w, cos_theta_o, cos_theta_e, two_sided: false,
+		};
+		match self {
+			Lights::PointLight(l) => Some(point_bounds(l.pos, &l.light, Vector3::z_axis(), -1.0, 0.0)),
+			Lights::DirectionalLight(_) => None,
+			Lights::AreaLight(l) => {
+				let corners: Vec<Point3<f32>> = [(-1.0, -1.0), (-1.0, 1.0), (1.0, -1.0), (1.0, 1.0)].iter()
+					.map(|&(y, z)| l.transformer * Point3::new(0.0, y, z))
+					.collect();
+				let (min, max) = corners.iter().fold((corners[0], corners[0]), |(min, max), c| (
+					Point3::new(min.x.min(c.x), min.y.min(c.y), min.z.min(c.z)),
+					Point3::new(max.x.max(c.x), max.y.max(c.y), max.z.max(c.z)),
+				));
+				Some(LightBounds {
+					min, max, phi: luminance(&l.light), w: l._normal(),
+					cos_theta_o: 1.0, cos_theta_e: 0.0, two_sided: true,
+				})
+			},
+			// full intensity inside inner angle, falloff band until outer angle
+			Lights::SpotLight(l) => {
+				let inner = l.inner_angle.min(l.outer_angle).max(0.0);
+				Some(point_bounds(l.pos, &l.light, l.dir, inner.cos(), (l.outer_angle - inner).cos()))
+			},
+		}
+	}
+}
+
+impl Validate for Lights {
+	fn validate(&self, path: &str, report: &mut Report) {
+		match self {
+			Lights::PointLight(l) => {
+				report.check_point(&field(path, "pos"), &l.pos);
+				report.check_light(&field(path, "light"), &l.light);
+			},
+			Lights::DirectionalLight(l) => {
+				report.check_unit(&field(path, "dir"), &l.dir);
+				report.check_light(&field(path, "light"), &l.light);
+			},
+			Lights::AreaLight(l) => {
+				report.check_transform(path, &l.transformer);
+				report.check_light(&field(path, "light"), &l.light);
+			},
+			Lights::SpotLight(l) => {
+				report.check_point(&field(path, "pos"), &l.pos);
+				report.check_unit(&field(path, "dir"), &l.dir);
+				report.check_light(&field(path, "light"), &l.light);
+				report.check_finite(&field(path, "inner_angle"), l.inner_angle);
+				let outer_path = field(path, "outer_angle");
+				report.check_positive(&outer_path, l.outer_angle);
+				if l.outer_angle > std::f32::consts::PI {
+					report.error(&outer_path, format!("must not be wider than pi, got {}", l.outer_angle));
+				}
+				if l.inner_angle > l.outer_angle {
+					report.warning(&field(path, "inner_angle"), "is wider than outer_angle, cone has no falloff");
+				}
+			},
+		}
+	}
 }
 
 // Point Light
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct PointLight {
 	pos: Point3<f32>,
 	light: Color3
@@ -43,7 +199,8 @@ impl PointLight {
 		self_light: Color3,
 		pos: Point3<f32>, 
 		norm: Unit<Vector3<f32>>,
-		scene: &Scene)
+		scene: &Scene,
+		time: f32)
 		-> Color3 {
 			
 		let (dir_to_obj, dist_to_obj) = Unit::new_and_get(pos - self_pos);
@@ -53,7 +210,7 @@ impl PointLight {
 			return [0.0, 0.0, 0.0].into();
 		}
 		
-		let hit_info = raycast(scene, self_pos, dir_to_obj);
+		let hit_info = raycast(scene, self_pos, dir_to_obj, time);
 		 
 		match hit_info {
 			None => scene.get_skylight(),
@@ -75,14 +232,19 @@ impl PointLight {
 }
 
 impl Light for PointLight {
-	fn direct_light_at(&self, pos: Point3<f32>, norm: Unit<Vector3<f32>>, scene: &Scene) -> Color3 {
-		Self::_light_at(self.pos, self.light, pos, norm, scene)	
+	fn direct_light_at(&self, pos: Point3<f32>, norm: Unit<Vector3<f32>>, scene: &Scene, time: f32, _sampler: &mut impl Sampler) -> Color3 {
+		Self::_light_at(self.pos, self.light, pos, norm, scene, time)	
+	}
+
+	fn sample(&self, pos: Point3<f32>, _u: (f32, f32)) -> LightSample {
+		let (dir, dist) = Unit::new_and_get(self.pos - pos);
+		LightSample::new(dir, dist, self.light / (dist * dist), None)
 	}
 }
 
 
 // Direction Light
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct DirectionalLight {
 	dir: Unit<Vector3<f32>>,
 	light: Color3,
@@ -95,7 +257,7 @@ impl DirectionalLight {
 }
 
 impl Light for DirectionalLight {
-	fn direct_light_at(&self, pos: Point3<f32>, norm: Unit<Vector3<f32>>, scene: &Scene) -> Color3 {
+	fn direct_light_at(&self, pos: Point3<f32>, norm: Unit<Vector3<f32>>, scene: &Scene, time: f32, _sampler: &mut impl Sampler) -> Color3 {
 		
 		let norm_attune = -norm.dot(self.dir.as_ref());
 		
@@ -103,24 +265,57 @@ impl Light for DirectionalLight {
 			return scene.get_skylight();
 		}
 		
-		let hit_info = raycast(scene, pos, -self.dir);
+		let hit_info = raycast(scene, pos, -self.dir, time);
 		
 		match hit_info {
 			None => self.light * norm_attune,
 			Some(_) => scene.get_skylight(),
 		}
 	}
+
+	fn sample(&self, _pos: Point3<f32>, _u: (f32, f32)) -> LightSample {
+		LightSample::new(-self.dir, f32::INFINITY, self.light, None)
+	}
 }
 
 
 // Area Light
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Clone)]
+#[serde(into = "AreaLightLayout")]
 pub struct AreaLight {
-	// #[serde(flatten)]
 	transformer: Similarity3<f32>,
 	light: Color3,
 }
 
+// how area light is written since version 2, part of the transform side by side instead of a nested Similarity3
+// square span [-scaling, scaling] around translation, facing +x turned by rotation
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename = "AreaLight")]
+struct AreaLightLayout {
+	translation: Vector3<f32>,
+	rotation: UnitQuaternion<f32>,
+	scaling: f32,
+	light: Color3,
+}
+
+// Similarity3 can't hold zero scaling, so it is refused while reading instead of by validation
+impl<'de> Deserialize<'de> for AreaLight {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		let layout = AreaLightLayout::deserialize(deserializer)?;
+		if layout.scaling.is_nan() || layout.scaling.abs() <= 1e-6 {
+			return Err(D::Error::custom(format!("area light scaling must not be zero, got {}", layout.scaling)));
+		}
+		Ok(AreaLight::new(layout.translation, layout.rotation, layout.scaling, Some(layout.light)))
+	}
+}
+
+impl From<AreaLight> for AreaLightLayout {
+	fn from(light: AreaLight) -> Self {
+		let t = light.transformer;
+		AreaLightLayout {translation: t.isometry.translation.vector, rotation: t.isometry.rotation, scaling: t.scaling(), light: light.light}
+	}
+}
+
 impl AreaLight{
 	pub fn new(center: Vector3<f32>, rotation: UnitQuaternion<f32>, scaling: f32, light: Option<Color3>) -> Self {
 		AreaLight {
@@ -131,49 +326,116 @@ impl AreaLight{
 		}
 	}
 	
-	fn _light_at_monte_carol(
-		self_trans: Similarity3<f32>,
-		self_light: Color3,
-		pos: Point3<f32>,
-		norm: Unit<Vector3<f32>>,
-		scene: &Scene) -> Color3 {
-		
-		let mut rng = thread_rng();
-		// TODO: Move to global or struct?
-		let distribution = Uniform::new_inclusive(-1.0, 1.0);
-		
+	fn _normal(&self) -> Unit<Vector3<f32>> {
+		self.transformer.isometry.rotation * Vector3::x_axis()
+	}
+
+	// light leave both side as lambertian emitter, intensity toward normal is `light`
+	fn _radiance(&self) -> Color3 {
+		let side = 2.0 * self.transformer.scaling();
+		self.light / (side * side)
+	}
+
+	// solid angle density of uniform point on the square, seen at dir and dist
+	fn _solid_angle_pdf(&self, dir: &Unit<Vector3<f32>>, dist: f32) -> f32 {
+		let side = 2.0 * self.transformer.scaling();
+		let cos = self._normal().dot(dir).abs();
+		if cos < 1e-6 { 0.0 } else { dist * dist / (side * side * cos) }
+	}
+}
+
+impl Light for AreaLight {
+	// light sample only, material weight it against bsdf sample with mis::direct_light
+	fn direct_light_at(&self, pos: Point3<f32>, norm: Unit<Vector3<f32>>, scene: &Scene, time: f32, sampler: &mut impl Sampler) -> Color3 {
 		(0..AREALIGHT_MONTECARLO_SAMPLE).map( |_| {
-			
-			let transformed_point = self_trans * 
-					Point3::new(0.0, distribution.sample(&mut rng), distribution.sample(&mut rng));
-			
-			PointLight::_light_at(transformed_point, self_light, pos, norm, scene)
+			let sample = self.sample(pos, sampler.get_2d());
+			match sample.pdf {
+				Some(pdf) if pdf > 0.0 && sample.visible(scene, pos, time) =>
+					sample.light * (norm.dot(&sample.dir).max(0.0) / pdf),
+				_ => Color3::zeros(),
+			}
 		}).sum::<Color3>() / (AREALIGHT_MONTECARLO_SAMPLE as f32)
 	}
-	
-	fn _light_at_finite_diff(
-	self_trans: Similarity3<f32>,
-	self_light: Color3,
+
+	// uniform point over the square, sampler stratify it so there's no grid aliasing
+	fn sample(&self, pos: Point3<f32>, (u, v): (f32, f32)) -> LightSample {
+		let point = self.transformer * Point3::new(0.0, 2.0 * u - 1.0, 2.0 * v - 1.0);
+		let (dir, dist) = Unit::new_and_get(point - pos);
+		if dist < 1e-6 {
+			return LightSample::new(self._normal(), 0.0, Color3::zeros(), Some(0.0));
+		}
+		LightSample::new(dir, dist, self._radiance(), Some(self._solid_angle_pdf(&dir, dist)))
+	}
+
+	fn pdf(&self, pos: Point3<f32>, dir: &Unit<Vector3<f32>>) -> f32 {
+		self.eval(pos, dir).map_or(0.0, |(_, dist)| self._solid_angle_pdf(dir, dist))
+	}
+
+	// intersect ray with the square, local y and z within [-1, 1]
+	fn eval(&self, pos: Point3<f32>, dir: &Unit<Vector3<f32>>) -> Option<(Color3, f32)> {
+		let normal = self._normal();
+		let denom = normal.dot(dir);
+		if denom.abs() < 1e-6 {
+			return None;
+		}
+		let center = self.transformer * Point3::origin();
+		let dist = normal.dot(&(center - pos)) / denom;
+		if dist <= 1e-4 {
+			return None;
+		}
+		let local = self.transformer.inverse_transform_point(&(pos + dir.into_inner() * dist));
+		if local.y.abs() <= 1.0 && local.z.abs() <= 1.0 {
+			Some((self._radiance(), dist))
+		} else {
+			None
+		}
+	}
+
+	fn is_delta(&self) -> bool {
+		false
+	}
+}
+
+// Spot Light, point light that only shine inside a cone around dir
+// light fade out from inner_angle to outer_angle (half angle of the cone, in radian)
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SpotLight {
 	pos: Point3<f32>,
-	norm: Unit<Vector3<f32>>,
-	scene: &Scene) -> Color3 {
-		
-		const SQRT_RAY_COUNT: u32 = 2 * AREALIGHT_FINITEDIFF_LENGTH + 1;
-		const FD_LENGTH: i32 = AREALIGHT_FINITEDIFF_LENGTH as i32;
-		const FD_FLOAT: f32 = FD_LENGTH as f32;
-		
-		(-FD_LENGTH..=FD_LENGTH)
-				.map(|x| (x as f32)/FD_FLOAT)
-				.cartesian_product((-FD_LENGTH..=FD_LENGTH).map(|x| (x as f32)/FD_FLOAT))
-				.map( |(i, j)| {
-					let transformed_point = self_trans * Point3::new(0.0, i, j);
-					PointLight::_light_at(transformed_point, self_light, pos, norm, scene)
-				}).sum::<Color3>() / (SQRT_RAY_COUNT * SQRT_RAY_COUNT) as f32
+	dir: Unit<Vector3<f32>>,
+	light: Color3,
+	inner_angle: f32,
+	outer_angle: f32,
+}
+
+impl SpotLight {
+	pub fn new(pos: Point3<f32>, dir: Unit<Vector3<f32>>, light: Color3, inner_angle: f32, outer_angle: f32) -> Self {
+		SpotLight {pos, dir, light, inner_angle, outer_angle}
+	}
+
+	// smoothstep between cosine of outer and inner angle
+	fn _falloff(&self, dir_to_obj: &Vector3<f32>) -> f32 {
+		let (cos_inner, cos_outer) = (self.inner_angle.cos(), self.outer_angle.cos());
+		let cos = dir_to_obj.dot(self.dir.as_ref());
+		if cos_inner <= cos_outer {
+			return if cos >= cos_outer { 1.0 } else { 0.0 };
+		}
+		let t = ((cos - cos_outer) / (cos_inner - cos_outer)).clamp(0.0, 1.0);
+		t * t * (3.0 - 2.0 * t)
 	}
 }
 
-impl Light for AreaLight {
-	fn direct_light_at(&self, pos: Point3<f32>, norm: Unit<Vector3<f32>>, scene: &Scene) -> Color3 {
-		Self::_light_at_finite_diff(self.transformer, self.light, pos, norm, scene)
+impl Light for SpotLight {
+	fn direct_light_at(&self, pos: Point3<f32>, norm: Unit<Vector3<f32>>, scene: &Scene, time: f32, _sampler: &mut impl Sampler) -> Color3 {
+		let falloff = self._falloff(&(pos - self.pos).normalize());
+		if falloff <= 0.0 {
+			return Color3::zeros();
+		}
+		PointLight::_light_at(self.pos, self.light, pos, norm, scene, time) * falloff
 	}
-}
\ No newline at end of file
+
+	fn sample(&self, pos: Point3<f32>, _u: (f32, f32)) -> LightSample {
+		let (dir, dist) = Unit::new_and_get(self.pos - pos);
+		let falloff = self._falloff(&-dir.into_inner());
+		LightSample::new(dir, dist, self.light * (falloff / (dist * dist)), None)
+	}
+}
@@ -0,0 +1,478 @@
+/*
+stochastic light selection
+
+shading point used to sample every light, cost grow with light count (area light alone is 49 shadow ray).
+instead pick one light with some probability and divide its contribution by that probability, still unbiased
+
+uniform: every light equally likely
+power: proportional to emitted power, alias table so picking is O(1)
+bvh: tree over light bounds, descend toward child that is likely brighter at the shading point
+	(importance of PBRT-v4, bound of position, power and cone of emission)
+*/
+use std::str::FromStr;
+
+use nalgebra::{Point3, Unit, UnitQuaternion, Vector3};
+use serde::{Deserialize, Serialize};
+
+use super::light::Lights;
+use super::sampler::Sampler;
+use super::{Color3, Scene};
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum LightSelection {
+	// sample every light at every shading point
+	All,
+	Uniform,
+	Power,
+	Bvh,
+}
+
+impl FromStr for LightSelection {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"all" => Ok(LightSelection::All),
+			"uniform" => Ok(LightSelection::Uniform),
+			"power" => Ok(LightSelection::Power),
+			"bvh" => Ok(LightSelection::Bvh),
+			_ => Err(format!("unknown light selection '{}', expected one of: all, uniform, power, bvh", s)),
+		}
+	}
+}
+
+pub fn luminance(color: &Color3) -> f32 {
+	0.2126 * color.x + 0.7152 * color.y + 0.0722 * color.z
+}
+
+// selection built for one scene, picking is done per shading point
+pub enum LightSelector {
+	All,
+	Uniform(usize),
+	Power(AliasTable),
+	Bvh(LightBvh),
+}
+
+// used by RayCastInfo that isn't given a selector
+pub static EVERY_LIGHT: LightSelector = LightSelector::All;
+
+impl LightSelector {
+	pub fn build(scene: &Scene, selection: LightSelection) -> Self {
+		match selection {
+			LightSelection::All => LightSelector::All,
+			LightSelection::Uniform => LightSelector::Uniform(scene.light_count()),
+			LightSelection::Power => {
+				let radius = _scene_radius(scene);
+				let power: Vec<f32> = scene.iter_light().map(|light| luminance(&light.power(radius))).collect();
+				LightSelector::Power(AliasTable::new(&power))
+			},
+			LightSelection::Bvh => LightSelector::Bvh(LightBvh::new(scene)),
+		}
+	}
+
+	// index of picked light and probability it was picked, None when no light can contribute
+	// normal is None for point that isn't on a one sided surface
+	pub fn pick(&self, pos: Point3<f32>, normal: Option<Unit<Vector3<f32>>>, u: f32) -> Option<(usize, f32)> {
+		match self {
+			LightSelector::All => None,
+			LightSelector::Uniform(0) => None,
+			LightSelector::Uniform(count) => Some((((u * *count as f32) as usize).min(count - 1), 1.0 / *count as f32)),
+			LightSelector::Power(table) => table.sample(u),
+			LightSelector::Bvh(bvh) => bvh.sample(pos, normal, u),
+		}
+	}
+
+	// lights to sample at pos with weight 1 / probability, All give every light at weight 1
+	// sampler is only drawn from when a light is picked, so All render the same as before selection existed
+	pub fn select<'s>(&self, scene: &'s Scene, pos: Point3<f32>, normal: Option<Unit<Vector3<f32>>>,
+					  sampler: &mut impl Sampler) -> Selected<'s> {
+		match self {
+			LightSelector::All => Selected::Every(scene.iter_light()),
+			_ => Selected::One(
+				self.pick(pos, normal, sampler.get_1d())
+					.and_then(|(index, prob)| scene.light(index).map(|light| (light, 1.0 / prob)))
+			),
+		}
+	}
+
+	// light picked one at a time take one sample instead of many, noise is averaged over pixel sample
+	pub fn is_all(&self) -> bool {
+		matches!(self, LightSelector::All)
+	}
+}
+
+pub enum Selected<'s> {
+	Every(std::slice::Iter<'s, Lights>),
+	One(Option<(&'s Lights, f32)>),
+}
+
+impl<'s> Iterator for Selected<'s> {
+	type Item = (&'s Lights, f32);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		match self {
+			Selected::Every(iter) => iter.next().map(|light| (light, 1.0)),
+			Selected::One(picked) => picked.take(),
+		}
+	}
+}
+
+// radius used to turn directional light irradiance into power, bounding sphere of every finite light
+fn _scene_radius(scene: &Scene) -> f32 {
+	let bounds = scene.iter_light()
+		.filter_map(|light| light.bounds())
+		.map(|bounds| (bounds.min, bounds.max))
+		.reduce(|a, b| (_component_min(&a.0, &b.0), _component_max(&a.1, &b.1)));
+	match bounds {
+		Some((min, max)) if max != min => (max - min).norm() / 2.0,
+		_ => 1.0,
+	}
+}
+
+// nalgebra's inf/sup is broken in this version, compare by hand
+fn _component_min(a: &Point3<f32>, b: &Point3<f32>) -> Point3<f32> {
+	Point3::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z))
+}
+
+fn _component_max(a: &Point3<f32>, b: &Point3<f32>) -> Point3<f32> {
+	Point3::new(a.x.max(b.x), a.y.max(b.y), a.z.max(b.z))
+}
+
+// Vose's alias method, each bin keep its own index with probability `prob`, the alias otherwise
+pub struct AliasTable {
+	bins: Vec<(f32, usize)>,
+	pmf: Vec<f32>,
+}
+
+impl AliasTable {
+	pub fn new(weights: &[f32]) -> Self {
+		let total: f32 = weights.iter().map(|w| w.max(0.0)).sum();
+		if total <= 0.0 {
+			return AliasTable {bins: Vec::new(), pmf: vec![0.0; weights.len()]};
+		}
+		let n = weights.len();
+		let pmf: Vec<f32> = weights.iter().map(|w| w.max(0.0) / total).collect();
+		let mut scaled: Vec<f32> = pmf.iter().map(|p| p * n as f32).collect();
+		let mut bins: Vec<(f32, usize)> = (0..n).map(|i| (1.0, i)).collect();
+
+		let (mut small, mut large): (Vec<usize>, Vec<usize>) = (0..n).partition(|&i| scaled[i] < 1.0);
+		while let (Some(&s), Some(&l)) = (small.last(), large.last()) {
+			small.pop();
+			large.pop();
+			bins[s] = (scaled[s], l);
+			scaled[l] -= 1.0 - scaled[s];
+			if scaled[l] < 1.0 { small.push(l) } else { large.push(l) }
+		}
+		// leftover are 1 up to rounding error
+		for i in small.into_iter().chain(large) {
+			bins[i] = (1.0, i);
+		}
+		AliasTable {bins, pmf}
+	}
+
+	pub fn sample(&self, u: f32) -> Option<(usize, f32)> {
+		if self.bins.is_empty() {
+			return None;
+		}
+		let x = u * self.bins.len() as f32;
+		let bin = (x as usize).min(self.bins.len() - 1);
+		let (prob, alias) = self.bins[bin];
+		let index = if x - (bin as f32) < prob { bin } else { alias };
+		Some((index, self.pmf[index]))
+	}
+
+	pub fn pmf(&self, index: usize) -> f32 {
+		self.pmf[index]
+	}
+}
+
+// bound of where a light is and where it shine, light emit within theta_o + theta_e around w
+#[derive(Clone, Debug)]
+pub struct LightBounds {
+	pub min: Point3<f32>,
+	pub max: Point3<f32>,
+	// luminance of intensity toward the brightest direction
+	pub phi: f32,
+	pub w: Unit<Vector3<f32>>,
+	// cosine of spread of the emitting normal
+	pub cos_theta_o: f32,
+	// cosine of how far from normal light still leave
+	pub cos_theta_e: f32,
+	pub two_sided: bool,
+}
+
+impl LightBounds {
+	fn _centroid(&self) -> Point3<f32> {
+		Point3::from((self.min.coords + self.max.coords) / 2.0)
+	}
+
+	fn _union(&self, other: &LightBounds) -> LightBounds {
+		if self.phi <= 0.0 {
+			return other.clone();
+		}
+		if other.phi <= 0.0 {
+			return self.clone();
+		}
+		let (w, cos_theta_o) = _cone_union((self.w, self.cos_theta_o), (other.w, other.cos_theta_o));
+		LightBounds {
+			min: _component_min(&self.min, &other.min),
+			max: _component_max(&self.max, &other.max),
+			phi: self.phi + other.phi,
+			w,
+			cos_theta_o,
+			cos_theta_e: self.cos_theta_e.min(other.cos_theta_e),
+			two_sided: self.two_sided || other.two_sided,
+		}
+	}
+
+	// conservative estimate of light reaching pos from anything inside the bound
+	fn _importance(&self, pos: Point3<f32>, normal: Option<Unit<Vector3<f32>>>) -> f32 {
+		let centroid = self._centroid();
+		let half_diagonal = (self.max - self.min).norm() / 2.0;
+		// don't let distance go to zero when pos is inside the bound
+		let dist_sq = (pos - centroid).norm_squared().max(half_diagonal * half_diagonal);
+		let (wi, dist) = Unit::new_and_get(pos - centroid);
+		if dist <= 0.0 {
+			return self.phi / dist_sq;
+		}
+
+		// angle between w and direction to pos, minus spread and what the bound subtend at pos
+		let mut cos_theta_w = self.w.dot(&wi);
+		if self.two_sided {
+			cos_theta_w = cos_theta_w.abs();
+		}
+		let sin_theta_w = _sin_from_cos(cos_theta_w);
+		let (cos_theta_b, sin_theta_b) = if dist <= half_diagonal {
+			(-1.0, 0.0)
+		} else {
+			let sin = half_diagonal / dist;
+			(_sin_from_cos(sin), sin)
+		};
+
+		let sin_theta_o = _sin_from_cos(self.cos_theta_o);
+		let cos_theta_x = _cos_sub_clamped(sin_theta_w, cos_theta_w, sin_theta_o, self.cos_theta_o);
+		let sin_theta_x = _sin_sub_clamped(sin_theta_w, cos_theta_w, sin_theta_o, self.cos_theta_o);
+		let cos_theta_p = _cos_sub_clamped(sin_theta_x, cos_theta_x, sin_theta_b, cos_theta_b);
+		// strict so spot without falloff (theta_e = 0) still light inside its cone
+		if cos_theta_p < self.cos_theta_e {
+			return 0.0;
+		}
+		let mut importance = self.phi * cos_theta_p / dist_sq;
+
+		// surface at pos only receive from the hemisphere above it
+		if let Some(normal) = normal {
+			let cos_theta_i = normal.dot(&-wi.into_inner()).abs();
+			let sin_theta_i = _sin_from_cos(cos_theta_i);
+			importance *= _cos_sub_clamped(sin_theta_i, cos_theta_i, sin_theta_b, cos_theta_b);
+		}
+		importance.max(0.0)
+	}
+}
+
+fn _sin_from_cos(cos: f32) -> f32 {
+	(1.0 - cos * cos).max(0.0).sqrt()
+}
+
+// cos(max(0, a - b)) from sine and cosine of both
+fn _cos_sub_clamped(sin_a: f32, cos_a: f32, sin_b: f32, cos_b: f32) -> f32 {
+	if cos_a > cos_b { 1.0 } else { cos_a * cos_b + sin_a * sin_b }
+}
+
+// sin(max(0, a - b))
+fn _sin_sub_clamped(sin_a: f32, cos_a: f32, sin_b: f32, cos_b: f32) -> f32 {
+	if cos_a > cos_b { 0.0 } else { sin_a * cos_b - cos_a * sin_b }
+}
+
+// smallest cone containing both cone (axis, cosine of half angle)
+fn _cone_union(a: (Unit<Vector3<f32>>, f32), b: (Unit<Vector3<f32>>, f32)) -> (Unit<Vector3<f32>>, f32) {
+	let (theta_a, theta_b) = (a.1.clamp(-1.0, 1.0).acos(), b.1.clamp(-1.0, 1.0).acos());
+	let theta_d = a.0.dot(&b.0).clamp(-1.0, 1.0).acos();
+	if (theta_d + theta_b).min(std::f32::consts::PI) <= theta_a {
+		return a;
+	}
+	if (theta_d + theta_a).min(std::f32::consts::PI) <= theta_b {
+		return b;
+	}
+	let theta_o = (theta_a + theta_d + theta_b) / 2.0;
+	if theta_o >= std::f32::consts::PI {
+		return (a.0, -1.0);
+	}
+	// rotate a's axis toward b's
+	let theta_r = theta_o - theta_a;
+	match Unit::try_new(a.0.cross(&b.0), 1e-6) {
+		Some(axis) => (UnitQuaternion::from_axis_angle(&axis, theta_r) * a.0, theta_o.cos()),
+		None => (a.0, -1.0),
+	}
+}
+
+enum BvhNode {
+	Leaf {light: usize, bounds: LightBounds},
+	Interior {children: [usize; 2], bounds: LightBounds},
+}
+
+impl BvhNode {
+	fn bounds(&self) -> &LightBounds {
+		match self {
+			BvhNode::Leaf {bounds, ..} | BvhNode::Interior {bounds, ..} => bounds,
+		}
+	}
+}
+
+// light with position go into the tree, directional light (no bound) are picked uniformly beside it
+pub struct LightBvh {
+	nodes: Vec<BvhNode>,
+	infinite: Vec<usize>,
+}
+
+impl LightBvh {
+	pub fn new(scene: &Scene) -> Self {
+		let mut infinite = Vec::new();
+		let mut bounded = Vec::new();
+		for (index, light) in scene.iter_light().enumerate() {
+			match light.bounds() {
+				None => infinite.push(index),
+				// black light never contribute, it doesn't need to be picked
+				Some(bounds) if bounds.phi > 0.0 => bounded.push((index, bounds)),
+				Some(_) => {},
+			}
+		}
+		let mut bvh = LightBvh {nodes: Vec::new(), infinite};
+		if !bounded.is_empty() {
+			bvh._build(bounded);
+		}
+		bvh
+	}
+
+	// median split along largest axis of centroid, return index of the node
+	fn _build(&mut self, mut lights: Vec<(usize, LightBounds)>) -> usize {
+		if lights.len() == 1 {
+			let (light, bounds) = lights.pop().unwrap();
+			self.nodes.push(BvhNode::Leaf {light, bounds});
+			return self.nodes.len() - 1;
+		}
+		let centroids: Vec<Point3<f32>> = lights.iter().map(|(_, b)| b._centroid()).collect();
+		let (min, max) = centroids.iter()
+			.fold((centroids[0], centroids[0]), |(min, max), c| (_component_min(&min, c), _component_max(&max, c)));
+		let extent = max - min;
+		let axis = if extent.x >= extent.y && extent.x >= extent.z { 0 } else if extent.y >= extent.z { 1 } else { 2 };
+		lights.sort_by(|a, b| a.1._centroid()[axis].partial_cmp(&b.1._centroid()[axis]).unwrap_or(std::cmp::Ordering::Equal));
+
+		let right = lights.split_off(lights.len() / 2);
+		let bounds = lights.iter().chain(right.iter())
+			.map(|(_, b)| b.clone())
+			.reduce(|a, b| a._union(&b))
+			.unwrap();
+		// reserve this node before children so root is always at 0
+		self.nodes.push(BvhNode::Leaf {light: 0, bounds: bounds.clone()});
+		let index = self.nodes.len() - 1;
+		let children = [self._build(lights), self._build(right)];
+		self.nodes[index] = BvhNode::Interior {children, bounds};
+		index
+	}
+
+	pub fn sample(&self, pos: Point3<f32>, normal: Option<Unit<Vector3<f32>>>, mut u: f32) -> Option<(usize, f32)> {
+		let bounded = if self.nodes.is_empty() { 0 } else { 1 };
+		let infinite_count = self.infinite.len();
+		if infinite_count + bounded == 0 {
+			return None;
+		}
+		// every directional light count as much as the whole tree
+		let p_infinite = infinite_count as f32 / (infinite_count + bounded) as f32;
+		if u < p_infinite {
+			let index = ((u / p_infinite * infinite_count as f32) as usize).min(infinite_count - 1);
+			return Some((self.infinite[index], p_infinite / infinite_count as f32));
+		}
+		u = ((u - p_infinite) / (1.0 - p_infinite)).min(1.0 - f32::EPSILON);
+
+		let mut pmf = 1.0 - p_infinite;
+		let mut node = 0;
+		loop {
+			match &self.nodes[node] {
+				BvhNode::Leaf {light, bounds} => {
+					return if node == 0 && bounds._importance(pos, normal) <= 0.0 { None } else { Some((*light, pmf)) };
+				},
+				BvhNode::Interior {children, ..} => {
+					let importance = [
+						self.nodes[children[0]].bounds()._importance(pos, normal),
+						self.nodes[children[1]].bounds()._importance(pos, normal),
+					];
+					if importance[0] <= 0.0 && importance[1] <= 0.0 {
+						return None;
+					}
+					let p_left = importance[0] / (importance[0] + importance[1]);
+					if u < p_left {
+						u = (u / p_left).min(1.0 - f32::EPSILON);
+						pmf *= p_left;
+						node = children[0];
+					} else {
+						u = ((u - p_left) / (1.0 - p_left)).min(1.0 - f32::EPSILON);
+						pmf *= 1.0 - p_left;
+						node = children[1];
+					}
+				},
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use nalgebra::Vector3;
+
+	use crate::rtracer::SceneObject;
+	use crate::rtracer::geometric::InfinitePlane;
+	use crate::rtracer::light::{AreaLight, DirectionalLight, Light, PointLight, SpotLight};
+	use crate::rtracer::material::Diffuse;
+	use crate::rtracer::sampler::SamplerKind;
+
+	use super::*;
+
+	#[test]
+	fn alias_table_test() {
+		let weights = [1.0, 0.0, 3.0, 4.0];
+		let table = AliasTable::new(&weights);
+		let count = 8000;
+		let mut hits = [0; 4];
+		for i in 0..count {
+			let (index, pmf) = table.sample((i as f32 + 0.5) / count as f32).unwrap();
+			assert_eq!(pmf, table.pmf(index));
+			hits[index] += 1;
+		}
+		for (i, &w) in weights.iter().enumerate() {
+			assert!((hits[i] as f32 / count as f32 - w / 8.0).abs() < 1e-3, "{:?}", hits);
+		}
+		assert!(AliasTable::new(&[0.0, 0.0]).sample(0.5).is_none());
+	}
+
+	// picking one light and dividing by its probability average to the sum over every light
+	#[test]
+	fn selection_unbiased_test() {
+		let mut scene = Scene::new();
+		scene.add_obj(SceneObject::new(
+			InfinitePlane {pos: Point3::origin(), norm: Vector3::z_axis()},
+			Diffuse::new(Color3::repeat(1.0))
+		));
+		scene.add_light(PointLight::new(Point3::new(0.0, 0.0, 1.0), Color3::repeat(1.0)).into());
+		scene.add_light(PointLight::new(Point3::new(5.0, 1.0, 2.0), Color3::repeat(8.0)).into());
+		scene.add_light(SpotLight::new(Point3::new(-2.0, 0.0, 3.0), -Vector3::z_axis(), Color3::repeat(4.0), 0.3, 0.6).into());
+		scene.add_light(DirectionalLight::new(Unit::new_normalize(Vector3::new(0.3, 0.0, -1.0)), Color3::repeat(0.5)).into());
+		// under the plane, contribute nothing however it's picked
+		let rotation = UnitQuaternion::from_axis_angle(&Vector3::y_axis(), std::f32::consts::FRAC_PI_2);
+		scene.add_light(AreaLight::new(Vector3::new(1.0, 3.0, -1.0), rotation, 0.5, None).into());
+
+		let (pos, normal) = (Point3::new(0.5, 0.3, 0.0), Vector3::z_axis());
+		let mut sampler = SamplerKind::Independent.build(1, 1);
+		let direct = |light: &Lights, sampler: &mut _| light.direct_light_at(pos, normal, &scene, 0.0, sampler);
+		let reference: Color3 = scene.iter_light().map(|light| direct(light, &mut sampler)).sum();
+
+		for &selection in &[LightSelection::Uniform, LightSelection::Power, LightSelection::Bvh] {
+			let selector = LightSelector::build(&scene, selection);
+			let count = 4000;
+			let estimate = (0..count).filter_map(|i| {
+				let (index, prob) = selector.pick(pos, Some(normal), (i as f32 + 0.5) / count as f32)?;
+				Some(direct(scene.light(index).unwrap(), &mut sampler) / prob)
+			}).sum::<Color3>() / count as f32;
+			assert!((estimate.x / reference.x - 1.0).abs() < 0.01, "{:?}: {} vs {}", selection, estimate.x, reference.x);
+		}
+	}
+}
@@ -1,15 +1,20 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
 use itertools::Itertools;
 use nalgebra::{Reflection, Unit, UnitQuaternion, Vector3};
 use noise::NoiseFn;
-use rand::{Rng, thread_rng};
-use rand::prelude::ThreadRng;
-use rand_distr::{Distribution, UnitBall};
 use serde::{Deserialize, Serialize};
 
 use enum_dispatch::enum_dispatch;
 
-use crate::rtracer::{Color3, helper, HitInfo, light::Light, RayCastInfo, REFLECTION_DEPTH_LIMIT, Scene, SceneObject};
+use crate::rtracer::{AREALIGHT_MONTECARLO_SAMPLE, Color3, helper, HitInfo, light::Light, RayCastInfo, REFLECTION_DEPTH_LIMIT, Scene, SceneObject};
+use crate::rtracer::mis::{self, Scatter};
 use crate::rtracer::renderer::raycast_compute_light;
+use crate::rtracer::sampler::Sampler;
+use crate::rtracer::serde_interface::{present, write_present};
+use crate::rtracer::texture::Texture;
+use crate::rtracer::validation::{field, Report, Validate};
 
 #[enum_dispatch]
 pub trait Material {
@@ -19,42 +24,310 @@ pub trait Material {
         hit_info: &HitInfo,
         hit_object: &SceneObject,
         raycase_info: RayCastInfo,
-        rng: &mut impl Rng)
-        -> Color3;
+        sampler: &mut impl Sampler)
+        -> Color3 {
+        self.compute_light_split(scene, hit_info, hit_object, raycase_info, sampler).total()
+    }
+
+    // same light as compute_light, split by lobe for AOV
+    fn compute_light_split(
+        &self,
+        scene: &Scene,
+        hit_info: &HitInfo,
+        hit_object: &SceneObject,
+        raycase_info: RayCastInfo,
+        sampler: &mut impl Sampler)
+        -> LightSplit;
+
+    // color of surface under white light, for albedo AOV
+    fn albedo(&self, hit_info: &HitInfo) -> Color3;
+}
+
+// light leaving hit point, direct came straight from light, indirect from another surface (or sky)
+#[derive(Clone, Copy, Debug)]
+pub struct LightSplit {
+    pub direct_diffuse: Color3,
+    pub indirect_diffuse: Color3,
+    pub direct_specular: Color3,
+    pub indirect_specular: Color3,
+}
+
+impl LightSplit {
+    pub fn zeros() -> Self {
+        LightSplit {
+            direct_diffuse: Color3::zeros(),
+            indirect_diffuse: Color3::zeros(),
+            direct_specular: Color3::zeros(),
+            indirect_specular: Color3::zeros(),
+        }
+    }
+
+    pub fn total(&self) -> Color3 {
+        self.direct_diffuse + self.indirect_diffuse + self.direct_specular + self.indirect_specular
+    }
 }
 
 #[enum_dispatch(Material)]
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub enum Materials {
     Diffuse,
     Reflective,
     PerfectReflective,
 }
 
-#[derive(Serialize, Deserialize)]
+impl Materials {
+    pub fn color(&self) -> Option<Color3> {
+        match self {
+            Materials::Diffuse(m) => Some(m.color),
+            Materials::PerfectReflective(m) => Some(m.color),
+            Materials::Reflective(_) => None,
+        }
+    }
+
+    pub fn roughness(&self) -> Option<f32> {
+        match self {
+            Materials::Reflective(m) => Some(m.roughness),
+            _ => None,
+        }
+    }
+
+    // animatable parameter, None if material doesn't have one
+    pub fn color_mut(&mut self) -> Option<&mut Color3> {
+        match self {
+            Materials::Diffuse(m) => Some(&mut m.color),
+            Materials::PerfectReflective(m) => Some(&mut m.color),
+            Materials::Reflective(_) => None,
+        }
+    }
+
+    pub fn roughness_mut(&mut self) -> Option<&mut f32> {
+        match self {
+            Materials::Reflective(m) => Some(&mut m.roughness),
+            _ => None,
+        }
+    }
+
+    pub fn texture(&self) -> Option<&Texture> {
+        match self {
+            Materials::Diffuse(m) => m.texture.as_ref(),
+            _ => None,
+        }
+    }
+
+    pub fn texture_mut(&mut self) -> Option<&mut Texture> {
+        match self {
+            Materials::Diffuse(m) => m.texture.as_mut(),
+            _ => None,
+        }
+    }
+}
+
+impl Validate for Materials {
+    fn validate(&self, path: &str, report: &mut Report) {
+        match self {
+            Materials::Diffuse(m) => {
+                report.check_albedo(&field(path, "color"), &m.color);
+                if let Some(texture) = m.texture.as_ref().filter(|t| !t.is_loaded()) {
+                    report.error(&field(path, "texture"), format!("texture '{}' is not loaded", texture.path()));
+                }
+            },
+            Materials::PerfectReflective(m) => report.check_albedo(&field(path, "color"), &m.color),
+            Materials::Reflective(m) => {
+                let roughness_path = field(path, "roughness");
+                report.check_finite(&roughness_path, m.roughness);
+                if m.roughness < 0.0 {
+                    report.error(&roughness_path, format!("must not be negative, got {}", m.roughness));
+                }
+                if m.iteration == 0 {
+                    report.error(&field(path, "iteration"), "must be at least 1");
+                }
+            },
+        }
+    }
+}
+
+// material of scene object, either written inline or referenced by name
+// from material library of SceneData, so many object can share one material
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(from = "MaterialSerdeInterface", into = "MaterialSerdeInterface")]
+pub enum MaterialRef {
+    // material is None until resolved against material library
+    Named(String, Option<Arc<Materials>>),
+    Inline(Arc<Materials>),
+}
+
+impl MaterialRef {
+    pub fn named(name: impl Into<String>) -> Self {
+        MaterialRef::Named(name.into(), None)
+    }
+
+    // None if material is named but not resolved yet
+    pub fn get(&self) -> Option<&Materials> {
+        match self {
+            MaterialRef::Named(_, material) => material.as_ref().map(|m| m.as_ref()),
+            MaterialRef::Inline(material) => Some(material.as_ref()),
+        }
+    }
+
+    // copy on write, so changing one object doesn't change every object sharing material
+    pub fn make_mut(&mut self) -> Option<&mut Materials> {
+        match self {
+            MaterialRef::Named(_, material) => material.as_mut().map(Arc::make_mut),
+            MaterialRef::Inline(material) => Some(Arc::make_mut(material)),
+        }
+    }
+
+    // look up named material in library if it isn't resolved yet,
+    // return false if it's still unresolved
+    pub fn resolve(&mut self, library: &BTreeMap<String, Arc<Materials>>) -> bool {
+        match self {
+            MaterialRef::Named(name, material) => {
+                if material.is_none() {
+                    *material = library.get(name).cloned();
+                }
+                material.is_some()
+            },
+            MaterialRef::Inline(_) => true,
+        }
+    }
+
+    // use material from library even if it's already resolved
+    pub fn override_with(&mut self, library: &BTreeMap<String, Arc<Materials>>) {
+        if let MaterialRef::Named(name, material) = self {
+            if let Some(m) = library.get(name) {
+                *material = Some(m.clone());
+            }
+        }
+    }
+}
+
+impl<T: Into<Materials>> From<T> for MaterialRef {
+    fn from(material: T) -> Self {
+        MaterialRef::Inline(Arc::new(material.into()))
+    }
+}
+
+// inline material keep their old syntax like Diffuse((color: ...)), reference is Named("name")
+#[derive(Serialize, Deserialize, Clone)]
+pub enum MaterialSerdeInterface {
+    Named(String),
+    Diffuse(Diffuse),
+    Reflective(Reflective),
+    PerfectReflective(PerfectReflective),
+}
+
+impl From<MaterialSerdeInterface> for MaterialRef {
+    fn from(inter: MaterialSerdeInterface) -> Self {
+        match inter {
+            MaterialSerdeInterface::Named(name) => MaterialRef::named(name),
+            MaterialSerdeInterface::Diffuse(m) => m.into(),
+            MaterialSerdeInterface::Reflective(m) => m.into(),
+            MaterialSerdeInterface::PerfectReflective(m) => m.into(),
+        }
+    }
+}
+
+impl From<MaterialRef> for MaterialSerdeInterface {
+    fn from(material: MaterialRef) -> Self {
+        match material {
+            MaterialRef::Named(name, _) => MaterialSerdeInterface::Named(name),
+            MaterialRef::Inline(m) => match m.as_ref().clone() {
+                Materials::Diffuse(m) => MaterialSerdeInterface::Diffuse(m),
+                Materials::Reflective(m) => MaterialSerdeInterface::Reflective(m),
+                Materials::PerfectReflective(m) => MaterialSerdeInterface::PerfectReflective(m),
+            },
+        }
+    }
+}
+
+impl Validate for MaterialRef {
+    fn validate(&self, path: &str, report: &mut Report) {
+        match self {
+            MaterialRef::Named(name, None) =>
+                report.error(path, format!("there is no material named '{}' in materials", name)),
+            // shared material is validated once in material library
+            MaterialRef::Named(_, Some(_)) => (),
+            MaterialRef::Inline(m) => m.validate(path, report),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Diffuse {
     color: Color3,
+    // multiplied with color where hit has uv
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[serde(deserialize_with = "present", serialize_with = "write_present")]
+    texture: Option<Texture>,
 }
 
 impl Diffuse {
     pub fn new(color: Color3) -> Self {
-        Diffuse {color}
+        Diffuse {color, texture: None}
+    }
+
+    pub fn with_texture(color: Color3, texture: Texture) -> Self {
+        Diffuse {color, texture: Some(texture)}
     }
 }
 
 impl Material for Diffuse {
-    fn compute_light(&self, scene: &Scene, hit_info: &HitInfo,
-                     hit_object: &SceneObject, _: RayCastInfo, rng: &mut impl Rng)
-        -> Color3 {
-        scene.iter_light()
-            .map(|x| x.direct_light_at(hit_info.intersection, hit_info.normal, scene))
+    // whitted only trace light on diffuse surface, so there's no indirect diffuse
+    fn compute_light_split(&self, scene: &Scene, hit_info: &HitInfo,
+                           hit_object: &SceneObject, raycast_info: RayCastInfo, sampler: &mut impl Sampler)
+        -> LightSplit {
+        let color = self.albedo(hit_info);
+        let scatter = DiffuseScatter {normal: hit_info.normal};
+        // light picked one at a time take a single sample, pixel sample average the noise
+        let lights = raycast_info.lights();
+        let area_sample = if lights.is_all() { AREALIGHT_MONTECARLO_SAMPLE } else { 1 };
+        let direct = lights.select(scene, hit_info.intersection, Some(hit_info.normal), sampler)
+            .map(|(light, weight)| weight * if light.is_delta() {
+                light.direct_light_at(hit_info.intersection, hit_info.normal, scene, raycast_info.time(), sampler)
+            } else {
+                (0..area_sample)
+                    .map(|_| mis::direct_light(scene, light, hit_info.intersection, &scatter, raycast_info, sampler))
+                    .sum::<Color3>() / area_sample as f32
+            })
             .sum::<Color3>()
-            .component_mul(&self.color)  // factor in material's color
+            .component_mul(&color);  // factor in material's color
+        LightSplit {direct_diffuse: direct, ..LightSplit::zeros()}
+    }
+
+    fn albedo(&self, hit_info: &HitInfo) -> Color3 {
+        let mut color = match (&self.texture, hit_info.uv) {
+            (Some(texture), Some(uv)) => self.color.component_mul(&texture.sample(uv)),
+            _ => self.color,
+        };
+        if let Some(vertex_color) = hit_info.vertex_color {
+            color.component_mul_assign(&vertex_color);
+        }
+        color
+    }
+}
+
+// lambertian lobe of Diffuse, color is applied after summing light
+// light is scaled the same way as direct_light_at (color * irradiance), so bsdf * cos is just cos
+struct DiffuseScatter {
+    normal: Unit<Vector3<f32>>,
+}
+
+impl Scatter for DiffuseScatter {
+    fn eval(&self, dir: &Unit<Vector3<f32>>) -> Color3 {
+        Color3::repeat(self.normal.dot(dir).max(0.0))
+    }
+
+    fn pdf(&self, dir: &Unit<Vector3<f32>>) -> f32 {
+        self.normal.dot(dir).max(0.0) / std::f32::consts::PI
+    }
+
+    fn sample(&self, sampler: &mut impl Sampler) -> Unit<Vector3<f32>> {
+        helper::sample_cosine_hemisphere(sampler.get_2d(), &self.normal)
     }
 }
 
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Reflective {
     roughness: f32,
     iteration: usize,
@@ -104,31 +377,40 @@ impl Reflective {
 
     fn _compute_light_unbiased(&self, scene: &Scene, hit_info: &HitInfo,
                                hit_object: &SceneObject, raycast_info: RayCastInfo,
-                               rng: &mut impl Rng) -> Color3
+                               sampler: &mut impl Sampler) -> LightSplit
     {
         use crate::rtracer::renderer::raycast_compute_light;
-        use rand_distr::UnitBall;
 
-        (0..self.iteration)
+        let scatter = GlossyScatter {
+            reflect: helper::calculate_reflect_ray(&hit_info.incoming_dir, &hit_info.normal),
+            roughness: self.roughness,
+        };
+
+        let (indirect, direct) = (0..self.iteration)
             .map(|_| {
-                let reflect_noise =
-                    self.roughness * Vector3::from(UnitBall.sample(rng));
-                let reflect_dir =
-                    Unit::new_normalize(
-                    helper::calculate_reflect_ray(
-                        &hit_info.incoming_dir,
-                        &hit_info.normal).into_inner()
-                        + reflect_noise
-                    );
-                raycast_compute_light(
+                let reflect_dir = scatter.sample(sampler);
+                let reflection_light = raycast_compute_light(
                     scene,
                     hit_info.intersection.clone(),
                     reflect_dir,
                     raycast_info,
-                    rng
-                )
+                    sampler
+                );
+
+                // raycast doesn't hit light, so light is added here without counting it twice
+                // mirror (roughness 0) has no lobe to weight against, it doesn't see light like PerfectReflective
+                if self.roughness <= 0.0 {
+                    return (reflection_light, Color3::zeros());
+                }
+                let direct_light = raycast_info.lights()
+                    .select(scene, hit_info.intersection, None, sampler)
+                    .map(|(light, weight)| weight * mis::direct_light(scene, light, hit_info.intersection, &scatter, raycast_info, sampler))
+                    .sum::<Color3>();
+                (reflection_light, direct_light)
             })
-            .sum::<Color3>() / self.iteration as f32
+            .fold((Color3::zeros(), Color3::zeros()), |(a, b), (c, d)| (a + c, b + d));
+        let n = self.iteration as f32;
+        LightSplit {direct_specular: direct / n, indirect_specular: indirect / n, ..LightSplit::zeros()}
     }
 
     // TODO: this can be potentially faster than monte carlo, but I can't figure out implementation as of now
@@ -136,7 +418,7 @@ impl Reflective {
     // reading implementation of UnitBall might be good
     fn _compute_light_finite(&self, scene: &Scene, hit_info: &HitInfo,
                                hit_object: &SceneObject, raycase_info: RayCastInfo,
-                             rng: &mut impl Rng) -> Color3
+                             sampler: &mut impl Sampler) -> Color3
     {
         use crate::rtracer::renderer::raycast_compute_light;
         use rand_distr::UnitBall;
@@ -184,7 +466,7 @@ impl Reflective {
                     hit_info.intersection.clone(),
                     reflect_dir,
                     raycase_info,
-                    rng
+                    sampler
                 )
             })
             .sum::<Color3>() / (self.iteration * self.iteration * self.iteration) as f32
@@ -192,20 +474,60 @@ impl Reflective {
 }
 
 impl Material for Reflective {
-    fn compute_light(&self, scene: &Scene, hit_info: &HitInfo,
-                     hit_object: &SceneObject, raycast_info: RayCastInfo, rng: &mut impl Rng)
-        -> Color3 {
+    fn compute_light_split(&self, scene: &Scene, hit_info: &HitInfo,
+                           hit_object: &SceneObject, raycast_info: RayCastInfo, sampler: &mut impl Sampler)
+        -> LightSplit {
         if raycast_info.ray_depth() > REFLECTION_DEPTH_LIMIT {
-            return scene.get_skylight();
+            return LightSplit {indirect_specular: scene.get_skylight(), ..LightSplit::zeros()};
         }
         else {
-            self._compute_light_unbiased(scene, hit_info, hit_object, raycast_info, rng)
+            self._compute_light_unbiased(scene, hit_info, hit_object, raycast_info, sampler)
+        }
+    }
+
+    // reflect every color, the lobe is white
+    fn albedo(&self, _hit_info: &HitInfo) -> Color3 {
+        Color3::repeat(1.0)
+    }
+}
+
+
+// lobe of Reflective, mirror direction moved by uniform point in ball of radius roughness
+// the lobe is defined by its sampling, so bsdf * cos equal pdf (and white, Reflective has no color)
+struct GlossyScatter {
+    reflect: Unit<Vector3<f32>>,
+    roughness: f32,
+}
+
+impl Scatter for GlossyScatter {
+    fn eval(&self, dir: &Unit<Vector3<f32>>) -> Color3 {
+        Color3::repeat(self.pdf(dir))
+    }
+
+    // integral of t^2 along the part of ray (origin, dir) inside the ball, over volume of the ball
+    fn pdf(&self, dir: &Unit<Vector3<f32>>) -> f32 {
+        let r = self.roughness;
+        let c = dir.dot(&self.reflect);
+        let disc = c * c - 1.0 + r * r;
+        if disc <= 0.0 {
+            return 0.0;
         }
+        let far = c + disc.sqrt();
+        let near = (c - disc.sqrt()).max(0.0);
+        if far <= 0.0 {
+            return 0.0;
+        }
+        (far.powi(3) - near.powi(3)) / (4.0 * std::f32::consts::PI * r.powi(3))
+    }
+
+    fn sample(&self, sampler: &mut impl Sampler) -> Unit<Vector3<f32>> {
+        let noise = self.roughness * helper::sample_unit_ball(sampler.get_2d(), sampler.get_1d());
+        Unit::new_normalize(self.reflect.into_inner() + noise)
     }
 }
 
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct PerfectReflective {
     color: Color3,
 }
@@ -217,13 +539,13 @@ impl PerfectReflective {
 }
 
 impl Material for PerfectReflective {
-    fn compute_light(&self, scene: &Scene, hit_info: &HitInfo,
-                     hit_object: &SceneObject, raycast_info: RayCastInfo, rng: &mut impl Rng)
-        -> Color3 {
+    fn compute_light_split(&self, scene: &Scene, hit_info: &HitInfo,
+                           hit_object: &SceneObject, raycast_info: RayCastInfo, sampler: &mut impl Sampler)
+        -> LightSplit {
         use helper::calculate_reflect_ray;
 
         if raycast_info.ray_depth() > REFLECTION_DEPTH_LIMIT {
-            return scene.get_skylight();
+            return LightSplit {indirect_specular: scene.get_skylight(), ..LightSplit::zeros()};
         }
 
         let reflect_dir =
@@ -238,9 +560,37 @@ impl Material for PerfectReflective {
                 hit_info.intersection.clone(),
                 reflect_dir,
                 raycast_info,
-                rng
+                sampler
             );
 
-         reflection_light.component_mul(&self.color)
+        LightSplit {indirect_specular: reflection_light.component_mul(&self.color), ..LightSplit::zeros()}
+    }
+
+    fn albedo(&self, _hit_info: &HitInfo) -> Color3 {
+        self.color
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // pdf integrate to 1 over the sphere, both when the ball is away from origin and when it contain it
+    #[test]
+    fn glossy_pdf_normalized_test() {
+        let (rings, segments) = (2000, 200);
+        for &roughness in &[0.4, 1.5] {
+            let scatter = GlossyScatter {reflect: Vector3::x_axis(), roughness};
+            let total: f32 = (0..rings)
+                .flat_map(|i| (0..segments).map(move |j| (i, j)))
+                .map(|(i, j)| {
+                    // equal area cell, uniform in z and angle
+                    let z = 1.0 - 2.0 * (i as f32 + 0.5) / rings as f32;
+                    let phi = 2.0 * std::f32::consts::PI * (j as f32 + 0.5) / segments as f32;
+                    let ring = (1.0 - z * z).sqrt();
+                    scatter.pdf(&Unit::new_normalize(Vector3::new(z, ring * phi.cos(), ring * phi.sin())))
+                })
+                .sum::<f32>() * 4.0 * std::f32::consts::PI / (rings * segments) as f32;
+            assert!((total - 1.0).abs() < 1e-2, "roughness {} integrate to {}", roughness, total);
+        }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,360 @@
+use std::cmp::Ordering::Equal;
+use std::sync::Arc;
+
+use nalgebra::{Point3, Unit, Vector2, Vector3};
+use serde::{Deserialize, Serialize};
+
+use super::{Color3, HitInfo, Shape};
+use super::stats;
+use super::validation::{field, index, Report, Validate};
+
+// maximum triangle in a leaf of bvh
+const BVH_LEAF_SIZE: usize = 4;
+
+// vertex and triangle of mesh as written in scene file
+// normals, uvs and colors are per vertex and can be left empty
+#[derive(Serialize, Deserialize, Clone)]
+pub struct MeshData {
+	pub positions: Vec<Point3<f32>>,
+	#[serde(default)]
+	pub normals: Vec<Vector3<f32>>,
+	#[serde(default)]
+	pub uvs: Vec<Vector2<f32>>,
+	// linear albedo multiplied with material color
+	#[serde(default)]
+	pub colors: Vec<Color3>,
+	pub triangles: Vec<[u32; 3]>,
+}
+
+// triangle mesh, hit both side of triangle
+// data is shared so cloning mesh (flattening group, posing animation) is cheap
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(from = "MeshData", into = "MeshData")]
+pub struct Mesh {
+	data: Arc<MeshData>,
+	bvh: Arc<Bvh>,
+}
+
+// bounding volume hierarchy over triangle of mesh, built once when mesh is created
+struct Bvh {
+	nodes: Vec<BvhNode>,
+	// triangle index, leaf refer to a range of it
+	order: Vec<usize>,
+}
+
+// left child is always the next node, leaf has count > 0
+struct BvhNode {
+	min: Point3<f32>,
+	max: Point3<f32>,
+	start: usize,
+	count: usize,
+	right: usize,
+}
+
+impl Mesh {
+	pub fn new(data: MeshData) -> Self {
+		let bvh = Bvh::build(&data);
+		Mesh {data: Arc::new(data), bvh: Arc::new(bvh)}
+	}
+
+	pub fn data(&self) -> &MeshData {
+		&self.data
+	}
+
+	pub fn triangle_count(&self) -> usize {
+		self.data.triangles.len()
+	}
+
+	// Möller–Trumbore, return distance and barycentric coordinate of vertex b and c
+	fn _intersect_triangle(&self, i: usize, origin: &Point3<f32>, dir: &Vector3<f32>) -> Option<(f32, f32, f32)> {
+		let [a, b, c] = self.data.vertices(i);
+		let (ab, ac) = (b - a, c - a);
+		let p = dir.cross(&ac);
+		let det = ab.dot(&p);
+		if det.abs() < 1e-12 {
+			return None;
+		}
+
+		let inv_det = 1.0 / det;
+		let ao = origin - a;
+		let u = ao.dot(&p) * inv_det;
+		if !(0.0..=1.0).contains(&u) {
+			return None;
+		}
+		let q = ao.cross(&ab);
+		let v = dir.dot(&q) * inv_det;
+		if v < 0.0 || u + v > 1.0 {
+			return None;
+		}
+
+		let dist = ac.dot(&q) * inv_det;
+		if dist <= 1e-6 {
+			return None;
+		}
+		Some((dist, u, v))
+	}
+
+	fn _hit_info(&self, i: usize, origin: Point3<f32>, dir: Unit<Vector3<f32>>, dist: f32, u: f32, v: f32) -> HitInfo {
+		let data = &self.data;
+		let [ia, ib, ic] = data.triangles[i];
+		let (ia, ib, ic) = (ia as usize, ib as usize, ic as usize);
+		let w = 1.0 - u - v;
+
+		let [a, b, c] = data.vertices(i);
+		let mut face_normal = (b - a).cross(&(c - a));
+		let mut normal = if data.normals.len() == data.positions.len() {
+			data.normals[ia] * w + data.normals[ib] * u + data.normals[ic] * v
+		}
+		else {
+			face_normal
+		};
+
+		// triangle is double sided, normal always face toward ray
+		if face_normal.dot(dir.as_ref()) > 0.0 {
+			face_normal = -face_normal;
+		}
+		if normal.dot(&face_normal) < 0.0 {
+			normal = -normal;
+		}
+
+		let uv = if data.uvs.len() == data.positions.len() {
+			Some(data.uvs[ia] * w + data.uvs[ib] * u + data.uvs[ic] * v)
+		}
+		else {
+			None
+		};
+
+		let vertex_color = if data.colors.len() == data.positions.len() {
+			Some(data.colors[ia] * w + data.colors[ib] * u + data.colors[ic] * v)
+		}
+		else {
+			None
+		};
+
+		HitInfo {
+			incoming_dir: dir,
+			dist,
+			intersection: origin + dir.as_ref() * dist,
+			normal: Unit::new_normalize(normal),
+			uv,
+			vertex_color,
+		}
+	}
+}
+
+impl Shape for Mesh {
+	fn intersect(&self, origin: Point3<f32>, dir: Unit<Vector3<f32>>) -> Option<HitInfo> {
+		let inv_dir = dir.map(|x| 1.0 / x);
+		let mut closest: Option<(usize, f32, f32, f32)> = None;
+		let mut stack = Vec::new();
+		// counted locally, thread local counter is touched once per ray
+		let mut tests = 0;
+		if !self.bvh.nodes.is_empty() {
+			stack.push(0);
+		}
+
+		while let Some(n) = stack.pop() {
+			let node = &self.bvh.nodes[n];
+			let max_dist = closest.map_or(f32::INFINITY, |(_, dist, _, _)| dist);
+			tests += 1;
+			if !_ray_box(&origin, &inv_dir, &node.min, &node.max, max_dist) {
+				continue;
+			}
+			if node.count == 0 {
+				stack.push(node.right);
+				stack.push(n + 1);
+				continue;
+			}
+			tests += node.count as u64;
+			for &i in &self.bvh.order[node.start..node.start + node.count] {
+				if let Some((dist, u, v)) = self._intersect_triangle(i, &origin, dir.as_ref()) {
+					if !matches!(closest, Some((_, d, _, _)) if d <= dist) {
+						closest = Some((i, dist, u, v));
+					}
+				}
+			}
+		}
+
+		stats::count_intersection_tests(tests);
+		closest.map(|(i, dist, u, v)| self._hit_info(i, origin, dir, dist, u, v))
+	}
+}
+
+// slab test, true if ray enter box before max_dist
+fn _ray_box(origin: &Point3<f32>, inv_dir: &Vector3<f32>, min: &Point3<f32>, max: &Point3<f32>, max_dist: f32) -> bool {
+	let (mut t_min, mut t_max) = (0.0f32, max_dist);
+	for axis in 0..3 {
+		let t0 = (min[axis] - origin[axis]) * inv_dir[axis];
+		let t1 = (max[axis] - origin[axis]) * inv_dir[axis];
+		let (t0, t1) = if t0 < t1 { (t0, t1) } else { (t1, t0) };
+		// NaN (ray parallel to slab and on its boundary) doesn't cull the box
+		t_min = if t0 > t_min { t0 } else { t_min };
+		t_max = if t1 < t_max { t1 } else { t_max };
+		if t_min > t_max {
+			return false;
+		}
+	}
+	true
+}
+
+impl MeshData {
+	fn vertices(&self, i: usize) -> [Point3<f32>; 3] {
+		let t = &self.triangles[i];
+		[self.positions[t[0] as usize], self.positions[t[1] as usize], self.positions[t[2] as usize]]
+	}
+
+	// index out of range can't be intersected, validation report it
+	fn is_indexable(&self) -> bool {
+		let n = self.positions.len();
+		self.triangles.iter().all(|t| t.iter().all(|&i| (i as usize) < n))
+	}
+}
+
+impl Bvh {
+	fn build(data: &MeshData) -> Bvh {
+		let mut bvh = Bvh {nodes: Vec::new(), order: (0..data.triangles.len()).collect()};
+		if bvh.order.is_empty() || !data.is_indexable() {
+			return bvh;
+		}
+
+		let centroids: Vec<Point3<f32>> = (0..data.triangles.len())
+			.map(|i| {
+				let [a, b, c] = data.vertices(i);
+				Point3::from((a.coords + b.coords + c.coords) / 3.0)
+			})
+			.collect();
+		let len = bvh.order.len();
+		bvh._build_node(data, &centroids, 0, len);
+		bvh
+	}
+
+	// split triangle at median centroid along longest axis
+	fn _build_node(&mut self, data: &MeshData, centroids: &[Point3<f32>], start: usize, end: usize) -> usize {
+		let (min, max) = _bounds(self.order[start..end].iter().flat_map(|&i| data.vertices(i).to_vec()));
+		let node = self.nodes.len();
+		self.nodes.push(BvhNode {min, max, start, count: end - start, right: 0});
+		if end - start <= BVH_LEAF_SIZE {
+			return node;
+		}
+
+		let (cmin, cmax) = _bounds(self.order[start..end].iter().map(|&i| centroids[i]));
+		let extent = cmax - cmin;
+		let axis = extent.imax();
+		if extent[axis] <= 0.0 || extent[axis].is_nan() {
+			return node;
+		}
+
+		let mid = (start + end) / 2;
+		self.order[start..end].select_nth_unstable_by(mid - start, |&a, &b|
+			centroids[a][axis].partial_cmp(&centroids[b][axis]).unwrap_or(Equal)
+		);
+		self.nodes[node].count = 0;
+		self._build_node(data, centroids, start, mid);
+		let right = self._build_node(data, centroids, mid, end);
+		self.nodes[node].right = right;
+		node
+	}
+}
+
+fn _bounds(points: impl Iterator<Item = Point3<f32>>) -> (Point3<f32>, Point3<f32>) {
+	let inf = f32::INFINITY;
+	points.fold(
+		(Point3::new(inf, inf, inf), Point3::new(-inf, -inf, -inf)),
+		|(min, max), p| (
+			Point3::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z)),
+			Point3::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z))
+		)
+	)
+}
+
+impl From<MeshData> for Mesh {
+	fn from(data: MeshData) -> Self {
+		Mesh::new(data)
+	}
+}
+
+impl From<Mesh> for MeshData {
+	fn from(mesh: Mesh) -> Self {
+		Arc::try_unwrap(mesh.data).unwrap_or_else(|data| data.as_ref().clone())
+	}
+}
+
+impl Validate for Mesh {
+	fn validate(&self, path: &str, report: &mut Report) {
+		let data = &self.data;
+		let positions_path = field(path, "positions");
+		for (i, p) in data.positions.iter().enumerate() {
+			report.check_point(&index(&positions_path, i), p);
+		}
+
+		let n = data.positions.len();
+		if !data.normals.is_empty() && data.normals.len() != n {
+			report.error(&field(path, "normals"), format!("must have one normal per position ({}), got {}", n, data.normals.len()));
+		}
+		if !data.uvs.is_empty() && data.uvs.len() != n {
+			report.error(&field(path, "uvs"), format!("must have one uv per position ({}), got {}", n, data.uvs.len()));
+		}
+		if !data.colors.is_empty() && data.colors.len() != n {
+			report.error(&field(path, "colors"), format!("must have one color per position ({}), got {}", n, data.colors.len()));
+		}
+
+		let triangles_path = field(path, "triangles");
+		if data.triangles.is_empty() {
+			report.warning(&triangles_path, "mesh has no triangle");
+		}
+		// only report the first bad triangle, a broken index buffer would flood the report
+		if let Some(i) = data.triangles.iter().position(|t| t.iter().any(|&v| v as usize >= n)) {
+			report.error(
+				&index(&triangles_path, i),
+				format!("vertex index out of range, mesh has {} positions", n)
+			);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use assert_approx_eq::assert_approx_eq;
+
+	use super::*;
+
+	// grid of quad on z = 0 plane, two triangle per quad
+	fn grid(size: u32) -> Mesh {
+		let mut positions = Vec::new();
+		let mut uvs = Vec::new();
+		for y in 0..=size {
+			for x in 0..=size {
+				positions.push(Point3::new(x as f32, y as f32, 0.0));
+				uvs.push(Vector2::new(x as f32 / size as f32, y as f32 / size as f32));
+			}
+		}
+		let mut triangles = Vec::new();
+		for y in 0..size {
+			for x in 0..size {
+				let i = y * (size + 1) + x;
+				triangles.push([i, i + 1, i + size + 1]);
+				triangles.push([i + 1, i + size + 2, i + size + 1]);
+			}
+		}
+		Mesh::new(MeshData {positions, normals: Vec::new(), uvs, colors: Vec::new(), triangles})
+	}
+
+	#[test]
+	fn mesh_intersect_test() {
+		let mesh = grid(8);
+		assert_eq!(mesh.triangle_count(), 128);
+
+		let hit = mesh.intersect(Point3::new(2.5, 6.25, 3.0), -Vector3::z_axis()).unwrap();
+		assert_approx_eq!(hit.dist, 3.0);
+		assert_approx_eq!(hit.intersection.x, 2.5);
+		let uv = hit.uv.unwrap();
+		assert_approx_eq!(uv.x, 2.5 / 8.0);
+		assert_approx_eq!(uv.y, 6.25 / 8.0);
+
+		// seen from below, normal still face the ray
+		let hit = mesh.intersect(Point3::new(1.5, 1.5, -1.0), Vector3::z_axis()).unwrap();
+		assert_approx_eq!(hit.normal.z, -1.0);
+
+		assert!(mesh.intersect(Point3::new(9.0, 1.0, 1.0), -Vector3::z_axis()).is_none());
+	}
+}
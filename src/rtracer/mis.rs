@@ -0,0 +1,171 @@
+/*
+multiple importance sampling of direct light
+
+light sample is good for small light on rough surface, bsdf sample is good for big light on glossy surface.
+each shading point take one sample of both and weight them by heuristic of their pdf,
+so whichever strategy is better at a direction dominate there
+
+material describe itself at the hit point with Scatter, light with Light::sample / pdf / eval
+*/
+use std::str::FromStr;
+
+use nalgebra::{Point3, Unit, Vector3};
+use serde::{Deserialize, Serialize};
+
+use super::{Color3, RayCastInfo, Scene};
+use super::light::{Light, LightSample, Lights};
+use super::sampler::Sampler;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum MisHeuristic {
+	// pdf / sum of pdf
+	Balance,
+	// pdf^2 / sum of pdf^2, cut more noise when one strategy is much better
+	Power,
+}
+
+impl FromStr for MisHeuristic {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"balance" => Ok(MisHeuristic::Balance),
+			"power" => Ok(MisHeuristic::Power),
+			_ => Err(format!("unknown mis heuristic '{}', expected one of: balance, power", s)),
+		}
+	}
+}
+
+impl MisHeuristic {
+	// weight of sample taken with density pdf, other strategy would pick it with density other
+	pub fn weight(self, pdf: f32, other: f32) -> f32 {
+		let (a, b) = match self {
+			MisHeuristic::Balance => (pdf, other),
+			MisHeuristic::Power => (pdf * pdf, other * other),
+		};
+		if a + b > 0.0 { a / (a + b) } else { 0.0 }
+	}
+}
+
+// material at one hit point, as seen by direct light
+pub trait Scatter {
+	// bsdf times cosine toward dir (dir point away from surface)
+	fn eval(&self, dir: &Unit<Vector3<f32>>) -> Color3;
+	// solid angle density of sample returning dir
+	fn pdf(&self, dir: &Unit<Vector3<f32>>) -> f32;
+	fn sample(&self, sampler: &mut impl Sampler) -> Unit<Vector3<f32>>;
+}
+
+// light reaching pos from one light and reflected by scatter, one light sample + one bsdf sample
+// delta light can't be hit by bsdf sample so its light sample take full weight
+pub fn direct_light(
+	scene: &Scene,
+	light: &Lights,
+	pos: Point3<f32>,
+	scatter: &impl Scatter,
+	info: RayCastInfo,
+	sampler: &mut impl Sampler)
+	-> Color3 {
+	let (time, heuristic) = (info.time(), info.heuristic());
+
+	let sample = light.sample(pos, sampler.get_2d());
+	let light_part = match sample.pdf {
+		Some(pdf) if pdf <= 0.0 => Color3::zeros(),
+		_ if !sample.visible(scene, pos, time) => Color3::zeros(),
+		None => scatter.eval(&sample.dir).component_mul(&sample.light),
+		Some(pdf) => {
+			let weight = heuristic.weight(pdf, scatter.pdf(&sample.dir));
+			scatter.eval(&sample.dir).component_mul(&sample.light) * (weight / pdf)
+		},
+	};
+	if light.is_delta() {
+		return light_part;
+	}
+
+	let dir = scatter.sample(sampler);
+	let scatter_pdf = scatter.pdf(&dir);
+	if scatter_pdf <= 0.0 {
+		return light_part;
+	}
+	let bsdf_part = match light.eval(pos, &dir) {
+		Some((radiance, dist)) if LightSample::new(dir, dist, radiance, None).visible(scene, pos, time) => {
+			let weight = heuristic.weight(scatter_pdf, light.pdf(pos, &dir));
+			scatter.eval(&dir).component_mul(&radiance) * (weight / scatter_pdf)
+		},
+		_ => Color3::zeros(),
+	};
+	light_part + bsdf_part
+}
+
+#[cfg(test)]
+mod tests {
+	use nalgebra::{UnitQuaternion, Vector3};
+
+	use crate::rtracer::{helper, SceneObject};
+	use crate::rtracer::geometric::InfinitePlane;
+	use crate::rtracer::light::AreaLight;
+	use crate::rtracer::material::Diffuse;
+	use crate::rtracer::sampler::SamplerKind;
+
+	use super::*;
+
+	struct Lambert(Unit<Vector3<f32>>);
+
+	impl Scatter for Lambert {
+		fn eval(&self, dir: &Unit<Vector3<f32>>) -> Color3 {
+			Color3::repeat(self.0.dot(dir).max(0.0))
+		}
+
+		fn pdf(&self, dir: &Unit<Vector3<f32>>) -> f32 {
+			self.0.dot(dir).max(0.0) / std::f32::consts::PI
+		}
+
+		fn sample(&self, sampler: &mut impl Sampler) -> Unit<Vector3<f32>> {
+			helper::sample_cosine_hemisphere(sampler.get_2d(), &self.0)
+		}
+	}
+
+	// mis and light sample only should converge to the same irradiance
+	#[test]
+	fn mis_match_light_sample_test() {
+		let mut scene = Scene::new();
+		scene.add_obj(SceneObject::new(
+			InfinitePlane {pos: Point3::origin(), norm: Vector3::z_axis()},
+			Diffuse::new(Color3::repeat(1.0))
+		));
+		let rotation = UnitQuaternion::from_axis_angle(&Vector3::y_axis(), std::f32::consts::FRAC_PI_2);
+		let light: Lights = AreaLight::new(Vector3::new(0.0, 0.0, 1.5), rotation, 1.0, None).into();
+		scene.add_light(light.clone());
+
+		let (pos, normal) = (Point3::new(0.5, 0.3, 0.0), Vector3::z_axis());
+		let mut sampler = SamplerKind::Independent.build(1, 1);
+		let count = 800;
+		let reference = (0..count).map(|i| {
+			sampler.start_sample(0, 0, i);
+			light.direct_light_at(pos, normal, &scene, 0.0, &mut sampler)
+		}).sum::<Color3>() / count as f32;
+
+		for &heuristic in &[MisHeuristic::Balance, MisHeuristic::Power] {
+			let info = RayCastInfo::at_time(0.0).with_heuristic(heuristic);
+			let count = 40 * count;
+			let estimate = (0..count).map(|i| {
+				sampler.start_sample(0, 0, i);
+				direct_light(&scene, &light, pos, &Lambert(normal), info, &mut sampler)
+			}).sum::<Color3>() / count as f32;
+			assert!((estimate.x / reference.x - 1.0).abs() < 0.03, "{:?}: {} vs {}", heuristic, estimate.x, reference.x);
+		}
+	}
+
+	#[test]
+	fn heuristic_weight_test() {
+		for &heuristic in &[MisHeuristic::Balance, MisHeuristic::Power] {
+			// weight of both strategy sum to one
+			let (a, b) = (heuristic.weight(2.0, 3.0), heuristic.weight(3.0, 2.0));
+			assert!((a + b - 1.0).abs() < 1e-6);
+			assert_eq!(heuristic.weight(1.0, 0.0), 1.0);
+			assert_eq!(heuristic.weight(0.0, 0.0), 0.0);
+		}
+		assert!((MisHeuristic::Balance.weight(1.0, 3.0) - 0.25).abs() < 1e-6);
+		assert!((MisHeuristic::Power.weight(1.0, 3.0) - 0.1).abs() < 1e-6);
+	}
+}
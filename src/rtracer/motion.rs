@@ -0,0 +1,98 @@
+use nalgebra::{Point3, Similarity3, Translation3, Unit, Vector3};
+use serde::{Deserialize, Serialize};
+
+use super::HitInfo;
+use super::validation::{field, Report, Validate};
+
+// transform of an object that move during the shutter interval
+// start is the transform at time = 0.0, end is the transform at time = 1.0
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct Motion {
+	pub start: Similarity3<f32>,
+	pub end: Similarity3<f32>,
+}
+
+impl Motion {
+	pub fn new(start: Similarity3<f32>, end: Similarity3<f32>) -> Self {
+		Motion {start, end}
+	}
+
+	// motion that doesn't move at all
+	pub fn fixed(transform: Similarity3<f32>) -> Self {
+		Motion {start: transform, end: transform}
+	}
+
+	pub fn is_static(&self) -> bool {
+		self.start == self.end
+	}
+
+	// interpolated transform at time, time outside [0, 1] is clamped
+	pub fn at(&self, time: f32) -> Similarity3<f32> {
+		if self.is_static() {
+			return self.start;
+		}
+
+		let t = time.clamp(0.0, 1.0);
+		let (s, e) = (&self.start, &self.end);
+
+		let translation = s.isometry.translation.vector.lerp(&e.isometry.translation.vector, t);
+		let rotation = s.isometry.rotation
+			.try_slerp(&e.isometry.rotation, t, 1e-6)
+			.unwrap_or(s.isometry.rotation);
+		let scaling = s.scaling() + (e.scaling() - s.scaling()) * t;
+
+		Similarity3::from_parts(Translation3::from(translation), rotation, scaling)
+	}
+
+	// move ray from world space into object space at given time
+	pub fn to_local(&self, time: f32, origin: Point3<f32>, dir: Unit<Vector3<f32>>)
+		-> (Point3<f32>, Unit<Vector3<f32>>) {
+		let inv = self.at(time).inverse();
+		(inv * origin, Unit::new_normalize(inv * dir.into_inner()))
+	}
+
+	// move hit that happened in object space back to world space
+	pub fn to_world(&self, time: f32, hit: HitInfo, world_dir: Unit<Vector3<f32>>) -> HitInfo {
+		let trans = self.at(time);
+		HitInfo {
+			incoming_dir: world_dir,
+			dist: hit.dist * trans.scaling(),
+			intersection: trans * hit.intersection,
+			normal: trans.isometry.rotation * hit.normal,
+			uv: hit.uv,
+			vertex_color: hit.vertex_color,
+		}
+	}
+}
+
+impl Validate for Motion {
+	fn validate(&self, path: &str, report: &mut Report) {
+		report.check_transform(&field(path, "start"), &self.start);
+		report.check_transform(&field(path, "end"), &self.end);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use nalgebra::{Similarity3, Vector3};
+
+	use assert_approx_eq::assert_approx_eq;
+
+	use super::*;
+
+	#[test]
+	fn motion_interpolate_test() {
+		let motion = Motion::new(
+			Similarity3::new(Vector3::new(0.0, 0.0, 0.0), Vector3::zeros(), 1.0),
+			Similarity3::new(Vector3::new(2.0, 0.0, 0.0), Vector3::zeros(), 3.0),
+		);
+
+		let mid = motion.at(0.5);
+		assert_approx_eq!(mid.isometry.translation.vector.x, 1.0);
+		assert_approx_eq!(mid.scaling(), 2.0);
+
+		// clamp outside of [0, 1]
+		assert_approx_eq!(motion.at(2.0).isometry.translation.vector.x, 2.0);
+		assert!(Motion::fixed(mid).is_static());
+	}
+}
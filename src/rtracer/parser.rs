@@ -1,10 +1,12 @@
+use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::fs;
 use std::io;
 use std::io::Write;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-use nalgebra::{Matrix, Point3, U1, U3, Vector3};
+use nalgebra::{Matrix, Point3, Similarity3, U1, U3, Vector3};
 use nalgebra::base::allocator::Allocator;
 use nalgebra::base::default_allocator::DefaultAllocator;
 use ron::de;
@@ -17,33 +19,358 @@ use custom_error::custom_error;
 use crate::rtracer::{Color3, light, SceneObject};
 
 use super::{Camera, Scene};
+use super::animation::Animation;
+use super::texture::TextureCache;
+use super::validation::{field, Report, Validate};
+use super::{MaterialRef, Materials};
+use super::{export, import};
+use super::serde_interface::{present, write_present};
 
-#[derive(Serialize, Deserialize)]
+mod migration;
+
+pub use self::migration::CURRENT_VERSION;
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct SceneData {
+	// layout version of file, older file is migrated on load so this is always current after loading
+	#[serde(default)]
+	pub version: u32,
 	pub scene: Scene,
 	pub camera: Camera,
+	#[serde(default)]
+	pub animation: Animation,
+	// material library, object refer to these with Named("name")
+	#[serde(default)]
+	pub materials: BTreeMap<String, Arc<Materials>>,
+	// image size (width, height) suggested by scene file, renderer setting decide the actual size
+	#[serde(default, deserialize_with = "present", serialize_with = "write_present", skip_serializing_if = "Option::is_none")]
+	pub resolution: Option<(u32, u32)>,
+}
+
+impl SceneData {
+	pub fn new(scene: Scene, camera: Camera) -> Self {
+		SceneData {
+			version: CURRENT_VERSION,
+			scene,
+			camera,
+			animation: Animation::default(),
+			materials: BTreeMap::new(),
+			resolution: None,
+		}
+	}
+
+	// point every unresolved named material to material library, unknown name is left unresolved
+	pub fn resolve_materials(&mut self) {
+		let library = &self.materials;
+		self.scene.for_each_obj_mut(|obj| {
+			obj.material.resolve(library);
+		});
+	}
+
+	// copy of scene data posed by animation at frame
+	pub fn at_frame(&self, frame: u32) -> SceneData {
+		let mut posed = self.clone();
+		self.animation.apply(frame as f32, &mut posed.scene, &mut posed.camera);
+		posed
+	}
+}
+
+impl Validate for SceneData {
+	fn validate(&self, path: &str, report: &mut Report) {
+		for (name, material) in &self.materials {
+			material.validate(&format!("{}[\"{}\"]", field(path, "materials"), name), report);
+		}
+		self.scene.validate(&field(path, "scene"), report);
+		self.camera.validate(&field(path, "camera"), report);
+		validate_resolution(self.resolution, &field(path, "resolution"), report);
+		self.animation.validate(&field(path, "animation"), &self.scene, report);
+	}
+}
+
+fn validate_resolution(resolution: Option<(u32, u32)>, path: &str, report: &mut Report) {
+	if let Some((width, height)) = resolution {
+		if width == 0 || height == 0 {
+			report.error(path, format!("must not be empty, got {}x{}", width, height));
+		}
+	}
+}
+
+// another scene file merged into the including one
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Include {
+	// relative to directory of the including file
+	pub path: String,
+	// place every included object and light under this transform
+	#[serde(default)]
+	pub transform: Option<Similarity3<f32>>,
+	// replace material of every included object
+	#[serde(default)]
+	pub material: Option<MaterialRef>,
+	// replace entry of material library of included file
+	#[serde(default)]
+	pub materials: BTreeMap<String, Arc<Materials>>,
+}
+
+// content of one scene file, everything is optional so a file can hold just a piece of scene
+// importer of other format build one of these too
+#[derive(Deserialize, Default)]
+pub(crate) struct SceneDocument {
+	#[serde(default)]
+	pub(crate) include: Vec<Include>,
+	#[serde(default, deserialize_with = "present")]
+	pub(crate) scene: Option<Scene>,
+	#[serde(default, deserialize_with = "present")]
+	pub(crate) camera: Option<Camera>,
+	#[serde(default)]
+	pub(crate) animation: Animation,
+	#[serde(default)]
+	pub(crate) materials: BTreeMap<String, Arc<Materials>>,
+	#[serde(default, deserialize_with = "present")]
+	pub(crate) resolution: Option<(u32, u32)>,
+}
+
+impl SceneDocument {
+	// only what the file wrote itself, included file is validated on its own
+	// animation is left to load, it can point at included object
+	fn validate(&self, report: &mut Report) {
+		for (name, material) in &self.materials {
+			material.validate(&format!("materials[\"{}\"]", name), report);
+		}
+		self.scene.validate("scene", report);
+		self.camera.validate("camera", report);
+		validate_resolution(self.resolution, "resolution", report);
+	}
 }
 
 custom_error!{ pub SceneParserError
-	RonSerializeError {source: ser::Error} = "Encounter error while serializing scene data to ron",
-	RonDeserializeError {source: de::Error} = "Encounter error while deserialize ron to scene data",
-	IOError {source: io::Error} = "Encounter error while opening file"
+	RonSerializeError {source: ser::Error} = "Encounter error while serializing scene data to ron: {source}",
+	RonDeserializeError {source: de::Error} = "Encounter error while deserialize ron to scene data: {source}",
+	JsonError {source: serde_json::Error} = "Encounter error while converting scene data to or from json: {source}",
+	IOError {source: io::Error} = "Encounter error while opening file: {source}",
+	InvalidScene {report: Report} = "Scene failed validation:\n{report}",
+	InFile {path: String, inner: Box<SceneParserError>} = "{path}: {inner}",
+	IncludeCycle {chain: String} = "Include cycle: {chain}",
+	MissingCamera {} = "Neither scene nor its include define a camera",
+	UnsupportedVersion {version: u32, supported: u32} = "Scene file version {version} is newer than supported version {supported}",
+	InvalidVersion {version: String} = "Scene file version '{version}' is not a positive whole number",
+	GltfError {source: gltf::Error} = "Encounter error while importing gltf: {source}",
+	TextureError {path: String, message: String} = "Could not load texture {path}: {message}",
+	MeshError {message: String} = "Invalid mesh file: {message}",
+	PbrtError {line: usize, message: String} = "Invalid pbrt file at line {line}: {message}",
+	UnsupportedFormat {extension: String} = "Can't save scene as '.{extension}', expected one of: ron, json, obj"
 }
 
+// load and validate scene, fail if validation found any error
 pub fn load_scene_data(path: impl AsRef<Path>) -> Result<SceneData, SceneParserError> {
-	let scene_data = de::from_reader(fs::File::open(path)?)?;
-	Ok(scene_data)
+	load_scene_data_with_report(path).map(|(scene_data, _)| scene_data)
+}
+
+// like load_scene_data but also return warning found during validation
+pub fn load_scene_data_with_report(path: impl AsRef<Path>) -> Result<(SceneData, Report), SceneParserError> {
+	let mut report = Report::new();
+	let doc = load_document(path.as_ref(), &mut Vec::new(), &mut report)?;
+	let mut scene_data = SceneData {
+		version: CURRENT_VERSION,
+		scene: doc.scene.unwrap_or_else(Scene::new),
+		camera: doc.camera.ok_or(SceneParserError::MissingCamera {})?,
+		animation: doc.animation,
+		materials: doc.materials,
+		resolution: doc.resolution,
+	};
+	scene_data.resolve_materials();
+	// every file is validated while loading it, see load_document
+	if report.has_error() {
+		return Err(SceneParserError::InvalidScene {report});
+	}
+	Ok((scene_data, report))
+}
+
+// read file and merge every include into it, stack is the chain of file being loaded
+// gltf, ply, stl and pbrt file is imported, warning of importer is added to report
+fn load_document(path: &Path, stack: &mut Vec<PathBuf>, report: &mut Report) -> Result<SceneDocument, SceneParserError> {
+	let in_file = |source: SceneParserError|
+		SceneParserError::InFile {path: path.display().to_string(), inner: Box::new(source)};
+
+	let canonical = fs::canonicalize(path).map_err(|e| in_file(e.into()))?;
+	if let Some(i) = stack.iter().position(|p| *p == canonical) {
+		let chain = stack[i..].iter().chain(std::iter::once(&canonical))
+			.map(|p| p.display().to_string())
+			.collect::<Vec<_>>()
+			.join(" -> ");
+		return Err(SceneParserError::IncludeCycle {chain});
+	}
+
+	let extension = path.extension().and_then(|e| e.to_str()).map(str::to_lowercase);
+	let mut doc = match extension.as_deref() {
+		Some("gltf") | Some("glb") => {
+			let mut import_report = Report::new();
+			let doc = import::gltf::load_gltf(path, &mut import_report).map_err(in_file)?;
+			report.append_from_file(&path.display().to_string(), import_report);
+			doc
+		},
+		Some("ply") => {
+			let mut import_report = Report::new();
+			let doc = import::ply::load_ply(path, &mut import_report).map_err(in_file)?;
+			report.append_from_file(&path.display().to_string(), import_report);
+			doc
+		},
+		Some("stl") => import::stl::load_stl(path).map_err(in_file)?,
+		Some("pbrt") => {
+			let mut import_report = Report::new();
+			let doc = import::pbrt::load_pbrt(path, &mut import_report).map_err(in_file)?;
+			report.append_from_file(&path.display().to_string(), import_report);
+			doc
+		},
+		Some("json") => {
+			let text = fs::read_to_string(path).map_err(|e| in_file(e.into()))?;
+			let value = serde_json::from_str(&text).map_err(|e| in_file(e.into()))?;
+			migration::read_json(value).map_err(in_file)?
+		},
+		_ => {
+			let text = fs::read_to_string(path).map_err(|e| in_file(e.into()))?;
+			migration::read_ron(&text).map_err(in_file)?
+		},
+	};
+
+	let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+	let mut textures = TextureCache::default();
+	load_textures(&mut doc, base_dir, &mut textures).map_err(in_file)?;
+
+	stack.push(canonical);
+	let mut children = Vec::new();
+	for mut include in std::mem::take(&mut doc.include) {
+		load_include_textures(&mut include, base_dir, &mut textures).map_err(in_file)?;
+		let child = load_document(&base_dir.join(&include.path), stack, report)?;
+		children.push((include, child));
+	}
+	stack.pop();
+
+	// own library first, then library of included file, named material of included file are already resolved
+	let mut library = doc.materials.clone();
+	for (_, child) in &children {
+		for (name, material) in &child.materials {
+			library.entry(name.clone()).or_insert_with(|| material.clone());
+		}
+	}
+	if let Some(scene) = doc.scene.as_mut() {
+		scene.for_each_obj_mut(|obj| {
+			obj.material.resolve(&library);
+		});
+	}
+
+	// checked before include is merged in, so diagnostic name the file that wrote the faulty part
+	let mut own_report = Report::new();
+	doc.validate(&mut own_report);
+
+	// animation can point at included object, so only the track this file wrote is checked against the merged scene
+	let own_animation = doc.animation.clone();
+	for (include, child) in children {
+		merge_include(&mut doc, child, &include);
+	}
+	own_animation.validate("animation", doc.scene.as_ref().unwrap_or(&Scene::new()), &mut own_report);
+	report.append_from_file(&path.display().to_string(), own_report);
+
+	Ok(doc)
+}
+
+// load image of every texture written in file, relative path start from directory of the file
+fn load_textures(doc: &mut SceneDocument, base_dir: &Path, textures: &mut TextureCache) -> Result<(), SceneParserError> {
+	for material in doc.materials.values_mut() {
+		load_texture(material, base_dir, textures)?;
+	}
+	let mut result = Ok(());
+	if let Some(scene) = doc.scene.as_mut() {
+		scene.for_each_obj_mut(|obj| {
+			if let (Ok(()), MaterialRef::Inline(material)) = (&result, &mut obj.material) {
+				result = load_texture(material, base_dir, textures);
+			}
+		});
+	}
+	result
+}
+
+fn load_include_textures(include: &mut Include, base_dir: &Path, textures: &mut TextureCache) -> Result<(), SceneParserError> {
+	if let Some(MaterialRef::Inline(material)) = include.material.as_mut() {
+		load_texture(material, base_dir, textures)?;
+	}
+	for material in include.materials.values_mut() {
+		load_texture(material, base_dir, textures)?;
+	}
+	Ok(())
+}
+
+// shared material is only copied when it has texture to load
+fn load_texture(material: &mut Arc<Materials>, base_dir: &Path, textures: &mut TextureCache) -> Result<(), SceneParserError> {
+	if matches!(material.texture(), Some(t) if !t.is_loaded()) {
+		if let Some(texture) = Arc::make_mut(material).texture_mut() {
+			texture.load_cached(base_dir, textures)?;
+		}
+	}
+	Ok(())
 }
 
+fn merge_include(doc: &mut SceneDocument, mut child: SceneDocument, include: &Include) {
+	let mut child_scene = child.scene.take().unwrap_or_else(Scene::new);
+
+	let library = &doc.materials;
+	child_scene.for_each_obj_mut(|obj| {
+		obj.material.override_with(&include.materials);
+		if let Some(material) = &include.material {
+			obj.material = material.clone();
+			obj.material.resolve(library);
+		}
+	});
+	if let Some(trans) = &include.transform {
+		for obj in child_scene.iter_obj_mut() {
+			obj.transform(trans);
+		}
+		for light in child_scene.iter_light_mut() {
+			light.transform(trans);
+		}
+		// object inside group follow through the group transform
+		for group in child_scene.iter_group_mut() {
+			group.transform = trans * group.transform;
+		}
+	}
+
+	// including file win on everything it define itself
+	let scene = doc.scene.get_or_insert_with(|| {
+		let mut scene = Scene::new();
+		scene.set_skylight(child_scene.get_skylight());
+		scene
+	});
+	let (object_offset, light_offset) = (scene.obj_count(), scene.light_count());
+	scene.merge(child_scene);
+	doc.animation.append(child.animation, object_offset, light_offset);
+	if doc.camera.is_none() {
+		doc.camera = child.camera;
+	}
+	if doc.resolution.is_none() {
+		doc.resolution = child.resolution;
+	}
+	for (name, material) in child.materials {
+		doc.materials.entry(name).or_insert(material);
+	}
+}
+
+// format is picked by extension: json, obj (geometry and material only, with .mtl next to it) or ron, also used without extension
 pub fn save_scene_data(path: impl AsRef<Path>, scene: &SceneData) -> Result<(), SceneParserError> {
-	let encoded_scene = to_string_pretty(scene, PrettyConfig::default())?;
+	let path = path.as_ref();
+	let extension = path.extension().map(|e| e.to_string_lossy().into_owned());
+	let encoded_scene = match extension.as_ref().map(|e| e.to_lowercase()).as_deref() {
+		Some("json") => serde_json::to_string_pretty(scene)?,
+		Some("obj") => return export::obj::save_obj(path, scene),
+		Some("ron") | None => to_string_pretty(scene, PrettyConfig::default())?,
+		// importer only format like glb would otherwise get ron text
+		Some(_) => return Err(SceneParserError::UnsupportedFormat {extension: extension.unwrap_or_default()}),
+	};
 	fs::write(path, encoded_scene)?;
 	Ok(())
 }
 
 pub mod serde_interface {
     use nalgebra::{Point3, Rotation3, Vector3};
-    use serde::{Deserialize, Serialize};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
     use super::super::camera::Camera;
 
@@ -62,6 +389,19 @@ pub mod serde_interface {
 		}
 	}
 
+	// optional field written without Some(..), so complete scene file is also a valid document
+	pub(crate) fn present<'de, D: Deserializer<'de>, T: Deserialize<'de>>(d: D) -> Result<Option<T>, D::Error> {
+		T::deserialize(d).map(Some)
+	}
+
+	// counterpart of present, used with skip_serializing_if = "Option::is_none"
+	pub(crate) fn write_present<S: Serializer, T: Serialize>(value: &Option<T>, s: S) -> Result<S::Ok, S::Error> {
+		match value {
+			Some(x) => x.serialize(s),
+			None => s.serialize_none(),
+		}
+	}
+
 	impl From<Camera> for CameraSerdeInterface {
 		fn from(camera: Camera) -> CameraSerdeInterface {
 			CameraSerdeInterface {
@@ -71,4 +411,109 @@ pub mod serde_interface {
 		}
 	}
 
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn write_files(dir: &str, files: &[(&str, &str)]) -> PathBuf {
+		let dir = std::env::temp_dir().join(dir);
+		for (name, content) in files {
+			let path = dir.join(name);
+			fs::create_dir_all(path.parent().unwrap()).unwrap();
+			fs::write(path, content).unwrap();
+		}
+		dir
+	}
+
+	#[test]
+	fn include_test() {
+		let dir = write_files("rtracer_include_test", &[
+			("main.ron", r#"(
+				include: [
+					(path: "rig/camera.ron"),
+					(path: "rig/props.ron", transform: Some((isometry: (rotation: [0, 0, 0, 1], translation: [0, 5, 0]), scaling: 1))),
+					(path: "rig/props.ron", materials: {"wood": Diffuse((color: [0, 1, 0]))}),
+				],
+				scene: (
+					objects: [(material: Named("wood"), shape: Sphere((pos: [3, 0, 0], radius: 1)))],
+					lights: [PointLight((pos: [0, 0, 3], light: [1, 1, 1]))],
+				),
+				materials: {"wood": Diffuse((color: [1, 0, 0]))},
+			)"#),
+			("rig/camera.ron", r#"(camera: (pos: [0, 0, 0], forward: [1, 0, 0], right: [0, 1, 0], up: [0, 0, 1]))"#),
+			("rig/props.ron", r#"(
+				scene: (objects: [(material: Named("wood"), shape: Sphere((pos: [4, 0, 0], radius: 0.5)))]),
+				materials: {"wood": Diffuse((color: [0, 0, 1]))},
+			)"#),
+		]);
+
+		let data = load_scene_data(dir.join("main.ron")).unwrap();
+		let colors: Vec<_> = data.scene.iter_obj()
+			.map(|obj| obj.material.get().map(|m| m.clone()).unwrap().color_mut().map(|c| *c).unwrap())
+			.collect();
+		// own object, props with its own library, then props with overridden library
+		assert_eq!(colors, vec![Color3::new(1.0, 0.0, 0.0), Color3::new(0.0, 0.0, 1.0), Color3::new(0.0, 1.0, 0.0)]);
+		assert!(data.scene.iter_obj().nth(1).unwrap().placement.is_some());
+		assert_eq!(data.scene.iter_light().count(), 1);
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn include_error_test() {
+		let dir = write_files("rtracer_include_error_test", &[
+			("a.ron", r#"(include: [(path: "b.ron")])"#),
+			("b.ron", r#"(include: [(path: "a.ron")])"#),
+			("c.ron", r#"(include: [(path: "d.ron")])"#),
+			("d.ron", r#"(scene: (objects: [oops]))"#),
+			("e.ron", r#"(include: [(path: "f.ron")], camera: (pos: [0, 0, 0], forward: [1, 0, 0], right: [0, 1, 0], up: [0, 0, 1]))"#),
+			("f.ron", r#"(scene: (objects: [(material: Named("gold"), shape: Sphere((pos: [3, 0, 0], radius: 1)))]))"#),
+		]);
+
+		let cycle = load_scene_data(dir.join("a.ron")).err().unwrap().to_string();
+		assert!(cycle.starts_with("Include cycle:") && cycle.ends_with("a.ron"), "{}", cycle);
+
+		let parse = load_scene_data(dir.join("c.ron")).err().unwrap().to_string();
+		assert!(parse.contains("d.ron: Encounter error while deserialize"), "{}", parse);
+
+		// diagnostic of included object name the file it is written in
+		let invalid = load_scene_data(dir.join("e.ron")).err().unwrap().to_string();
+		assert!(invalid.contains("f.ron:scene.objects[0].material: there is no material named 'gold'"), "{}", invalid);
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	// animation move included object inside the space include transform put it in
+	#[test]
+	fn include_animation_test() {
+		let dir = write_files("rtracer_include_animation_test", &[
+			("main.ron", r#"(
+				include: [(path: "prop.ron", transform: Some((isometry: (rotation: [0, 0, 0, 1], translation: [0, 5, 0]), scaling: 1)))],
+				camera: (pos: [0, 0, 0], forward: [1, 0, 0], right: [0, 1, 0], up: [0, 0, 1]),
+				animation: (objects: [(object: 0, translation: Some((keys: [
+					(frame: 0, value: [1, 0, 0]),
+					(frame: 10, value: [2, 0, 0]),
+				])))], materials: [
+					(object: 0, color: Some((keys: [(frame: 0, value: [1, 0, 0])]))),
+					(object: 1, color: Some((keys: [(frame: 0, value: [1, 0, 0])]))),
+				]),
+			)"#),
+			("prop.ron", r#"(scene: (objects: [
+				(material: Diffuse((color: [1, 1, 1])), shape: Sphere((pos: [4, 0, 0], radius: 0.5))),
+				(material: Reflective((roughness: 0.1, iteration: 2)), shape: Sphere((pos: [4, 2, 0], radius: 0.5))),
+			]))"#),
+		]);
+
+		let (data, report) = load_scene_data_with_report(dir.join("main.ron")).unwrap();
+		fs::remove_dir_all(&dir).unwrap();
+		// only the reflective sphere can't take a color
+		let warnings: Vec<_> = report.diagnostics().iter().map(|d| d.path.as_str()).filter(|p| p.contains(":animation.")).collect();
+		assert_eq!(warnings, vec![format!("{}:animation.materials[1].color", dir.join("main.ron").display())]);
+		let center = |frame| {
+			let posed = data.at_frame(frame);
+			posed.scene.iter_obj().next().unwrap().transform_at(0.0) * Point3::new(4.0, 0.0, 0.0)
+		};
+		assert_eq!(center(0), Point3::new(5.0, 5.0, 0.0));
+		assert_eq!(center(10), Point3::new(6.0, 5.0, 0.0));
+	}
+}
@@ -0,0 +1,230 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use nalgebra::Similarity3;
+use ron::de;
+use serde::{Deserialize, Deserializer};
+use serde::de::Error;
+use serde_json::Value;
+
+use super::{Include, SceneDocument, SceneParserError};
+use super::serde_interface::present;
+use super::super::{Camera, Color3, Group, Lights, Materials, Scene, SceneObject};
+use super::super::animation::Animation;
+use super::super::light::{AreaLight, DirectionalLight, PointLight, SpotLight};
+
+/*
+scene file carry the version of layout it was written in, file written before there was a version is version 1
+older document is read with the layout of its version, kept frozen below, then converted up to the current one,
+so camera, light or material can change shape without breaking file written by an older release
+
+version 2: area light write translation, rotation and scaling side by side instead of a nested Similarity3
+*/
+pub const CURRENT_VERSION: u32 = 2;
+
+// every field except version is skipped
+#[derive(Deserialize)]
+struct Versioned {
+	#[serde(default = "first_version")]
+	version: u32,
+}
+
+fn first_version() -> u32 {
+	1
+}
+
+fn check_version(version: u32) -> Result<u32, SceneParserError> {
+	if version == 0 {
+		return Err(SceneParserError::InvalidVersion {version: version.to_string()});
+	}
+	if version > CURRENT_VERSION {
+		return Err(SceneParserError::UnsupportedVersion {version, supported: CURRENT_VERSION});
+	}
+	Ok(version)
+}
+
+pub(crate) fn read_ron(text: &str) -> Result<SceneDocument, SceneParserError> {
+	// syntax error is left to reading the whole document
+	let version = de::from_str::<Versioned>(text).map_or(CURRENT_VERSION, |versioned| versioned.version);
+	Ok(match check_version(version)? {
+		1 => de::from_str::<SceneDocumentV1>(text)?.into(),
+		_ => de::from_str::<SceneDocument>(text)?,
+	})
+}
+
+pub(crate) fn read_json(value: Value) -> Result<SceneDocument, SceneParserError> {
+	let version = match value.get("version") {
+		None => first_version(),
+		Some(v) => v.as_u64()
+			.filter(|v| *v <= u32::MAX as u64)
+			.ok_or_else(|| SceneParserError::InvalidVersion {version: v.to_string()})? as u32,
+	};
+	Ok(match check_version(version)? {
+		1 => serde_json::from_value::<SceneDocumentV1>(value)?.into(),
+		_ => serde_json::from_value::<SceneDocument>(value)?,
+	})
+}
+
+/*
+layout of version 1, only what changed since has its own type here, the rest is shared with the current layout
+a shared type that change shape get frozen here first, along with everything holding it
+*/
+#[derive(Deserialize)]
+struct SceneDocumentV1 {
+	#[serde(default)]
+	include: Vec<Include>,
+	#[serde(default, deserialize_with = "present")]
+	scene: Option<SceneV1>,
+	#[serde(default, deserialize_with = "present")]
+	camera: Option<Camera>,
+	#[serde(default)]
+	animation: Animation,
+	#[serde(default)]
+	materials: BTreeMap<String, Arc<Materials>>,
+	#[serde(default, deserialize_with = "present")]
+	resolution: Option<(u32, u32)>,
+}
+
+#[derive(Deserialize)]
+struct SceneV1 {
+	#[serde(default)]
+	objects: Vec<SceneObject>,
+	#[serde(default)]
+	lights: Vec<LightsV1>,
+	#[serde(default = "Color3::zeros")]
+	skylight: Color3,
+	#[serde(default)]
+	groups: Vec<GroupV1>,
+}
+
+#[derive(Deserialize)]
+struct GroupV1 {
+	name: String,
+	#[serde(default = "Similarity3::identity")]
+	transform: Similarity3<f32>,
+	#[serde(default)]
+	objects: Vec<SceneObject>,
+	#[serde(default)]
+	lights: Vec<LightsV1>,
+	#[serde(default)]
+	children: Vec<GroupV1>,
+}
+
+#[derive(Deserialize)]
+enum LightsV1 {
+	PointLight(PointLight),
+	DirectionalLight(DirectionalLight),
+	AreaLight(AreaLightV1),
+	SpotLight(SpotLight),
+}
+
+#[derive(Deserialize)]
+struct AreaLightV1 {
+	#[serde(deserialize_with = "nonzero_scaling")]
+	transformer: Similarity3<f32>,
+	light: Color3,
+}
+
+// raw Similarity3 could hold zero scaling, which the current area light refuse as well
+fn nonzero_scaling<'de, D: Deserializer<'de>>(d: D) -> Result<Similarity3<f32>, D::Error> {
+	let transform = Similarity3::<f32>::deserialize(d)?;
+	if transform.scaling().is_nan() || transform.scaling().abs() <= 1e-6 {
+		return Err(D::Error::custom(format!("area light scaling must not be zero, got {}", transform.scaling())));
+	}
+	Ok(transform)
+}
+
+impl From<SceneDocumentV1> for SceneDocument {
+	fn from(doc: SceneDocumentV1) -> Self {
+		SceneDocument {
+			include: doc.include,
+			scene: doc.scene.map(Scene::from),
+			camera: doc.camera,
+			animation: doc.animation,
+			materials: doc.materials,
+			resolution: doc.resolution,
+		}
+	}
+}
+
+impl From<SceneV1> for Scene {
+	fn from(scene: SceneV1) -> Self {
+		let lights = scene.lights.into_iter().map(Lights::from).collect();
+		let mut upgraded = Scene::from_maybe_component(Some(scene.objects), Some(lights), Some(scene.skylight));
+		for group in scene.groups {
+			upgraded.add_group(group.into());
+		}
+		upgraded
+	}
+}
+
+impl From<GroupV1> for Group {
+	fn from(group: GroupV1) -> Self {
+		let mut upgraded = Group::new(group.name, group.transform);
+		for obj in group.objects {
+			upgraded.add_obj(obj);
+		}
+		for light in group.lights {
+			upgraded.add_light(light.into());
+		}
+		for child in group.children {
+			upgraded.add_child(child.into());
+		}
+		upgraded
+	}
+}
+
+impl From<LightsV1> for Lights {
+	fn from(light: LightsV1) -> Self {
+		match light {
+			LightsV1::PointLight(l) => l.into(),
+			LightsV1::DirectionalLight(l) => l.into(),
+			LightsV1::AreaLight(l) => {
+				let t = l.transformer;
+				AreaLight::new(t.isometry.translation.vector, t.isometry.rotation, t.scaling(), Some(l.light)).into()
+			},
+			LightsV1::SpotLight(l) => l.into(),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// same area light written as version 1 and as current is read to the same light
+	#[test]
+	fn area_light_migration_test() {
+		let v1 = "(scene: (lights: [AreaLight((transformer: (isometry: (rotation: [0, 0, 0, 1], translation: [1, 2, 3]), scaling: 0.5), light: [4, 4, 4]))]))";
+		let current = format!(
+			"(version: {}, scene: (lights: [AreaLight((translation: [1, 2, 3], rotation: [0, 0, 0, 1], scaling: 0.5, light: [4, 4, 4]))]))",
+			CURRENT_VERSION
+		);
+		let json = r#"{"version": 1, "scene": {"groups": [{"name": "g", "lights": [{"AreaLight": {"transformer": {"isometry": {"rotation": [0, 0, 0, 1], "translation": [1, 2, 3]}, "scaling": 0.5}, "light": [4, 4, 4]}}]}]}}"#;
+		let written: Vec<String> = vec![
+			read_ron(v1).unwrap(),
+			read_ron(&current).unwrap(),
+			read_json(serde_json::from_str(json).unwrap()).unwrap(),
+		].into_iter()
+			.map(|doc| ron::ser::to_string(doc.scene.unwrap().flatten().iter_light().next().unwrap()).unwrap())
+			.collect();
+		assert_eq!(written[0], "AreaLight((translation:[1,2,3,],rotation:[0,0,0,1,],scaling:0.5,light:[4,4,4,],))");
+		assert_eq!(written[0], written[1]);
+		assert_eq!(written[2], written[1]);
+
+		// zero scaling is refused in both layout instead of panicking
+		assert!(read_ron(&v1.replace("0.5", "0")).is_err());
+		assert!(read_ron(&current.replace("0.5", "0")).is_err());
+	}
+
+	#[test]
+	fn version_check_test() {
+		let newer = format!("(version: {}, scene: (objects: []))", CURRENT_VERSION + 1);
+		assert!(matches!(read_ron(&newer), Err(SceneParserError::UnsupportedVersion {..})));
+		let json = serde_json::from_str(&format!("{{\"version\": {}}}", CURRENT_VERSION + 1)).unwrap();
+		assert!(matches!(read_json(json), Err(SceneParserError::UnsupportedVersion {..})));
+		let json = serde_json::from_str("{\"version\": \"1\"}").unwrap();
+		assert!(matches!(read_json(json), Err(SceneParserError::InvalidVersion {..})));
+		assert!(matches!(read_ron("(version: 0)"), Err(SceneParserError::InvalidVersion {..})));
+	}
+}
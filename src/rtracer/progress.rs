@@ -0,0 +1,163 @@
+/*
+progress report and cancellation for application embedding the renderer
+
+observer is called by render thread after each finished row, from several thread at once.
+cancel token is checked before every pixel, a cancelled render stop within one pixel and
+still return what was rendered, pixel without sample is black
+*/
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Debug)]
+pub struct Progress {
+	pub rows_done: u32,
+	pub rows_total: u32,
+	// every ray cast so far, camera ray and secondary ray alike
+	pub rays: u64,
+	pub elapsed: Duration,
+}
+
+impl Progress {
+	pub fn fraction(&self) -> f32 {
+		if self.rows_total == 0 { 1.0 } else { self.rows_done as f32 / self.rows_total as f32 }
+	}
+
+	// remaining time if the rest of the rows cost like the finished one
+	pub fn eta(&self) -> Option<Duration> {
+		if self.rows_done == 0 {
+			return None;
+		}
+		Some(self.elapsed.mul_f64((self.rows_total - self.rows_done) as f64 / self.rows_done as f64))
+	}
+}
+
+pub trait ProgressObserver: Sync {
+	fn on_progress(&self, progress: &Progress);
+}
+
+impl<F: Fn(&Progress) + Sync> ProgressObserver for F {
+	fn on_progress(&self, progress: &Progress) {
+		self(progress)
+	}
+}
+
+// cheap to clone, every clone cancel the same render
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	pub fn cancel(&self) {
+		self.0.store(true, Ordering::Relaxed);
+	}
+
+	pub fn is_cancelled(&self) -> bool {
+		self.0.load(Ordering::Relaxed)
+	}
+}
+
+// hook of the embedding application into a render, both part are optional
+#[derive(Clone, Default)]
+pub struct RenderControl<'a> {
+	pub observer: Option<&'a dyn ProgressObserver>,
+	pub cancel: Option<CancelToken>,
+}
+
+impl RenderControl<'_> {
+	pub fn is_cancelled(&self) -> bool {
+		matches!(&self.cancel, Some(cancel) if cancel.is_cancelled())
+	}
+}
+
+// shared by render thread for one pass over the image
+pub(crate) struct ProgressCounter<'a> {
+	observer: Option<&'a dyn ProgressObserver>,
+	start: Instant,
+	rows_total: u32,
+	rows_done: AtomicU32,
+	rays: AtomicU64,
+}
+
+impl<'a> ProgressCounter<'a> {
+	pub(crate) fn new(control: &RenderControl<'a>, rows_total: u32) -> Self {
+		ProgressCounter {
+			observer: control.observer,
+			start: Instant::now(),
+			rows_total,
+			rows_done: AtomicU32::new(0),
+			rays: AtomicU64::new(0),
+		}
+	}
+
+	pub(crate) fn finish_row(&self, rays: u64) {
+		let rows_done = self.rows_done.fetch_add(1, Ordering::Relaxed) + 1;
+		let rays = self.rays.fetch_add(rays, Ordering::Relaxed) + rays;
+		if let Some(observer) = self.observer {
+			observer.on_progress(&Progress {rows_done, rows_total: self.rows_total, rays, elapsed: self.start.elapsed()});
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::Mutex;
+
+	use nalgebra::Point3;
+
+	use crate::rtracer::{Aov, Camera, Color3, Denoise, render_controlled, RenderSettings, Scene, SceneObject};
+	use crate::rtracer::geometric::Sphere;
+	use crate::rtracer::light::PointLight;
+	use crate::rtracer::material::Diffuse;
+
+	use super::*;
+
+	// every row is reported once, and cancelling from the observer stop the render with a partial image
+	#[test]
+	fn progress_and_cancel_test() {
+		let mut scene = Scene::new();
+		scene.add_obj(SceneObject::new(Sphere {pos: Point3::new(3.0, 0.0, 0.0), radius: 1.0}, Diffuse::new(Color3::repeat(1.0))));
+		scene.add_light(PointLight::new(Point3::origin(), Color3::repeat(1.0)).into());
+		let camera = Camera::new(Point3::origin(), nalgebra::Rotation3::identity());
+		let mut settings = RenderSettings::new(8, 8);
+		settings.threads = 1;
+
+		let reports = Mutex::new(Vec::new());
+		let observer = |progress: &Progress| reports.lock().unwrap().push(*progress);
+		let full = render_controlled(&scene, &camera, &settings, &RenderControl {observer: Some(&observer), cancel: None}).unwrap();
+		assert!(!full.cancelled);
+		let reports = reports.into_inner().unwrap();
+		assert_eq!(reports.len(), 8);
+		assert_eq!(reports.last().unwrap().fraction(), 1.0);
+		assert!(reports.last().unwrap().rays >= 64);
+
+		let cancel = CancelToken::new();
+		let observer = |progress: &Progress| if progress.rows_done == 2 { cancel.cancel() };
+		let control = RenderControl {observer: Some(&observer), cancel: Some(cancel.clone())};
+		let output = render_controlled(&scene, &camera, &settings, &control).unwrap();
+		assert!(output.cancelled);
+		// row 0 and 1 are rendered, the rest is left black
+		for (x, y, pixel) in output.radiance.enumerate_pixels() {
+			let expected = if y < 2 { full.radiance.get_pixel(x, y)[0] } else { 0.0 };
+			assert_eq!(pixel[0], expected);
+		}
+		assert!(full.radiance.pixels().any(|p| p[0] > 0.0));
+
+		// aov and denoiser of a partial image see black, not 0 / 0, where nothing was sampled
+		settings.aovs = vec![Aov::Albedo, Aov::Normal, Aov::DirectDiffuse, Aov::Shadow];
+		settings.denoise = Some(Denoise::default());
+		let cancel = CancelToken::new();
+		let observer = |progress: &Progress| if progress.rows_done == 2 { cancel.cancel() };
+		let control = RenderControl {observer: Some(&observer), cancel: Some(cancel.clone())};
+		let output = render_controlled(&scene, &camera, &settings, &control).unwrap();
+		assert!(output.cancelled);
+		let images = std::iter::once(&output.radiance).chain(output.aovs.iter().map(|(_, image)| image));
+		for image in images {
+			assert!(image.pixels().all(|p| p.data.iter().all(|v| !v.is_nan())));
+		}
+		assert!(output.radiance.enumerate_pixels().all(|(_, y, p)| y < 2 || p[0] == 0.0));
+	}
+}
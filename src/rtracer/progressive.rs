@@ -0,0 +1,246 @@
+/*
+progressive rendering, the whole image is refined in pass of 1, 2, 4 ... spp so a usable picture exist early
+
+render stop after the pass reaching target spp, once average pixel error is below target error,
+or before a pass that wouldn't fit in the time budget anymore.
+accumulated sample is kept in a checkpoint that can be saved after each pass. sample only depend on
+(seed, pixel, sample index), so a resumed render end up exactly like one that was never interrupted,
+even when it was cancelled in the middle of a pass
+*/
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use custom_error::custom_error;
+use serde::{Deserialize, Serialize};
+
+use super::Camera;
+use super::aov::Aov;
+use super::light_selection::LightSelection;
+use super::mis::MisHeuristic;
+use super::progress::RenderControl;
+use super::renderer::{Accumulation, check_crop, check_materials, check_region, in_thread_pool, Integrator, RenderContext, RenderError, RenderOutput, RenderSettings};
+use super::sampler::SamplerKind;
+use super::scene::Scene;
+
+custom_error!{ pub CheckpointError
+	Io {source: io::Error} = "unable to access checkpoint: {source}",
+	Json {source: serde_json::Error} = "invalid checkpoint: {source}",
+	Mismatch {what: String} = "checkpoint was rendered with different {what}",
+	Render {source: RenderError} = "{source}",
+}
+
+// adaptive and spp of render settings are ignored, progressive has its own stopping rule
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Progressive {
+	pub target_spp: u32,
+	// average relative standard error of pixel, see adaptive.rs
+	pub target_error: Option<f32>,
+	pub time_budget: Option<Duration>,
+}
+
+impl Default for Progressive {
+	fn default() -> Self {
+		Progressive {target_spp: 256, target_error: None, time_budget: None}
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Checkpoint {
+	// camera ray of a pixel depend on frame size and viewport, accumulation may only cover a crop of it
+	frame: (u32, u32),
+	viewport_size: f32,
+	// sampler stratify over target spp, continuing with another one would break the pattern
+	sampler: SamplerKind,
+	target_spp: u32,
+	// sample computed another way can't be averaged with the one already accumulated
+	integrator: Integrator,
+	light_selection: LightSelection,
+	mis_heuristic: MisHeuristic,
+	// include guide of denoiser
+	aovs: Vec<Aov>,
+	accumulation: Accumulation,
+}
+
+impl Checkpoint {
+	// sample every pixel has
+	pub fn spp(&self) -> u32 {
+		self.accumulation.min_spp()
+	}
+
+	pub fn seed(&self) -> u64 {
+		self.accumulation.seed
+	}
+
+	// written next to the target then renamed, so a render killed while saving keep the previous checkpoint
+	pub fn save(&self, path: &Path) -> Result<(), CheckpointError> {
+		let tmp = path.with_extension("tmp");
+		fs::write(&tmp, serde_json::to_vec(self)?)?;
+		fs::rename(&tmp, path)?;
+		Ok(())
+	}
+
+	pub fn load(path: &Path) -> Result<Self, CheckpointError> {
+		Ok(serde_json::from_slice(&fs::read(path)?)?)
+	}
+
+	fn _check(&self, settings: &RenderSettings, progressive: &Progressive, aovs: &[Aov]) -> Result<(), CheckpointError> {
+		let mismatch = |what: &str| Err(CheckpointError::Mismatch {what: what.to_string()});
+		if self.frame != (settings.width, settings.height) {
+			return mismatch("resolution");
+		}
+		if self.viewport_size != settings.viewport_size {
+			return mismatch("viewport size");
+		}
+		let accumulation = &self.accumulation;
+		if (accumulation.x, accumulation.y, accumulation.width, accumulation.height) != settings.region() {
+			return mismatch("crop window");
+		}
+		if self.sampler != settings.sampler {
+			return mismatch("sampler");
+		}
+		if self.target_spp != progressive.target_spp {
+			return mismatch("target spp");
+		}
+		if self.integrator != settings.integrator {
+			return mismatch("integrator");
+		}
+		if self.light_selection != settings.light_selection {
+			return mismatch("light selection");
+		}
+		if self.mis_heuristic != settings.mis_heuristic {
+			return mismatch("mis heuristic");
+		}
+		if self.aovs != aovs {
+			return mismatch("aovs");
+		}
+		Ok(())
+	}
+}
+
+// on_pass get the image and checkpoint after every complete pass, to save intermediate result
+// return the final image and checkpoint, seed of resumed render is the one of checkpoint
+// progress of control is reported per pass
+pub fn render_progressive(
+	scene: &Scene,
+	camera: &Camera,
+	settings: &RenderSettings,
+	progressive: &Progressive,
+	resume: Option<Checkpoint>,
+	control: &RenderControl,
+	mut on_pass: impl FnMut(&RenderOutput, &Checkpoint))
+	-> Result<(RenderOutput, Checkpoint), CheckpointError> {
+	let scene = &*scene.flatten();
+	check_materials(scene)?;
+	check_crop(settings)?;
+	check_region(settings)?;
+	let target_spp = RenderSettings {spp: progressive.target_spp, ..settings.clone()}.effective_spp(camera);
+	let context = RenderContext::new(scene, camera, settings, target_spp);
+	let mut checkpoint = match resume {
+		Some(checkpoint) => {
+			checkpoint._check(settings, progressive, context.aov_kinds())?;
+			checkpoint
+		},
+		None => Checkpoint {
+			frame: (settings.width, settings.height),
+			viewport_size: settings.viewport_size,
+			sampler: settings.sampler,
+			target_spp: progressive.target_spp,
+			integrator: settings.integrator,
+			light_selection: settings.light_selection,
+			mis_heuristic: settings.mis_heuristic,
+			aovs: context.aov_kinds().to_vec(),
+			accumulation: context.empty_accumulation(None),
+		},
+	};
+
+	let start = Instant::now();
+	let mut last_output = None;
+	// time one sample of every pixel took in last pass
+	let mut sample_time = None;
+	loop {
+		let spp = checkpoint.spp();
+		let next = if spp == 0 { 1 } else { (spp * 2).min(target_spp) };
+		if spp >= target_spp || matches!(progressive.target_error, Some(e) if spp >= 2 && checkpoint.accumulation.mean_error() <= e) {
+			break;
+		}
+		if let (Some(budget), Some(sample_time)) = (progressive.time_budget, sample_time) {
+			if start.elapsed() + sample_time * (next - spp) > budget {
+				break;
+			}
+		}
+
+		let pass_start = Instant::now();
+		let cancelled = in_thread_pool(settings, || context.sample(&mut checkpoint.accumulation, control, |e| e.count() >= next));
+		if cancelled {
+			let output = RenderOutput {cancelled, ..context.output(&checkpoint.accumulation)};
+			return Ok((output, checkpoint));
+		}
+		sample_time = Some(pass_start.elapsed() / (next - spp));
+
+		let output = context.output(&checkpoint.accumulation);
+		on_pass(&output, &checkpoint);
+		last_output = Some(output);
+	}
+
+	let output = last_output.unwrap_or_else(|| context.output(&checkpoint.accumulation));
+	Ok((output, checkpoint))
+}
+
+#[cfg(test)]
+mod tests {
+	use nalgebra::{Point3, UnitQuaternion, Vector3};
+
+	use crate::rtracer::{Color3, SceneObject};
+	use crate::rtracer::geometric::InfinitePlane;
+	use crate::rtracer::light::AreaLight;
+	use crate::rtracer::material::Diffuse;
+
+	use super::*;
+
+	// render stopped after the 2 spp pass then resumed give the same image as one straight to 4 spp
+	#[test]
+	fn resume_checkpoint_test() {
+		let mut scene = Scene::new();
+		scene.add_obj(SceneObject::new(
+			InfinitePlane {pos: Point3::new(0.0, 0.0, -1.0), norm: Vector3::z_axis()},
+			Diffuse::new(Color3::repeat(0.8))
+		));
+		let rotation = UnitQuaternion::from_axis_angle(&Vector3::y_axis(), std::f32::consts::FRAC_PI_2);
+		scene.add_light(AreaLight::new(Vector3::new(3.0, 0.0, 1.0), rotation, 0.5, Some(Color3::repeat(4.0))).into());
+		let camera = Camera::new(Point3::origin(), nalgebra::Rotation3::identity());
+		let settings = RenderSettings::new(8, 6);
+		let progressive = Progressive {target_spp: 4, ..Progressive::default()};
+
+		let mut passes = Vec::new();
+		let (full, _) = render_progressive(&scene, &camera, &settings, &progressive, None, &RenderControl::default(), |_, checkpoint| {
+			passes.push(checkpoint.clone());
+		}).unwrap();
+		assert_eq!(passes.iter().map(|c| c.spp()).collect::<Vec<_>>(), vec![1, 2, 4]);
+
+		let path = std::env::temp_dir().join(format!("rtracer_checkpoint_{}.json", std::process::id()));
+		passes[1].save(&path).unwrap();
+		let checkpoint = Checkpoint::load(&path).unwrap();
+		fs::remove_file(&path).unwrap();
+		let (resumed, checkpoint) = render_progressive(&scene, &camera, &settings, &progressive, Some(checkpoint), &RenderControl::default(), |_, _| {}).unwrap();
+		assert_eq!(checkpoint.spp(), 4);
+		assert_eq!(resumed.radiance.into_raw(), full.radiance.into_raw());
+
+		let other = Progressive {target_spp: 8, ..progressive};
+		assert!(render_progressive(&scene, &camera, &settings, &other, Some(checkpoint.clone()), &RenderControl::default(), |_, _| {}).is_err());
+
+		// sample of another integrator or without denoise guide can't join the accumulation
+		let resume = |settings: &RenderSettings| {
+			render_progressive(&scene, &camera, settings, &progressive, Some(checkpoint.clone()), &RenderControl::default(), |_, _| {})
+				.err()
+				.map(|e| e.to_string())
+		};
+		let other = RenderSettings {integrator: Integrator::Normal, ..settings.clone()};
+		assert_eq!(resume(&other).as_deref(), Some("checkpoint was rendered with different integrator"));
+		let other = RenderSettings {light_selection: LightSelection::Power, ..settings.clone()};
+		assert_eq!(resume(&other).as_deref(), Some("checkpoint was rendered with different light selection"));
+		let other = RenderSettings {denoise: Some(crate::rtracer::Denoise::default()), ..settings.clone()};
+		assert_eq!(resume(&other).as_deref(), Some("checkpoint was rendered with different aovs"));
+	}
+}
@@ -1,11 +1,27 @@
+use super::light_selection::{EVERY_LIGHT, LightSelector};
+use super::mis::MisHeuristic;
+
 #[derive(Copy, Clone)]
-pub struct RayCastInfo {
-    ray_number: usize
+pub struct RayCastInfo<'a> {
+    ray_number: usize,
+    time: f32,
+    heuristic: MisHeuristic,
+    lights: &'a LightSelector,
 }
 
-impl RayCastInfo {
-    pub fn new() -> Self {
-        RayCastInfo {ray_number: 0}
+impl<'a> RayCastInfo<'a> {
+    pub fn at_time(time: f32) -> Self {
+        RayCastInfo {ray_number: 0, time, heuristic: MisHeuristic::Power, lights: &EVERY_LIGHT}
+    }
+
+    pub fn with_heuristic(mut self, heuristic: MisHeuristic) -> Self {
+        self.heuristic = heuristic;
+        self
+    }
+
+    pub fn with_lights(mut self, lights: &'a LightSelector) -> Self {
+        self.lights = lights;
+        self
     }
 
     pub fn increment_ray_number(&mut self) {
@@ -15,4 +31,19 @@ impl RayCastInfo {
     pub fn ray_depth(&self) -> usize {
         self.ray_number
     }
-}
\ No newline at end of file
+
+    // time (within camera shutter) that this ray is cast
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    // how light sample and bsdf sample are weighted against each other
+    pub fn heuristic(&self) -> MisHeuristic {
+        self.heuristic
+    }
+
+    // which light a shading point sample, every light unless a selector is given
+    pub fn lights(&self) -> &'a LightSelector {
+        self.lights
+    }
+}
@@ -1,4 +1,5 @@
 use std::cmp::Ordering::Equal;
+use std::str::FromStr;
 
 use image::ImageBuffer;
 use image::imageops::{BiLevel, blur, dither};
@@ -9,43 +10,526 @@ use itertools::MinMaxResult::*;
 use nalgebra::{Point3, Unit, Vector3};
 use rand::prelude::{Rng, SmallRng};
 use rand::SeedableRng;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use custom_error::custom_error;
 
 use crate::rtracer::{material::Material, RayCastInfo, SceneObject};
 
 use super::Camera;
 use super::Color3;
 use super::HitInfo;
+use super::adaptive::{Adaptive, PixelEstimate};
+use super::aov::{self, Aov, AovContext, AovImage};
+use super::denoise::Denoise;
+use super::integrator;
 use super::light::Light;
+use super::light_selection::{LightSelection, LightSelector};
+use super::material::MaterialRef;
+use super::mis::MisHeuristic;
+use super::progress::{ProgressCounter, RenderControl};
+use super::sampler::{Sampler, SamplerKind, Samplers};
 use super::scene::Scene;
-use super::shape::Shape;
+use super::stats;
+use super::MOTION_BLUR_SAMPLE;
 
 pub type RenderImage = ImageBuffer<Rgb<u8>, Vec<u8>>;
 type RenderBuffer = ImageBuffer<Rgb<f32>, Vec<f32>>;
 
-// TODO: extract per-ray render part for improving reusability
-pub fn render(scene: &Scene, camera: &Camera, image_size: u32, unit_per_pixel: f32) -> RenderImage {
-	
-	let mut img: RenderBuffer = ImageBuffer::new(image_size, image_size);
-	let mut rng = SmallRng::from_entropy();
-	let raycast_info = RayCastInfo::new();
+// light transport algorithm used to compute color of camera ray
+// everything but Whitted is a lookdev / debug view, see integrator.rs
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Integrator {
+	// direct light on diffuse surface + recursive reflection
+	Whitted,
+	AmbientOcclusion {radius: f32, samples: u32},
+	Normal,
+	Depth,
+	Uv,
+	ObjectId,
+	// intersection test per camera ray
+	Heatmap,
+}
+
+impl FromStr for Integrator {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"whitted" => Ok(Integrator::Whitted),
+			"ao" => Ok(Integrator::AmbientOcclusion {radius: 1.0, samples: 16}),
+			"normal" => Ok(Integrator::Normal),
+			"depth" => Ok(Integrator::Depth),
+			"uv" => Ok(Integrator::Uv),
+			"object-id" => Ok(Integrator::ObjectId),
+			"heatmap" => Ok(Integrator::Heatmap),
+			_ => Err(format!(
+				"unknown integrator '{}', expected one of: whitted, ao, normal, depth, uv, object-id, heatmap", s
+			)),
+		}
+	}
+}
+
+// how f32 radiance is mapped into u8 color
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ToneMap {
+	// linearly map [min, max] to [0, 255], missing bound is taken from image
+	Normalize(Option<f32>, Option<f32>),
+	// scale by exposure then clamp to [0, 1]
+	Clamp(f32),
+	// scale by exposure then x / (1 + x)
+	Reinhard(f32),
+}
+
+// part of the frame to render, camera ray of each pixel stay the one of the full frame
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CropWindow {
+	// x, y, width, height in pixel
+	Pixels(u32, u32, u32, u32),
+	// x0, y0, x1, y1 as fraction of frame size
+	Normalized(f32, f32, f32, f32),
+}
+
+impl CropWindow {
+	// pixel rectangle (x, y, width, height) inside a frame, never empty
+	pub fn rect(&self, width: u32, height: u32) -> (u32, u32, u32, u32) {
+		let (x0, y0, x1, y1) = match *self {
+			CropWindow::Pixels(x, y, w, h) => (x, y, x.saturating_add(w), y.saturating_add(h)),
+			// pixel partly inside the window is rendered
+			CropWindow::Normalized(x0, y0, x1, y1) => (
+				(x0.clamp(0.0, 1.0) * width as f32).floor() as u32,
+				(y0.clamp(0.0, 1.0) * height as f32).floor() as u32,
+				(x1.clamp(0.0, 1.0) * width as f32).ceil() as u32,
+				(y1.clamp(0.0, 1.0) * height as f32).ceil() as u32,
+			),
+		};
+		let (x0, y0) = (x0.min(width.saturating_sub(1)), y0.min(height.saturating_sub(1)));
+		let (x1, y1) = (x1.clamp(x0 + 1, width.max(1)), y1.clamp(y0 + 1, height.max(1)));
+		(x0, y0, x1 - x0, y1 - y0)
+	}
+}
+
+// "x,y,width,height" in pixel, or "x0,y0,x1,y1" with decimal point for normalized
+impl FromStr for CropWindow {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let parts: Vec<&str> = s.split(',').map(str::trim).collect();
+		let error = || format!("invalid crop window '{}', expected x,y,width,height or x0,y0,x1,y1 like 0.25,0.25,0.75,0.75", s);
+		if parts.len() != 4 {
+			return Err(error());
+		}
+		if parts.iter().any(|p| p.contains('.')) {
+			let v: Vec<f32> = parts.iter().map(|p| p.parse()).collect::<Result<_, _>>().map_err(|_| error())?;
+			Ok(CropWindow::Normalized(v[0], v[1], v[2], v[3]))
+		} else {
+			let v: Vec<u32> = parts.iter().map(|p| p.parse()).collect::<Result<_, _>>().map_err(|_| error())?;
+			Ok(CropWindow::Pixels(v[0], v[1], v[2], v[3]))
+		}
+	}
+}
+
+#[derive(Clone, Debug)]
+pub struct RenderSettings {
+	pub width: u32,
+	pub height: u32,
+	// width of image plane at distance 1 from camera
+	pub viewport_size: f32,
+	// sample per pixel, each sample get its own sub-pixel position and shutter time
+	pub spp: u32,
+	// None = random seed, same seed always give the same image
+	pub seed: Option<u64>,
+	pub sampler: SamplerKind,
+	// 0 = use every available core
+	pub threads: usize,
+	pub integrator: Integrator,
+	// weighting of light and bsdf sample of area light
+	pub mis_heuristic: MisHeuristic,
+	// how shading point choose which light to sample
+	pub light_selection: LightSelection,
+	// extra pass rendered along beauty, see render_with_aovs
+	pub aovs: Vec<Aov>,
+	// filter radiance guided by albedo and normal, None = keep it as sampled
+	pub denoise: Option<Denoise>,
+	// sample noisy pixel more, spp is ignored when set
+	pub adaptive: Option<Adaptive>,
+	// render only this region, output is region sized unless crop_full_frame
+	// tone map must have fixed bound so the region match the full frame, see check_crop
+	pub crop: Option<CropWindow>,
+	// put the region in a black image of the full frame size instead
+	pub crop_full_frame: bool,
+	pub tone_map: ToneMap,
+}
+
+impl RenderSettings {
+	pub fn new(width: u32, height: u32) -> Self {
+		RenderSettings {
+			width,
+			height,
+			viewport_size: 2.0,
+			spp: 1,
+			seed: None,
+			sampler: SamplerKind::Independent,
+			threads: 0,
+			integrator: Integrator::Whitted,
+			mis_heuristic: MisHeuristic::Power,
+			light_selection: LightSelection::All,
+			aovs: Vec::new(),
+			denoise: None,
+			adaptive: None,
+			crop: None,
+			crop_full_frame: false,
+			tone_map: ToneMap::Normalize(Some(0.0), None),
+		}
+	}
+
+	pub fn unit_per_pixel(&self) -> f32 {
+		self.viewport_size / self.width as f32
+	}
+
+	// sample taken per pixel, open shutter need several shutter time for blur whatever spp say
+	pub fn effective_spp(&self, camera: &Camera) -> u32 {
+		if camera.has_shutter_interval() { self.spp.max(MOTION_BLUR_SAMPLE) } else { self.spp.max(1) }
+	}
+
+	// pixel rectangle (x, y, width, height) that is rendered, whole frame without crop
+	pub fn region(&self) -> (u32, u32, u32, u32) {
+		match self.crop {
+			Some(crop) => crop.rect(self.width, self.height),
+			None => (0, 0, self.width, self.height),
+		}
+	}
+}
+
+// everything a render produce, image is radiance after tone mapping
+pub struct RenderOutput {
+	pub image: RenderImage,
+	pub radiance: AovImage,
+	// one image per aov of settings, in the same order
+	pub aovs: Vec<(Aov, AovImage)>,
+	// image before denoising, when settings ask to keep it
+	pub noisy: Option<RenderImage>,
+	// render was stopped by its cancel token, image is partial
+	pub cancelled: bool,
+}
+
+custom_error!{ pub RenderError
+	UnknownMaterial {names: String} = "no material named {names}, resolve named material against material library before rendering",
+	CropAutoExposure {what: String} = "crop can't use {what}, it would be scaled to the crop alone and not match the full frame",
+	EmptyRegion {width: u32, height: u32} = "nothing to render, region is {width}x{height} pixel",
+}
+
+pub fn render(scene: &Scene, camera: &Camera, settings: &RenderSettings) -> Result<RenderImage, RenderError> {
+	render_with_aovs(scene, camera, settings).map(|output| output.image)
+}
+
+pub fn render_with_aovs(scene: &Scene, camera: &Camera, settings: &RenderSettings) -> Result<RenderOutput, RenderError> {
+	render_controlled(scene, camera, settings, &RenderControl::default())
+}
+
+// report progress to the observer of control, and stop early once its token is cancelled
+pub fn render_controlled(
+	scene: &Scene,
+	camera: &Camera,
+	settings: &RenderSettings,
+	control: &RenderControl)
+	-> Result<RenderOutput, RenderError> {
+	let scene = &*scene.flatten();
+	check_materials(scene)?;
+	check_crop(settings)?;
+	check_region(settings)?;
+	Ok(in_thread_pool(settings, || {
+		// most sample any pixel can take, sampler stratify over all of them
+		let spp = settings.adaptive.map_or(settings.effective_spp(camera), |adaptive| adaptive.max_spp());
+		let context = RenderContext::new(scene, camera, settings, spp);
+		let mut accumulation = context.empty_accumulation(None);
+		let cancelled = context.sample(&mut accumulation, control, |estimate| match settings.adaptive {
+			Some(adaptive) => adaptive.is_done(estimate),
+			None => estimate.count() >= spp,
+		});
+		RenderOutput {cancelled, ..context.output(&accumulation)}
+	}))
+}
+
+// named material that was never resolved would render black, SceneData::resolve_materials resolve loaded scene
+pub(crate) fn check_materials(scene: &Scene) -> Result<(), RenderError> {
+	let names: Vec<String> = scene.iter_obj()
+		.filter_map(|obj| match &obj.material {
+			MaterialRef::Named(name, None) => Some(format!("'{}'", name)),
+			_ => None,
+		})
+		.unique()
+		.collect();
+	if names.is_empty() { Ok(()) } else { Err(RenderError::UnknownMaterial {names: names.join(", ")}) }
+}
+
+// bound taken from the image would come from the crop only, so a re-rendered region couldn't go back into the frame
+pub(crate) fn check_crop(settings: &RenderSettings) -> Result<(), RenderError> {
+	if settings.crop.is_none() {
+		return Ok(());
+	}
+	match (settings.integrator, settings.tone_map) {
+		(Integrator::Heatmap, _) => Err(RenderError::CropAutoExposure {what: "heatmap".to_string()}),
+		(_, ToneMap::Normalize(None, _)) | (_, ToneMap::Normalize(_, None)) =>
+			Err(RenderError::CropAutoExposure {what: "normalize tone map with bound taken from the image".to_string()}),
+		_ => Ok(()),
+	}
+}
+
+// zero sized image leave no pixel row to split among thread, crop is never empty on a non empty frame
+pub(crate) fn check_region(settings: &RenderSettings) -> Result<(), RenderError> {
+	if settings.width == 0 || settings.height == 0 {
+		return Err(RenderError::EmptyRegion {width: settings.width, height: settings.height});
+	}
+	Ok(())
+}
+
+// run on settings.threads thread, 0 = rayon's global pool with every core
+pub(crate) fn in_thread_pool<R: Send>(settings: &RenderSettings, f: impl FnOnce() -> R + Send) -> R {
+	if settings.threads == 0 {
+		return f();
+	}
+
+	match rayon::ThreadPoolBuilder::new().num_threads(settings.threads).build() {
+		Ok(pool) => pool.install(f),
+		Err(_) => f(),
+	}
+}
+
+// sample taken so far of every pixel, more can be added to it any time
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Accumulation {
+	// region of the frame, see RenderSettings::region
+	pub x: u32,
+	pub y: u32,
+	pub width: u32,
+	pub height: u32,
+	// every sample is derived from it, continuing with another seed would repeat sample pattern
+	pub seed: u64,
+	// row major
+	pub(crate) estimates: Vec<PixelEstimate>,
+	// sum of each aov (settings aovs, then denoise guide) of every pixel
+	pub(crate) passes: Vec<Vec<Color3>>,
+}
+
+impl Accumulation {
+	pub fn min_spp(&self) -> u32 {
+		self.estimates.iter().map(|e| e.count()).min().unwrap_or(0)
+	}
+
+	// average relative standard error of pixel, see adaptive.rs
+	pub fn mean_error(&self) -> f32 {
+		let total: f32 = self.estimates.iter().map(|e| e.error().min(1.0)).sum();
+		total / self.estimates.len().max(1) as f32
+	}
+}
+
+// what every pass of a render share, built once
+pub(crate) struct RenderContext<'a> {
+	scene: &'a Scene,
+	camera: &'a Camera,
+	settings: &'a RenderSettings,
+	lights: LightSelector,
+	aov_kinds: Vec<Aov>,
+	aov: AovContext<'a>,
+	// sample count sampler stratify over
+	spp: u32,
+}
+
+impl<'a> RenderContext<'a> {
+	// scene should be flattened already
+	pub(crate) fn new(scene: &'a Scene, camera: &'a Camera, settings: &'a RenderSettings, spp: u32) -> Self {
+		// denoiser need albedo and normal even if they aren't asked for
+		let mut aov_kinds = settings.aovs.clone();
+		if settings.denoise.is_some() {
+			for guide in &[Aov::Albedo, Aov::Normal] {
+				if !aov_kinds.contains(guide) {
+					aov_kinds.push(*guide);
+				}
+			}
+		}
+		RenderContext {
+			scene,
+			camera,
+			settings,
+			lights: LightSelector::build(scene, settings.light_selection),
+			aov: AovContext::new(scene, &aov_kinds),
+			aov_kinds,
+			spp,
+		}
+	}
+
+	// aov asked for and guide of denoiser, in order of accumulation
+	pub(crate) fn aov_kinds(&self) -> &[Aov] {
+		&self.aov_kinds
+	}
+
+	// without seed pick one at random, every sample is still derived from it
+	pub(crate) fn empty_accumulation(&self, seed: Option<u64>) -> Accumulation {
+		let (x, y, width, height) = self.settings.region();
+		let seed = seed.or(self.settings.seed).unwrap_or_else(|| SmallRng::from_entropy().gen());
+		let count = (width * height) as usize;
+		Accumulation {
+			x,
+			y,
+			width,
+			height,
+			seed,
+			estimates: (0..count).map(|_| PixelEstimate::new()).collect(),
+			passes: (0..count).map(|_| self.aov.empty_pixel()).collect(),
+		}
+	}
+
+	// add sample to every pixel until done say it has enough
+	// each row is rendered in parallel with its own sampler
+	// sample only depend on pixel and sample index, not on which pixel was rendered before it
+	// true when cancelled, pixel after the cancel have fewer sample than asked
+	pub(crate) fn sample(
+		&self,
+		accumulation: &mut Accumulation,
+		control: &RenderControl,
+		done: impl Fn(&PixelEstimate) -> bool + Sync)
+		-> bool {
+		let (width, seed) = (accumulation.width as usize, accumulation.seed);
+		// pixel keep its frame coordinate, so crop sample the same as full frame
+		let (x, y) = (accumulation.x, accumulation.y);
+		let progress = ProgressCounter::new(control, accumulation.height);
+		accumulation.estimates.par_chunks_mut(width)
+			.zip(accumulation.passes.par_chunks_mut(width))
+			.enumerate()
+			.for_each(|(row, (estimates, passes))| {
+				let py = y + row as u32;
+				let mut sampler = self.settings.sampler.build(seed, self.spp);
+				// ray of whatever ran on this thread before
+				stats::take_rays();
+				for (column, (estimate, passes)) in estimates.iter_mut().zip(passes.iter_mut()).enumerate() {
+					let px = x + column as u32;
+					if control.is_cancelled() {
+						return;
+					}
+					while !done(estimate) {
+						let light = self._sample_pixel(px, py, estimate.count(), passes, &mut sampler);
+						estimate.add(light);
+					}
+				}
+				progress.finish_row(stats::take_rays());
+			});
+		control.is_cancelled()
+	}
+
+	fn _sample_pixel(&self, px: u32, py: u32, i: u32, passes: &mut [Color3], sampler: &mut Samplers) -> Color3 {
+		let (scene, camera, settings, aov) = (self.scene, self.camera, self.settings, &self.aov);
+		sampler.start_sample(px, py, i);
+
+		// single sample keep looking through pixel corner like before
+		let (dx, dy) = if self.spp > 1 { sampler.get_2d() } else { (0.0, 0.0) };
+
+		// time sample in shutter interval
+		let time = if camera.has_shutter_interval() {
+			camera.shutter_time(sampler.get_1d())
+		} else {
+			camera.shutter_open
+		};
 
-	let half_width = img.width()/2;
-	let half_height = img.height()/2;
-	
-	for (px, py, pixel) in img.enumerate_pixels_mut() {
 		// get ray from camera
-		let ray_dir =
-			camera.ray_at_pixel_position(px, py, unit_per_pixel, half_width, half_height);
-		
+		let (ray_origin, ray_dir) = camera.ray_at_time(
+			px as f32 + dx, py as f32 + dy, settings.unit_per_pixel(), settings.width / 2, settings.height / 2, time
+		);
+
 		// raycast!
-		let light = raycast_compute_light(scene, camera.pos, ray_dir, raycast_info, &mut rng);
-		*pixel = Rgb([light[0], light[1], light[2]]);
+		let info = RayCastInfo::at_time(time).with_heuristic(settings.mis_heuristic).with_lights(&self.lights);
+		let (light, split) = match settings.integrator {
+			// lighting pass is split while computing beauty, so they match exactly
+			Integrator::Whitted if aov.needs_split() => {
+				let (light, split) = aov::trace_split(scene, ray_origin, ray_dir, info, sampler);
+				(light, Some(split))
+			},
+			Integrator::Whitted => (raycast_compute_light(scene, ray_origin, ray_dir, info, sampler), None),
+			other => (_debug_integrator(other, scene, ray_origin, ray_dir, time, sampler), None),
+		};
+		if !aov.is_empty() {
+			aov.sample(passes, i, split, (ray_origin, ray_dir), info, sampler);
+		}
+		light
+	}
+
+	// image of what is accumulated so far, accumulation is left as is so sampling can go on
+	pub(crate) fn output(&self, accumulation: &Accumulation) -> RenderOutput {
+		let settings = self.settings;
+		let (width, height) = (accumulation.width, accumulation.height);
+		let pixel = |px: u32, py: u32| (py * width + px) as usize;
+
+		// count is only meaningful against the most expensive pixel
+		let heat_max = accumulation.estimates.iter().map(|e| e.mean().x).fold(0.0, f32::max);
+		let radiance: AovImage = ImageBuffer::from_fn(width, height, |px, py| {
+			let light = accumulation.estimates[pixel(px, py)].mean();
+			let light = match settings.integrator {
+				Integrator::Heatmap => integrator::heat_ramp(light.x, heat_max),
+				_ => light,
+			};
+			Rgb([light[0], light[1], light[2]])
+		});
+		let finished: Vec<Vec<Color3>> = accumulation.passes.iter().zip(&accumulation.estimates)
+			.map(|(passes, estimate)| self.aov.finish(passes.clone(), estimate.count()))
+			.collect();
+		let mut aovs: Vec<(Aov, AovImage)> = self.aov_kinds.iter().enumerate()
+			.map(|(index, &kind)| (kind, ImageBuffer::from_fn(width, height, |px, py| {
+				let value = finished[pixel(px, py)][index];
+				Rgb([value[0], value[1], value[2]])
+			})))
+			.collect();
+
+		let (radiance, noisy) = match &settings.denoise {
+			Some(denoise) => {
+				let guide = |kind| &aovs.iter().find(|(k, _)| *k == kind).unwrap().1;
+				let sampled: Vec<bool> = accumulation.estimates.iter().map(|e| e.count() > 0).collect();
+				let denoised = denoise.apply_sampled(&radiance, guide(Aov::Albedo), guide(Aov::Normal), &sampled);
+				(denoised, if denoise.keep_noisy { Some(radiance) } else { None })
+			},
+			None => (radiance, None),
+		};
+		aovs.truncate(settings.aovs.len());
+
+		// denoising only see the region, black outside isn't filtered into it
+		let (radiance, aovs, noisy) = if settings.crop.is_some() && settings.crop_full_frame {
+			let embed = |image: &AovImage| ImageBuffer::from_fn(settings.width, settings.height, |px, py| {
+				let (rx, ry) = (px.wrapping_sub(accumulation.x), py.wrapping_sub(accumulation.y));
+				if rx < width && ry < height { *image.get_pixel(rx, ry) } else { Rgb([0.0; 3]) }
+			});
+			let aovs = aovs.iter().map(|(kind, image)| (*kind, embed(image))).collect();
+			(embed(&radiance), aovs, noisy.as_ref().map(embed))
+		} else {
+			(radiance, aovs, noisy)
+		};
+
+		// map from f32 image to u8 image
+		let image = tone_map(radiance.clone(), settings.tone_map);
+		let noisy = noisy.map(|noisy| tone_map(noisy, settings.tone_map));
+		RenderOutput {image, radiance, aovs, noisy, cancelled: false}
+		// TODO: post process with dither and blur
+	}
+}
+
+// integrator other than whitted, color of one camera ray
+fn _debug_integrator(
+	integrator: Integrator,
+	scene: &Scene,
+	ray_origin: Point3<f32>,
+	ray_dir: Unit<Vector3<f32>>,
+	time: f32,
+	sampler: &mut impl Sampler)
+	-> Color3 {
+	match integrator {
+		Integrator::Whitted => unreachable!("whitted is traced by the render loop"),
+		Integrator::AmbientOcclusion {radius, samples} =>
+			integrator::ambient_occlusion(scene, ray_origin, ray_dir, time, radius, samples, sampler),
+		Integrator::Normal => integrator::normal(scene, ray_origin, ray_dir, time),
+		Integrator::Depth => integrator::depth(scene, ray_origin, ray_dir, time),
+		Integrator::Uv => integrator::uv(scene, ray_origin, ray_dir, time),
+		Integrator::ObjectId => integrator::object_id(scene, ray_origin, ray_dir, time),
+		Integrator::Heatmap => integrator::intersection_count(scene, ray_origin, ray_dir, time),
 	}
-	
-	// map from f32 image to u8 image
-	color_map(img, Some(0.0), None)
-	// color_map(img, None, None)
-	// TODO: post process with dither and blur
 }
 
 pub fn raycast_compute_light(
@@ -53,14 +537,17 @@ pub fn raycast_compute_light(
 	origin: Point3<f32>,
 	dir: Unit<Vector3<f32>>,
 	info: RayCastInfo,
-	rng: &mut impl Rng)
+	sampler: &mut impl Sampler)
 	-> Color3 {
 	let mut info = info.clone();
 	info.increment_ray_number();
 
-	if let Some((hit, obj_ref)) = raycast_return_ref(scene, origin, dir) {
-		// TODO: make this dependent on material
-		obj_ref.material.compute_light(&scene, &hit, &obj_ref, info, rng)
+	if let Some((hit, obj_ref)) = raycast_return_ref(scene, origin, dir, info.time()) {
+		match obj_ref.material.get() {
+			Some(material) => material.compute_light(&scene, &hit, &obj_ref, info, sampler),
+			// unresolved material name, check_materials reject it before rendering
+			None => Color3::zeros(),
+		}
 	}
 	else {
 		scene.get_skylight()
@@ -68,6 +555,29 @@ pub fn raycast_compute_light(
 }
 
 
+fn tone_map(buf: RenderBuffer, tone_map: ToneMap) -> RenderImage {
+	let curve: fn(f32) -> f32 = match tone_map {
+		ToneMap::Normalize(vmin, vmax) => return color_map(buf, vmin, vmax),
+		ToneMap::Clamp(_) => |x| x.max(0.0).min(1.0),
+		ToneMap::Reinhard(_) => |x| { let x = x.max(0.0); x / (1.0 + x) },
+	};
+	let exposure = match tone_map {
+		ToneMap::Clamp(e) | ToneMap::Reinhard(e) => e,
+		ToneMap::Normalize(..) => 1.0,
+	};
+
+	let mut img: RenderImage = ImageBuffer::new(buf.width(), buf.height());
+	for (b, p) in buf.pixels().zip(img.pixels_mut()) {
+		*p = Rgb([
+			(255.0 * curve(exposure * b[0])) as u8,
+			(255.0 * curve(exposure * b[1])) as u8,
+			(255.0 * curve(exposure * b[2])) as u8
+		]);
+	}
+
+	img
+}
+
 // TODO: maybe change to input array of Color3?
 fn color_map(buf: RenderBuffer, vmin: Option<f32>, vmax: Option<f32>) -> RenderImage {
 	
@@ -97,21 +607,23 @@ fn color_map(buf: RenderBuffer, vmin: Option<f32>, vmax: Option<f32>) -> RenderI
 	img
 }
 
-pub fn raycast(scene: &Scene, origin: Point3<f32>, dir: Unit<Vector3<f32>>) -> Option<HitInfo> {
+pub fn raycast(scene: &Scene, origin: Point3<f32>, dir: Unit<Vector3<f32>>, time: f32) -> Option<HitInfo> {
+	stats::count_ray();
 	scene
 		.iter_obj()
-		.filter_map(|x| x.shape.intersect(origin, dir))
+		.filter_map(|x| x.intersect(origin, dir, time))
 		.filter(|x| x.dist > 1e-6)
 		.map(|x| x)
 		.min_by(|a, b| a.dist.partial_cmp(&b.dist).unwrap_or(Equal))
 }
 
 
-pub fn raycast_return_ref(scene: &Scene, origin: Point3<f32>, dir: Unit<Vector3<f32>>)
+pub fn raycast_return_ref(scene: &Scene, origin: Point3<f32>, dir: Unit<Vector3<f32>>, time: f32)
 						  -> Option<(HitInfo, &SceneObject)> {
+	stats::count_ray();
 	scene
 		.iter_obj()
-		.filter_map(|x| x.shape.intersect(origin, dir).map(|k| (k, x)))
+		.filter_map(|x| x.intersect(origin, dir, time).map(|k| (k, x)))
 		.filter(|(x, a): &(HitInfo, _)| x.dist > 1e-6)
 		.min_by(|(a, _), (b, _)|
 			a.dist.partial_cmp(&b.dist).unwrap_or(Equal)
@@ -0,0 +1,456 @@
+/*
+sample generator for every random decision of the renderer (pixel position, shutter time, light, reflection)
+
+renderer call start_sample before each camera sample, then every get_1d / get_2d take the next dimension
+of that sample. the value only depend on (seed, pixel, sample index, dimension), so pixel can be
+rendered in any order and still give the same image
+
+low discrepancy sampler stratify the spp samples of a pixel in each dimension,
+every dimension (pair) is scrambled with its own hash so dimension don't correlate with each other
+*/
+use std::str::FromStr;
+
+use rand::prelude::{Rng, SmallRng};
+use rand::SeedableRng;
+
+use enum_dispatch::enum_dispatch;
+use serde::{Deserialize, Serialize};
+
+#[enum_dispatch]
+pub trait Sampler {
+	// begin sample `index` of pixel (px, py), dimension restart from 0
+	fn start_sample(&mut self, px: u32, py: u32, index: u32);
+	// next dimension of current sample, in [0, 1)
+	fn get_1d(&mut self) -> f32;
+	// next two dimension, stratified together
+	fn get_2d(&mut self) -> (f32, f32);
+}
+
+#[enum_dispatch(Sampler)]
+#[derive(Clone, Debug)]
+pub enum Samplers {
+	IndependentSampler,
+	StratifiedSampler,
+	HaltonSampler,
+	SobolSampler,
+	CmjSampler,
+}
+
+// sampler choice of render settings, build one sampler per thread
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum SamplerKind {
+	// uniform random, no stratification
+	Independent,
+	// jittered grid, ceil(sqrt(spp))^2 cell in 2d
+	Stratified,
+	// radical inverse of prime base, rotated per pixel
+	Halton,
+	// owen scrambled sobol with shuffled index per dimension pair
+	Sobol,
+	// correlated multi-jittered
+	Cmj,
+}
+
+impl FromStr for SamplerKind {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"independent" => Ok(SamplerKind::Independent),
+			"stratified" => Ok(SamplerKind::Stratified),
+			"halton" => Ok(SamplerKind::Halton),
+			"sobol" => Ok(SamplerKind::Sobol),
+			"cmj" => Ok(SamplerKind::Cmj),
+			_ => Err(format!("unknown sampler '{}', expected one of: independent, stratified, halton, sobol, cmj", s)),
+		}
+	}
+}
+
+impl SamplerKind {
+	pub fn build(self, seed: u64, spp: u32) -> Samplers {
+		let position = Position::new(seed, spp);
+		match self {
+			SamplerKind::Independent => IndependentSampler {position, rng: SmallRng::seed_from_u64(seed)}.into(),
+			SamplerKind::Stratified => StratifiedSampler {position}.into(),
+			SamplerKind::Halton => HaltonSampler {position}.into(),
+			SamplerKind::Sobol => SobolSampler {position}.into(),
+			SamplerKind::Cmj => CmjSampler {position}.into(),
+		}
+	}
+}
+
+// where the sampler currently is, shared by every implementation
+#[derive(Clone, Debug)]
+struct Position {
+	seed: u64,
+	spp: u32,
+	// hash of seed and pixel
+	pixel: u64,
+	index: u32,
+	dim: u32,
+}
+
+impl Position {
+	fn new(seed: u64, spp: u32) -> Self {
+		Position {seed, spp: spp.max(1), pixel: 0, index: 0, dim: 0}
+	}
+
+	fn start(&mut self, px: u32, py: u32, index: u32) {
+		self.pixel = _mix(self.seed ^ _mix((u64::from(py) << 32) | u64::from(px)));
+		self.index = index;
+		self.dim = 0;
+	}
+
+	// hash of pixel and current dimension, then move on by `count` dimension
+	fn next_dim(&mut self, count: u32) -> (u32, u64) {
+		let dim = self.dim;
+		self.dim += count;
+		(dim, _mix(self.pixel ^ u64::from(dim).wrapping_mul(0xD1B5_4A32_D192_ED03)))
+	}
+}
+
+// splitmix64 finalizer, close input give unrelated output
+fn _mix(mut x: u64) -> u64 {
+	x = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+	x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+	x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+	x ^ (x >> 31)
+}
+
+// top 24 bit as float, so result never round up to 1
+fn _to_unit(x: u32) -> f32 {
+	(x >> 8) as f32 / (1u32 << 24) as f32
+}
+
+
+// Independent Sampler, fresh rng stream for every pixel sample
+#[derive(Clone, Debug)]
+pub struct IndependentSampler {
+	position: Position,
+	rng: SmallRng,
+}
+
+impl Sampler for IndependentSampler {
+	fn start_sample(&mut self, px: u32, py: u32, index: u32) {
+		self.position.start(px, py, index);
+		self.rng = SmallRng::seed_from_u64(_mix(self.position.pixel ^ u64::from(index)));
+	}
+
+	fn get_1d(&mut self) -> f32 {
+		self.rng.gen()
+	}
+
+	fn get_2d(&mut self) -> (f32, f32) {
+		(self.rng.gen(), self.rng.gen())
+	}
+}
+
+
+// Stratified Sampler, sample i of a pixel is put in a shuffled cell then jittered inside it
+#[derive(Clone, Debug)]
+pub struct StratifiedSampler {
+	position: Position,
+}
+
+impl StratifiedSampler {
+	fn _jitter(&self, hash: u64, offset: u64) -> f32 {
+		_to_unit((_mix(hash ^ ((u64::from(self.position.index) << 2) | offset)) >> 32) as u32)
+	}
+}
+
+impl Sampler for StratifiedSampler {
+	fn start_sample(&mut self, px: u32, py: u32, index: u32) {
+		self.position.start(px, py, index);
+	}
+
+	fn get_1d(&mut self) -> f32 {
+		let spp = self.position.spp;
+		let (_, hash) = self.position.next_dim(1);
+		let cell = _permute(self.position.index % spp, spp, hash as u32);
+		(cell as f32 + self._jitter(hash, 0)) / spp as f32
+	}
+
+	fn get_2d(&mut self) -> (f32, f32) {
+		let spp = self.position.spp;
+		let nx = (spp as f32).sqrt().ceil() as u32;
+		let ny = (spp as f32 / nx as f32).ceil() as u32;
+		let (_, hash) = self.position.next_dim(2);
+		let cell = _permute(self.position.index % (nx * ny), nx * ny, hash as u32);
+		(
+			((cell % nx) as f32 + self._jitter(hash, 1)) / nx as f32,
+			((cell / nx) as f32 + self._jitter(hash, 2)) / ny as f32,
+		)
+	}
+}
+
+
+// Halton Sampler, dimension d use radical inverse in base PRIMES[d]
+// every pixel rotate the sequence by random offset (cranley patterson rotation)
+// base get too large to be useful past the table, those dimension fall back to random
+#[derive(Clone, Debug)]
+pub struct HaltonSampler {
+	position: Position,
+}
+
+const PRIMES: [u32; 32] = [
+	2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53,
+	59, 61, 67, 71, 73, 79, 83, 89, 97, 101, 103, 107, 109, 113, 127, 131,
+];
+
+fn _radical_inverse(base: u32, mut index: u32) -> f32 {
+	let inv_base = 1.0 / base as f64;
+	let (mut reversed, mut inv_base_n) = (0.0, 1.0);
+	while index > 0 {
+		let next = index / base;
+		reversed = reversed * base as f64 + (index - next * base) as f64;
+		inv_base_n *= inv_base;
+		index = next;
+	}
+	// f64 then f32 could round up to 1
+	((reversed * inv_base_n) as f32).min(1.0 - f32::EPSILON / 2.0)
+}
+
+impl HaltonSampler {
+	fn _sample(&self, dim: u32, hash: u64) -> f32 {
+		let offset = _to_unit((hash >> 32) as u32);
+		match PRIMES.get(dim as usize) {
+			Some(&base) => {
+				let x = _radical_inverse(base, self.position.index) + offset;
+				if x >= 1.0 { x - 1.0 } else { x }
+			},
+			None => _to_unit((_mix(hash ^ u64::from(self.position.index)) >> 32) as u32),
+		}
+	}
+}
+
+impl Sampler for HaltonSampler {
+	fn start_sample(&mut self, px: u32, py: u32, index: u32) {
+		self.position.start(px, py, index);
+	}
+
+	fn get_1d(&mut self) -> f32 {
+		let (dim, hash) = self.position.next_dim(1);
+		self._sample(dim, hash)
+	}
+
+	fn get_2d(&mut self) -> (f32, f32) {
+		let (dim, hash) = self.position.next_dim(2);
+		(self._sample(dim, hash), self._sample(dim + 1, _mix(hash)))
+	}
+}
+
+
+// Sobol Sampler, first two sobol dimension with owen scrambling for every dimension pair
+// index is shuffled by its own scramble so pairs don't correlate
+// "Practical Hash-based Owen Scrambling", Burley 2020
+#[derive(Clone, Debug)]
+pub struct SobolSampler {
+	position: Position,
+}
+
+// second sobol dimension, direction number of primitive polynomial x + 1
+fn _sobol_1(mut index: u32) -> u32 {
+	let (mut v, mut result) = (1u32 << 31, 0);
+	while index != 0 {
+		if index & 1 != 0 {
+			result ^= v;
+		}
+		index >>= 1;
+		v ^= v >> 1;
+	}
+	result
+}
+
+fn _laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+	x = x.wrapping_add(seed);
+	x ^= x.wrapping_mul(0x6C50_B47C);
+	x ^= x.wrapping_mul(0xB82F_1E52);
+	x ^= x.wrapping_mul(0xC7AF_E638);
+	x ^= x.wrapping_mul(0x8D22_F6E6);
+	x
+}
+
+fn _nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+	_laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+impl SobolSampler {
+	// scrambled sobol point of current index, (first dimension, second dimension)
+	fn _sample(&self, hash: u64) -> (u32, u32) {
+		let index = _nested_uniform_scramble(self.position.index, hash as u32);
+		(
+			_nested_uniform_scramble(index.reverse_bits(), (_mix(hash) >> 32) as u32),
+			_nested_uniform_scramble(_sobol_1(index), (hash >> 32) as u32),
+		)
+	}
+}
+
+impl Sampler for SobolSampler {
+	fn start_sample(&mut self, px: u32, py: u32, index: u32) {
+		self.position.start(px, py, index);
+	}
+
+	fn get_1d(&mut self) -> f32 {
+		let (_, hash) = self.position.next_dim(1);
+		_to_unit(self._sample(hash).0)
+	}
+
+	fn get_2d(&mut self) -> (f32, f32) {
+		let (_, hash) = self.position.next_dim(2);
+		let (x, y) = self._sample(hash);
+		(_to_unit(x), _to_unit(y))
+	}
+}
+
+
+// Correlated Multi-Jittered Sampler, stratified in 2d and in each 1d projection
+// "Correlated Multi-Jittered Sampling", Kensler 2013
+#[derive(Clone, Debug)]
+pub struct CmjSampler {
+	position: Position,
+}
+
+// hash permutation of [0, l), cycle walk until value land inside
+fn _permute(mut i: u32, l: u32, p: u32) -> u32 {
+	if l <= 1 {
+		return 0;
+	}
+	let mut w = l - 1;
+	w |= w >> 1;
+	w |= w >> 2;
+	w |= w >> 4;
+	w |= w >> 8;
+	w |= w >> 16;
+	loop {
+		i ^= p;
+		i = i.wrapping_mul(0xE170_893D);
+		i ^= p >> 16;
+		i ^= (i & w) >> 4;
+		i ^= p >> 8;
+		i = i.wrapping_mul(0x0929_EB3F);
+		i ^= p >> 23;
+		i ^= (i & w) >> 1;
+		i = i.wrapping_mul(1 | p >> 27);
+		i = i.wrapping_mul(0x6935_FA69);
+		i ^= (i & w) >> 11;
+		i = i.wrapping_mul(0x74DC_B303);
+		i ^= (i & w) >> 2;
+		i = i.wrapping_mul(0x9E50_1CC3);
+		i ^= (i & w) >> 2;
+		i = i.wrapping_mul(0xC860_A3DF);
+		i &= w;
+		i ^= i >> 5;
+		if i < l {
+			return (i.wrapping_add(p)) % l;
+		}
+	}
+}
+
+fn _rand_float(mut i: u32, p: u32) -> f32 {
+	i ^= p;
+	i ^= i >> 17;
+	i ^= i >> 10;
+	i = i.wrapping_mul(0xB365_34E5);
+	i ^= i >> 12;
+	i ^= i >> 21;
+	i = i.wrapping_mul(0x93FC_4795);
+	i ^= 0xDF6E_307F;
+	i ^= i >> 17;
+	i = i.wrapping_mul(1 | p >> 18);
+	_to_unit(i)
+}
+
+impl Sampler for CmjSampler {
+	fn start_sample(&mut self, px: u32, py: u32, index: u32) {
+		self.position.start(px, py, index);
+	}
+
+	fn get_1d(&mut self) -> f32 {
+		let n = self.position.spp;
+		let (_, hash) = self.position.next_dim(1);
+		let p = hash as u32;
+		let s = self.position.index % n;
+		(_permute(s, n, p.wrapping_mul(0x5163_3E2D)) as f32 + _rand_float(s, p.wrapping_mul(0x967A_889B))) / n as f32
+	}
+
+	fn get_2d(&mut self) -> (f32, f32) {
+		let n = self.position.spp;
+		let m = ((n as f32).sqrt() as u32).max(1);
+		let rows = (n as f32 / m as f32).ceil() as u32;
+		let (_, hash) = self.position.next_dim(2);
+		let p = hash as u32;
+
+		let s = _permute(self.position.index % n, n, p.wrapping_mul(0x5163_3E2D));
+		let sx = _permute(s % m, m, p.wrapping_mul(0x68BC_21EB));
+		let sy = _permute(s / m, rows, p.wrapping_mul(0x02E5_BE93));
+		let jx = _rand_float(s, p.wrapping_mul(0x967A_889B));
+		let jy = _rand_float(s, p.wrapping_mul(0x368C_C8B7));
+		((sx as f32 + (sy as f32 + jx) / rows as f32) / m as f32, (s as f32 + jy) / n as f32)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const KINDS: [SamplerKind; 5] =
+		[SamplerKind::Independent, SamplerKind::Stratified, SamplerKind::Halton, SamplerKind::Sobol, SamplerKind::Cmj];
+
+	// (1d, 2d) sample of the first two dimension for every sample of one pixel
+	fn pixel_samples(kind: SamplerKind, spp: u32) -> Vec<(f32, (f32, f32))> {
+		let mut sampler = kind.build(42, spp);
+		(0..spp).map(|i| {
+			sampler.start_sample(3, 5, i);
+			(sampler.get_1d(), sampler.get_2d())
+		}).collect()
+	}
+
+	#[test]
+	fn sample_in_unit_range_test() {
+		for &kind in KINDS.iter() {
+			let mut sampler = kind.build(7, 16);
+			for i in 0..16 {
+				sampler.start_sample(1, 2, i);
+				for _ in 0..40 {
+					let (x, y) = sampler.get_2d();
+					let z = sampler.get_1d();
+					assert!([x, y, z].iter().all(|v| (0.0..1.0).contains(v)), "{:?} gave {:?}", kind, (x, y, z));
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn same_sample_repeat_test() {
+		for &kind in KINDS.iter() {
+			let mut sampler = kind.build(7, 4);
+			sampler.start_sample(10, 20, 3);
+			let first = (sampler.get_1d(), sampler.get_2d());
+			sampler.start_sample(11, 20, 0);
+			sampler.get_2d();
+			sampler.start_sample(10, 20, 3);
+			assert_eq!(first, (sampler.get_1d(), sampler.get_2d()), "{:?}", kind);
+		}
+	}
+
+	#[test]
+	fn low_discrepancy_stratified_test() {
+		for &kind in &[SamplerKind::Stratified, SamplerKind::Halton, SamplerKind::Sobol, SamplerKind::Cmj] {
+			let samples = pixel_samples(kind, 16);
+
+			// one sample in each 1/16 of the 1d dimension
+			let mut strata: Vec<u32> = samples.iter().map(|s| (s.0 * 16.0) as u32).collect();
+			strata.sort();
+			assert_eq!(strata, (0..16).collect::<Vec<_>>(), "{:?} 1d", kind);
+
+			// one sample in each cell of 4x4 grid, halton base 2 and 3 don't line up with the grid
+			if kind != SamplerKind::Halton {
+				let mut cells: Vec<u32> = samples.iter()
+					.map(|s| ((s.1).0 * 4.0) as u32 + 4 * ((s.1).1 * 4.0) as u32)
+					.collect();
+				cells.sort();
+				assert_eq!(cells, (0..16).collect::<Vec<_>>(), "{:?} 2d", kind);
+			}
+		}
+	}
+}
@@ -1,16 +1,25 @@
+use std::borrow::Cow;
 use std::cell::RefCell;
-use std::slice::Iter;
+use std::collections::BTreeSet;
+use std::slice::{Iter, IterMut};
 use std::vec::Vec;
 
+use nalgebra::Similarity3;
 use serde::{Deserialize, Serialize};
 
-use super::{Color3, light, SceneObject};
+use super::{Color3, Group, light, SceneObject};
+use super::validation::{field, index, Report, Validate};
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Scene {
+	#[serde(default)]
 	objects: Vec<SceneObject>,
+	#[serde(default)]
 	lights: Vec<light::Lights>,
+	#[serde(default = "Color3::zeros")]
 	skylight: Color3,
+	#[serde(default)]
+	groups: Vec<Group>,
 }
 
 impl Scene {
@@ -20,6 +29,7 @@ impl Scene {
 			objects: Vec::new(),
 			lights: Vec::new(),
 			skylight: Color3::new(0.0, 0.0, 0.0),
+			groups: Vec::new(),
 		}
 	}
 
@@ -32,6 +42,7 @@ impl Scene {
 			objects: objs.unwrap_or_else(Vec::new),
 			lights: lights.unwrap_or_else(Vec::new),
 			skylight: skylight.unwrap_or_else(|| Color3::new(0.0, 0.0, 0.0)),
+			groups: Vec::new(),
 		}
 	}
 	
@@ -60,9 +71,138 @@ impl Scene {
 	pub fn iter_light(&self) -> Iter<light::Lights> {
 		self.lights.iter()
 	}
+
+	pub fn iter_obj_mut(&mut self) -> IterMut<SceneObject> {
+		self.objects.iter_mut()
+	}
+
+	pub fn obj_mut(&mut self, index: usize) -> Option<&mut SceneObject> {
+		self.objects.get_mut(index)
+	}
+
+	pub fn light(&self, index: usize) -> Option<&light::Lights> {
+		self.lights.get(index)
+	}
+
+	pub fn light_mut(&mut self, index: usize) -> Option<&mut light::Lights> {
+		self.lights.get_mut(index)
+	}
 	
 	pub fn get_skylight(&self) -> Color3 {
 		self.skylight
 	}
+
+	pub fn set_skylight(&mut self, skylight: Color3) {
+		self.skylight = skylight;
+	}
+
+	pub fn obj_count(&self) -> usize {
+		self.objects.len()
+	}
+
+	pub fn light_count(&self) -> usize {
+		self.lights.len()
+	}
+
+	pub fn iter_light_mut(&mut self) -> IterMut<light::Lights> {
+		self.lights.iter_mut()
+	}
+
+	// move every object, light and group of other scene into this one, skylight is kept
+	pub fn merge(&mut self, mut other: Scene) {
+		self.append_objs(other.objects);
+		self.append_light(other.lights);
+		self.groups.append(&mut other.groups);
+	}
+
+	pub fn add_group(&mut self, group: Group) {
+		self.groups.push(group);
+	}
+
+	pub fn iter_group(&self) -> Iter<Group> {
+		self.groups.iter()
+	}
+
+	pub fn iter_group_mut(&mut self) -> IterMut<Group> {
+		self.groups.iter_mut()
+	}
+
+	// find group by name anywhere in the hierarchy
+	pub fn group(&self, name: &str) -> Option<&Group> {
+		self.groups.iter().find_map(|g| g.find(name))
+	}
+
+	pub fn group_mut(&mut self, name: &str) -> Option<&mut Group> {
+		self.groups.iter_mut().find_map(|g| g.find_mut(name))
+	}
+
+	// world transform of named group, parent transforms included
+	pub fn group_world_transform(&self, name: &str) -> Option<Similarity3<f32>> {
+		self.groups.iter().find_map(|g| g.find_transform(name))
+	}
+
+	// visit every object, including the one inside groups
+	pub fn for_each_obj_mut(&mut self, mut f: impl FnMut(&mut SceneObject)) {
+		self.objects.iter_mut().for_each(&mut f);
+		for group in self.groups.iter_mut() {
+			group.for_each_obj_mut(&mut f);
+		}
+	}
+
+	// number of light once groups are flattened
+	pub fn total_light_count(&self) -> usize {
+		self.lights.len() + self.groups.iter().map(Group::light_count).sum::<usize>()
+	}
+
+	// scene with every group baked into plain objects and lights, what renderer work on
+	// top level objects keep their index so animation still refer to the same object
+	pub fn flatten(&self) -> Cow<Scene> {
+		if self.groups.is_empty() {
+			return Cow::Borrowed(self);
+		}
+		let mut flat = Scene {
+			objects: self.objects.clone(),
+			lights: self.lights.clone(),
+			skylight: self.skylight,
+			groups: Vec::new(),
+		};
+		for group in self.groups.iter() {
+			group.flatten_into(&Similarity3::identity(), &mut flat.objects, &mut flat.lights);
+		}
+		Cow::Owned(flat)
+	}
 	
-}
\ No newline at end of file
+}
+
+impl Validate for Scene {
+	fn validate(&self, path: &str, report: &mut Report) {
+		let objects_path = field(path, "objects");
+		for (i, obj) in self.objects.iter().enumerate() {
+			obj.validate(&index(&objects_path, i), report);
+		}
+		let lights_path = field(path, "lights");
+		for (i, light) in self.lights.iter().enumerate() {
+			light.validate(&index(&lights_path, i), report);
+		}
+		let groups_path = field(path, "groups");
+		for (i, group) in self.groups.iter().enumerate() {
+			group.validate(&index(&groups_path, i), report);
+		}
+		// name lookup only find the first one
+		let mut names = BTreeSet::new();
+		let mut stack: Vec<(String, &Group)> = self.groups.iter().enumerate().rev()
+			.map(|(i, g)| (index(&groups_path, i), g))
+			.collect();
+		while let Some((group_path, group)) = stack.pop() {
+			if !names.insert(group.name.as_str()) {
+				report.warning(&field(&group_path, "name"), format!("group name '{}' is used more than once", group.name));
+			}
+			let children_path = field(&group_path, "children");
+			stack.extend(group.iter_children().enumerate().rev().map(|(i, g)| (index(&children_path, i), g)));
+		}
+		if self.total_light_count() == 0 {
+			report.warning(&lights_path, "scene has no light, only skylight will be visible");
+		}
+		report.check_light(&field(path, "skylight"), &self.skylight);
+	}
+}
@@ -1,19 +1,92 @@
+use nalgebra::{Point3, Similarity3, Unit, Vector3};
 use serde::{Deserialize, Serialize};
 
-use super::{material, Materials};
+use super::{HitInfo, Materials, Shape};
+use super::material::MaterialRef;
+use super::motion::Motion;
+use super::stats;
+use super::validation::{field, Report, Validate};
 use super::shape::geometric::Shapes;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct SceneObject {
-	pub material: Materials,
+	pub material: MaterialRef,
 	pub shape: Shapes,
+	#[serde(default)]
+	pub motion: Option<Motion>,
+	// where include and group put the object, applied on top of motion so animation can't undo it
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub placement: Option<Similarity3<f32>>,
 }
 
 impl SceneObject {
 	pub fn new (shape: impl Into<Shapes>, material: impl Into<Materials>) -> Self {
 		SceneObject {
-			material: material.into(),
+			material: material.into().into(),
 			shape: shape.into(),
+			motion: None,
+			placement: None,
 		}
 	}
-}
\ No newline at end of file
+
+	// object using named material from material library of SceneData
+	pub fn with_material_ref(shape: impl Into<Shapes>, material: MaterialRef) -> Self {
+		SceneObject {
+			material,
+			shape: shape.into(),
+			motion: None,
+			placement: None,
+		}
+	}
+
+	pub fn with_motion(mut self, motion: Motion) -> Self {
+		self.motion = Some(motion);
+		self
+	}
+
+	// place object under transform, on top of its own motion
+	pub fn transform(&mut self, trans: &Similarity3<f32>) {
+		self.placement = Some(match &self.placement {
+			None => *trans,
+			Some(placement) => trans * placement,
+		});
+	}
+
+	// placement and motion together, None when the shape is already in world space
+	pub fn world_motion(&self) -> Option<Motion> {
+		match (&self.placement, &self.motion) {
+			(None, motion) => *motion,
+			(Some(placement), None) => Some(Motion::fixed(*placement)),
+			(Some(placement), Some(m)) => Some(Motion::new(placement * m.start, placement * m.end)),
+		}
+	}
+
+	// object to world transform at time
+	pub fn transform_at(&self, time: f32) -> Similarity3<f32> {
+		self.world_motion().map_or_else(Similarity3::identity, |m| m.at(time))
+	}
+
+	// intersect shape at the given time, shape is transformed by placement and motion (if any)
+	pub fn intersect(&self, origin: Point3<f32>, dir: Unit<Vector3<f32>>, time: f32) -> Option<HitInfo> {
+		stats::count_intersection_tests(1);
+		match &self.world_motion() {
+			None => self.shape.intersect(origin, dir),
+			Some(motion) => {
+				let (local_origin, local_dir) = motion.to_local(time, origin, dir);
+				self.shape.intersect(local_origin, local_dir)
+					.map(|hit| motion.to_world(time, hit, dir))
+			}
+		}
+	}
+}
+
+impl Validate for SceneObject {
+	fn validate(&self, path: &str, report: &mut Report) {
+		self.material.validate(&field(path, "material"), report);
+		self.shape.validate(&field(path, "shape"), report);
+		self.motion.validate(&field(path, "motion"), report);
+		if let Some(placement) = &self.placement {
+			report.check_transform(&field(path, "placement"), placement);
+		}
+	}
+}
@@ -15,18 +15,44 @@ pub mod geometric {
 
 	use enum_dispatch::enum_dispatch;
 
+	use crate::rtracer::validation::{field, Report, Validate};
+
 	use super::HitInfo;
 	use super::Shape;
 
+	pub use crate::rtracer::mesh::{Mesh, MeshData};
+
 	#[enum_dispatch(Shape)]
-	#[derive(Serialize, Deserialize)]
+	#[derive(Serialize, Deserialize, Clone)]
 	pub enum Shapes {
 		Sphere,
 		InfinitePlane,
-		Disc
+		Disc,
+		Mesh,
+	}
+
+	impl Validate for Shapes {
+		fn validate(&self, path: &str, report: &mut Report) {
+			match self {
+				Shapes::Sphere(s) => {
+					report.check_point(&field(path, "pos"), &s.pos);
+					report.check_positive(&field(path, "radius"), s.radius);
+				},
+				Shapes::InfinitePlane(s) => {
+					report.check_point(&field(path, "pos"), &s.pos);
+					report.check_unit(&field(path, "norm"), &s.norm);
+				},
+				Shapes::Disc(s) => {
+					report.check_point(&field(path, "pos"), &s.pos);
+					report.check_unit(&field(path, "norm"), &s.norm);
+					report.check_positive(&field(path, "r_sq"), s.r_sq);
+				},
+				Shapes::Mesh(s) => s.validate(path, report),
+			}
+		}
 	}
 
-	#[derive(Serialize, Deserialize)]
+	#[derive(Serialize, Deserialize, Clone)]
 	pub struct Sphere {
 		pub pos: Point3<f32>,
 		pub radius: f32
@@ -62,11 +88,13 @@ pub mod geometric {
 				dist,
 				intersection,
 				normal,
+				uv: None,
+				vertex_color: None,
 			})
 		}
 	}
 
-	#[derive(Serialize, Deserialize)]
+	#[derive(Serialize, Deserialize, Clone)]
 	pub struct InfinitePlane {
 		pub pos: Point3<f32>,
 		pub norm: Unit<Vector3<f32>>,
@@ -93,7 +121,9 @@ pub mod geometric {
 					incoming_dir: dir,
 					dist,
 					intersection: origin + dir.as_ref() * dist,
-					normal: self_norm
+					normal: self_norm,
+					uv: None,
+					vertex_color: None,
 				})
 			}
 			else {
@@ -108,7 +138,7 @@ pub mod geometric {
 		}
 	}
 
-	#[derive(Serialize, Deserialize)]
+	#[derive(Serialize, Deserialize, Clone)]
 	pub struct Disc {
 		pub pos: Point3<f32>,
 		pub norm: Unit<Vector3<f32>>,
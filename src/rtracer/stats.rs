@@ -0,0 +1,30 @@
+/*
+render statistic counted while tracing, kept per thread so counting doesn't contend
+heatmap integrator reset it before camera ray and read it after,
+renderer take the ray count after each row for progress report
+*/
+use std::cell::Cell;
+
+thread_local! {
+	static INTERSECTION_TESTS: Cell<u64> = const { Cell::new(0) };
+	static RAYS: Cell<u64> = const { Cell::new(0) };
+}
+
+// shape test and bvh box test both count as one
+pub fn count_intersection_tests(count: u64) {
+	INTERSECTION_TESTS.with(|tests| tests.set(tests.get() + count));
+}
+
+// number of test since last take, counter start over from 0
+pub fn take_intersection_tests() -> u64 {
+	INTERSECTION_TESTS.with(|tests| tests.replace(0))
+}
+
+// camera, shadow, reflection and ao ray alike
+pub fn count_ray() {
+	RAYS.with(|rays| rays.set(rays.get() + 1));
+}
+
+pub fn take_rays() -> u64 {
+	RAYS.with(|rays| rays.replace(0))
+}
@@ -0,0 +1,199 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use nalgebra::Vector2;
+use serde::{Deserialize, Serialize};
+
+use super::Color3;
+use super::import;
+use super::parser::SceneParserError;
+
+// image mapped on surface by uv of hit, scene file only keep path to the image
+// path like "model.glb#2" refer to the third image embedded in a gltf file
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(from = "String", into = "String")]
+pub struct Texture {
+	path: String,
+	// None until loaded by parser
+	image: Option<Arc<TextureImage>>,
+}
+
+// linear color pixel, row major from top left
+pub struct TextureImage {
+	width: u32,
+	height: u32,
+	pixels: Vec<Color3>,
+}
+
+impl Texture {
+	pub fn new(path: impl Into<String>, image: TextureImage) -> Self {
+		Texture {path: path.into(), image: Some(Arc::new(image))}
+	}
+
+	pub fn path(&self) -> &str {
+		&self.path
+	}
+
+	pub fn is_loaded(&self) -> bool {
+		self.image.is_some()
+	}
+
+	// read image if it isn't loaded yet, relative path start from base_dir
+	pub fn load(&mut self, base_dir: &Path) -> Result<(), SceneParserError> {
+		self.load_cached(base_dir, &mut TextureCache::default())
+	}
+
+	// like load, gltf file already imported for another texture is reused from cache
+	pub fn load_cached(&mut self, base_dir: &Path, cache: &mut TextureCache) -> Result<(), SceneParserError> {
+		if self.image.is_none() {
+			let image = cache.open(&base_dir.join(&self.path))
+				.map_err(|message| SceneParserError::TextureError {path: self.path.clone(), message})?;
+			self.image = Some(image);
+		}
+		Ok(())
+	}
+
+	// texture that isn't loaded doesn't change color
+	pub fn sample(&self, uv: Vector2<f32>) -> Color3 {
+		match &self.image {
+			Some(image) => image.sample(uv),
+			None => Color3::new(1.0, 1.0, 1.0),
+		}
+	}
+}
+
+impl From<String> for Texture {
+	fn from(path: String) -> Self {
+		Texture {path, image: None}
+	}
+}
+
+impl From<Texture> for String {
+	fn from(texture: Texture) -> Self {
+		texture.path
+	}
+}
+
+// image of every gltf file opened so far, so "model.glb#0" and "model.glb#1" import the file once
+#[derive(Default)]
+pub struct TextureCache {
+	gltf: HashMap<PathBuf, Vec<Arc<TextureImage>>>,
+}
+
+impl TextureCache {
+	pub fn open(&mut self, path: &Path) -> Result<Arc<TextureImage>, String> {
+		let (file, image_index) = match _gltf_image(path) {
+			Some(image) => image,
+			None => return TextureImage::open(path).map(Arc::new),
+		};
+		if !self.gltf.contains_key(&file) {
+			let images = import::gltf::load_images(&file)?;
+			self.gltf.insert(file.clone(), images.into_iter().map(Arc::new).collect());
+		}
+		let images = &self.gltf[&file];
+		images.get(image_index)
+			.cloned()
+			.ok_or_else(|| format!("{} has only {} images", file.display(), images.len()))
+	}
+}
+
+// "model.glb#2" is the third image of a gltf file, any other path with # is an ordinary file name
+fn _gltf_image(path: &Path) -> Option<(PathBuf, usize)> {
+	let path = path.to_str()?;
+	let i = path.rfind('#')?;
+	let (file, image_index) = (&path[..i], &path[i + 1..]);
+	let extension = Path::new(file).extension()?.to_str()?.to_lowercase();
+	if extension != "gltf" && extension != "glb" {
+		return None;
+	}
+	image_index.parse().ok().map(|image_index| (PathBuf::from(file), image_index))
+}
+
+impl TextureImage {
+	pub fn new(width: u32, height: u32, pixels: Vec<Color3>) -> Result<Self, String> {
+		if pixels.len() != width as usize * height as usize {
+			return Err(format!("{}x{} image has {} pixels", width, height, pixels.len()));
+		}
+		Ok(TextureImage {width, height, pixels})
+	}
+
+	// 8 bit sRGB pixel with given number of channel, channel after the third (alpha) is dropped
+	// one or two channel image is gray
+	pub fn from_srgb8(width: u32, height: u32, channels: usize, data: &[u8]) -> Result<Self, String> {
+		if channels == 0 || data.len() != width as usize * height as usize * channels {
+			return Err(format!("{}x{} image with {} channels has {} bytes", width, height, channels, data.len()));
+		}
+		let decode = |x: u8| (x as f32 / 255.0).powf(2.2);
+		let pixels = data.chunks_exact(channels)
+			.map(|p| match channels {
+				1 | 2 => Color3::repeat(decode(p[0])),
+				_ => Color3::new(decode(p[0]), decode(p[1]), decode(p[2])),
+			})
+			.collect();
+		TextureImage::new(width, height, pixels)
+	}
+
+	// image file, or image embedded in gltf file for path like "model.glb#2"
+	pub fn open(path: &Path) -> Result<Self, String> {
+		if let Some((file, image_index)) = _gltf_image(path) {
+			let mut images = import::gltf::load_images(&file)?;
+			let count = images.len();
+			return if image_index < count {
+				Ok(images.swap_remove(image_index))
+			} else {
+				Err(format!("{} has only {} images", file.display(), count))
+			};
+		}
+
+		let image = image::open(path).map_err(|e| e.to_string())?.to_rgb();
+		let (width, height) = image.dimensions();
+		TextureImage::from_srgb8(width, height, 3, &image.into_raw())
+	}
+
+	pub fn dimensions(&self) -> (u32, u32) {
+		(self.width, self.height)
+	}
+
+	// bilinear filtered, uv outside [0, 1] repeat the image
+	pub fn sample(&self, uv: Vector2<f32>) -> Color3 {
+		if self.pixels.is_empty() {
+			return Color3::new(1.0, 1.0, 1.0);
+		}
+		let x = uv.x * self.width as f32 - 0.5;
+		let y = uv.y * self.height as f32 - 0.5;
+		let (x0, y0) = (x.floor(), y.floor());
+		let (fx, fy) = (x - x0, y - y0);
+
+		let texel = |dx: i64, dy: i64| {
+			let px = (x0 as i64 + dx).rem_euclid(self.width as i64);
+			let py = (y0 as i64 + dy).rem_euclid(self.height as i64);
+			self.pixels[(py * self.width as i64 + px) as usize]
+		};
+
+		(texel(0, 0) * (1.0 - fx) + texel(1, 0) * fx) * (1.0 - fy)
+			+ (texel(0, 1) * (1.0 - fx) + texel(1, 1) * fx) * fy
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn gltf_image_path_test() {
+		assert_eq!(_gltf_image(Path::new("model.glb#2")), Some((PathBuf::from("model.glb"), 2)));
+		assert_eq!(_gltf_image(Path::new("dir/Model.GLTF#0")), Some((PathBuf::from("dir/Model.GLTF"), 0)));
+		assert_eq!(_gltf_image(Path::new("wood#2.png")), None);
+		assert_eq!(_gltf_image(Path::new("model.glb#front")), None);
+	}
+
+	// truncated image data is an error, not a panic
+	#[test]
+	fn truncated_image_test() {
+		assert!(TextureImage::from_srgb8(2, 2, 3, &[0; 11]).is_err());
+		assert!(TextureImage::from_srgb8(2, 2, 0, &[]).is_err());
+		assert!(TextureImage::new(2, 1, vec![Color3::zeros()]).is_err());
+		assert_eq!(TextureImage::from_srgb8(2, 2, 3, &[0; 12]).unwrap().pixels.len(), 4);
+	}
+}
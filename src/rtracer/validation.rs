@@ -0,0 +1,227 @@
+use std::fmt;
+
+use nalgebra::{Point3, Similarity3, Unit, Vector3};
+
+use super::Color3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+	Warning,
+	Error,
+}
+
+// one problem found in scene, path point to the offending field like scene.objects[2].shape.radius
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+	pub severity: Severity,
+	pub path: String,
+	pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let severity = match self.severity {
+			Severity::Warning => "warning",
+			Severity::Error => "error",
+		};
+		write!(f, "{}: {}: {}", severity, self.path, self.message)
+	}
+}
+
+// every diagnostic collected by validation pass
+#[derive(Clone, Debug, Default)]
+pub struct Report {
+	diagnostics: Vec<Diagnostic>,
+}
+
+impl Report {
+	pub fn new() -> Self {
+		Report {diagnostics: Vec::new()}
+	}
+
+	pub fn error(&mut self, path: &str, message: impl Into<String>) {
+		self.push(Severity::Error, path, message.into());
+	}
+
+	pub fn warning(&mut self, path: &str, message: impl Into<String>) {
+		self.push(Severity::Warning, path, message.into());
+	}
+
+	fn push(&mut self, severity: Severity, path: &str, message: String) {
+		self.diagnostics.push(Diagnostic {severity, path: path.to_string(), message});
+	}
+
+	// move diagnostic of other report to the end of this one
+	pub fn append(&mut self, mut other: Report) {
+		self.diagnostics.append(&mut other.diagnostics);
+	}
+
+	// like append, but path of other is relative to given file
+	pub fn append_from_file(&mut self, file: &str, other: Report) {
+		for mut d in other.diagnostics {
+			d.path = format!("{}:{}", file, d.path);
+			self.diagnostics.push(d);
+		}
+	}
+
+	pub fn diagnostics(&self) -> &[Diagnostic] {
+		&self.diagnostics
+	}
+
+	pub fn has_error(&self) -> bool {
+		self.diagnostics.iter().any(|d| d.severity == Severity::Error)
+	}
+
+	pub fn error_count(&self) -> usize {
+		self.diagnostics.iter().filter(|d| d.severity == Severity::Error).count()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.diagnostics.is_empty()
+	}
+
+	// check helper shared by every Validate impl
+
+	pub fn check_finite(&mut self, path: &str, value: f32) {
+		if !value.is_finite() {
+			self.error(path, format!("must be finite, got {}", value));
+		}
+	}
+
+	pub fn check_positive(&mut self, path: &str, value: f32) {
+		if value.is_nan() || value <= 0.0 || value.is_infinite() {
+			self.error(path, format!("must be positive, got {}", value));
+		}
+	}
+
+	pub fn check_point(&mut self, path: &str, p: &Point3<f32>) {
+		if p.iter().any(|x| !x.is_finite()) {
+			self.error(path, format!("must be finite, got [{}, {}, {}]", p.x, p.y, p.z));
+		}
+	}
+
+	// non-negative color, warn on albedo that reflect more than it receive
+	pub fn check_albedo(&mut self, path: &str, c: &Color3) {
+		self.check_light(path, c);
+		if c.iter().any(|x| *x > 1.0) {
+			self.warning(path, format!("component greater than 1 add energy, got [{}, {}, {}]", c.x, c.y, c.z));
+		}
+	}
+
+	pub fn check_light(&mut self, path: &str, c: &Color3) {
+		if c.iter().any(|x| !x.is_finite()) {
+			self.error(path, format!("must be finite, got [{}, {}, {}]", c.x, c.y, c.z));
+		}
+		else if c.iter().any(|x| *x < 0.0) {
+			self.error(path, format!("must not be negative, got [{}, {}, {}]", c.x, c.y, c.z));
+		}
+	}
+
+	// unit vector are deserialized as-is, so they might not be unit at all
+	pub fn check_unit(&mut self, path: &str, v: &Unit<Vector3<f32>>) {
+		self.check_direction(path, v.as_ref());
+		let norm = v.as_ref().norm();
+		if norm.is_finite() && norm > 1e-6 && (norm - 1.0).abs() > 1e-3 {
+			self.error(path, format!("must be unit length, got length {}", norm));
+		}
+	}
+
+	pub fn check_direction(&mut self, path: &str, v: &Vector3<f32>) {
+		if v.iter().any(|x| !x.is_finite()) {
+			self.error(path, format!("must be finite, got [{}, {}, {}]", v.x, v.y, v.z));
+		}
+		else if v.norm() <= 1e-6 {
+			self.error(path, "must not be zero length");
+		}
+	}
+
+	pub fn check_transform(&mut self, path: &str, t: &Similarity3<f32>) {
+		let v = t.isometry.translation.vector;
+		if v.iter().any(|x| !x.is_finite()) {
+			self.error(&format!("{}.translation", path), "must be finite");
+		}
+		let q = t.isometry.rotation.as_ref().coords;
+		if q.iter().any(|x| !x.is_finite()) || q.norm() <= 1e-6 {
+			self.error(&format!("{}.rotation", path), "must be a non-zero quaternion");
+		}
+		else if (q.norm() - 1.0).abs() > 1e-3 {
+			self.warning(&format!("{}.rotation", path), format!("quaternion is not normalized (length {})", q.norm()));
+		}
+		let s = t.scaling();
+		if s.is_nan() || s.abs() <= 1e-6 {
+			self.error(&format!("{}.scaling", path), format!("must not be zero, got {}", s));
+		}
+	}
+}
+
+impl fmt::Display for Report {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		for (i, d) in self.diagnostics.iter().enumerate() {
+			if i > 0 {
+				writeln!(f)?;
+			}
+			write!(f, "{}", d)?;
+		}
+		Ok(())
+	}
+}
+
+// implemented next to each type since most field are private
+pub trait Validate {
+	fn validate(&self, path: &str, report: &mut Report);
+}
+
+impl<T: Validate> Validate for Option<T> {
+	fn validate(&self, path: &str, report: &mut Report) {
+		if let Some(x) = self {
+			x.validate(path, report);
+		}
+	}
+}
+
+pub fn validate(data: &impl Validate) -> Report {
+	let mut report = Report::new();
+	data.validate("", &mut report);
+	report
+}
+
+// join field path, root path is empty
+pub fn field(path: &str, name: &str) -> String {
+	if path.is_empty() { name.to_string() } else { format!("{}.{}", path, name) }
+}
+
+pub fn index(path: &str, i: usize) -> String {
+	format!("{}[{}]", path, i)
+}
+
+#[cfg(test)]
+mod tests {
+	use nalgebra::{Point3, Rotation3};
+
+	use crate::rtracer::{Camera, Scene, SceneData, SceneObject};
+	use crate::rtracer::geometric::Sphere;
+	use crate::rtracer::material::Diffuse;
+
+	use super::*;
+
+	#[test]
+	fn validate_scene_test() {
+		let mut scene = Scene::new();
+		scene.add_obj(SceneObject::new(
+			Sphere {pos: Point3::origin(), radius: -1.0},
+			Diffuse::new(Color3::new(0.5, 0.5, 0.5))
+		));
+		let data = SceneData::new(
+			scene,
+			Camera::new(Point3::origin(), Rotation3::identity()).with_shutter(1.0, 0.0)
+		);
+
+		let report = validate(&data);
+		let paths: Vec<_> = report.diagnostics().iter().map(|d| (d.severity, d.path.as_str())).collect();
+		assert_eq!(paths, vec![
+			(Severity::Error, "scene.objects[0].shape.radius"),
+			(Severity::Warning, "scene.lights"),
+			(Severity::Error, "camera.shutter_close"),
+		]);
+	}
+}
@@ -0,0 +1,211 @@
+use std::sync::Arc;
+
+use nalgebra::{Point3, Rotation3, Unit, Vector3};
+
+use rtracer::{Aov, Camera, Color3, CropWindow, load_scene_data, render, render_with_aovs, RenderSettings, save_scene_data, Scene, SceneData, SceneObject, ToneMap};
+use rtracer::geometric::{InfinitePlane, Sphere};
+use rtracer::light::PointLight;
+use rtracer::material::{Diffuse, MaterialRef, Reflective};
+
+// red sphere in front of camera on top of white floor, lit from above
+fn build_scene() -> SceneData {
+	let mut scene = Scene::new();
+	scene.add_obj(SceneObject::new(
+		Sphere {pos: Point3::new(3.0, 0.0, 0.0), radius: 1.0},
+		Diffuse::new(Color3::new(1.0, 0.0, 0.0))
+	));
+	scene.add_obj(SceneObject::new(
+		InfinitePlane {pos: Point3::new(0.0, 0.0, -1.0), norm: Unit::new_normalize(Vector3::new(0.0, 0.0, 1.0))},
+		Diffuse::new(Color3::new(1.0, 1.0, 1.0))
+	));
+	scene.add_light(PointLight::new(Point3::new(1.0, 0.0, 3.0), Color3::new(10.0, 10.0, 10.0)).into());
+
+	SceneData::new(scene, Camera::new(Point3::origin(), Rotation3::identity()))
+}
+
+#[test]
+fn render_public_scene() {
+	let data = build_scene();
+	let mut settings = RenderSettings::new(32, 24);
+	settings.seed = Some(1);
+
+	let img = render(&data.scene, &data.camera, &settings).unwrap();
+	assert_eq!(img.dimensions(), (32, 24));
+
+	// camera look straight at red sphere
+	let center = img.get_pixel(16, 12);
+	assert!(center[0] > 0, "sphere should be lit");
+	assert_eq!(center[1], 0);
+	assert_eq!(center[2], 0);
+}
+
+#[test]
+fn empty_image_is_error() {
+	let data = build_scene();
+	assert!(render(&data.scene, &data.camera, &RenderSettings::new(0, 24)).is_err());
+	let mut settings = RenderSettings::new(32, 0);
+	settings.crop = Some(CropWindow::Pixels(0, 0, 8, 8));
+	assert!(render(&data.scene, &data.camera, &settings).is_err());
+}
+
+// rough mirror sample random direction, image only repeat when seed is the same
+#[test]
+fn seeded_render_is_deterministic() {
+	let mut data = build_scene();
+	data.scene.add_obj(SceneObject::new(
+		Sphere {pos: Point3::new(3.0, 1.5, 0.0), radius: 0.5},
+		Reflective::new(0.3, 4)
+	));
+	let mut settings = RenderSettings::new(32, 24);
+	settings.spp = 2;
+
+	settings.seed = Some(7);
+	let first = render(&data.scene, &data.camera, &settings).unwrap();
+	let again = render(&data.scene, &data.camera, &settings).unwrap();
+	let first_raw = first.into_raw();
+	assert!(first_raw == again.into_raw(), "same seed should render the same image");
+
+	settings.seed = Some(8);
+	let other = render(&data.scene, &data.camera, &settings).unwrap();
+	assert!(first_raw != other.into_raw(), "different seed should render different noise");
+}
+
+// open shutter take several time sample even at default spp, or nothing would blur
+#[test]
+fn shutter_interval_raise_spp() {
+	let mut data = build_scene();
+	data.camera = data.camera.with_shutter(0.0, 1.0);
+	let mut settings = RenderSettings::new(8, 6);
+	settings.aovs = vec![Aov::SampleCount];
+
+	let output = render_with_aovs(&data.scene, &data.camera, &settings).unwrap();
+	assert!(output.aovs[0].1.pixels().all(|p| p[0] == 16.0));
+}
+
+// crop trace the same ray and sample as full frame, only fewer of them
+#[test]
+fn crop_window_match_full_frame() {
+	let data = build_scene();
+	let mut settings = RenderSettings::new(32, 24);
+	settings.seed = Some(3);
+	settings.spp = 2;
+	let full = render_with_aovs(&data.scene, &data.camera, &settings).unwrap().radiance;
+
+	// bound taken from the crop alone would give it another exposure
+	settings.crop = Some("0.25,0.5,0.75,1.0".parse().unwrap());
+	assert!(render(&data.scene, &data.camera, &settings).is_err());
+	settings.tone_map = ToneMap::Normalize(Some(0.0), Some(1.0));
+	let crop = render_with_aovs(&data.scene, &data.camera, &settings).unwrap().radiance;
+	assert_eq!(crop.dimensions(), (16, 12));
+	for (x, y, pixel) in crop.enumerate_pixels() {
+		assert_eq!(pixel, full.get_pixel(x + 8, y + 12));
+	}
+
+	settings.crop = Some(CropWindow::Pixels(8, 12, 16, 12));
+	settings.crop_full_frame = true;
+	let framed = render_with_aovs(&data.scene, &data.camera, &settings).unwrap().radiance;
+	assert_eq!(framed.dimensions(), (32, 24));
+	for (x, y, pixel) in framed.enumerate_pixels() {
+		let inside = (8..24).contains(&x) && (12..24).contains(&y);
+		assert_eq!(*pixel, if inside { *full.get_pixel(x, y) } else { image::Rgb([0.0; 3]) });
+	}
+}
+
+#[test]
+fn save_and_load_scene() {
+	let data = build_scene();
+	let path = std::env::temp_dir().join("rtracer_api_test_scene.ron");
+
+	save_scene_data(&path, &data).unwrap();
+	let loaded = load_scene_data(&path).unwrap();
+	std::fs::remove_file(&path).unwrap();
+
+	assert_eq!(loaded.scene.iter_obj().count(), 2);
+	assert_eq!(loaded.scene.iter_light().count(), 1);
+	assert_eq!(loaded.camera.pos, data.camera.pos);
+
+	// format that can only be imported is refused instead of getting ron text
+	let glb = std::env::temp_dir().join("rtracer_api_test_scene.glb");
+	let err = save_scene_data(&glb, &data).expect_err("glb can't be saved");
+	assert!(err.to_string().contains("'.glb'"), "{}", err);
+	assert!(!glb.exists());
+}
+
+// data.ron is written as version 1, before area light layout changed, it must keep loading across release
+#[test]
+fn load_unversioned_fixture() {
+	let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("data.ron");
+	let loaded = load_scene_data(&path).unwrap();
+
+	assert_eq!(loaded.version, rtracer::CURRENT_VERSION);
+	assert_eq!(loaded.scene.iter_obj().count(), 3);
+	assert_eq!(loaded.scene.iter_light().count(), 1);
+
+	// migrated scene saved in the current layout render the same
+	let saved = std::env::temp_dir().join("rtracer_api_test_migrated.ron");
+	save_scene_data(&saved, &loaded).unwrap();
+	let reloaded = load_scene_data(&saved).unwrap();
+	std::fs::remove_file(&saved).unwrap();
+	let mut settings = RenderSettings::new(16, 12);
+	settings.seed = Some(1);
+	let image = render(&loaded.scene, &loaded.camera, &settings).unwrap();
+	assert_eq!(render(&reloaded.scene, &reloaded.camera, &settings).unwrap().into_raw(), image.into_raw());
+}
+
+#[test]
+fn save_and_load_scene_json() {
+	let data = build_scene();
+	let path = std::env::temp_dir().join("rtracer_api_test_scene.json");
+
+	save_scene_data(&path, &data).unwrap();
+	let text = std::fs::read_to_string(&path).unwrap();
+	let loaded = load_scene_data(&path).unwrap();
+	std::fs::remove_file(&path).unwrap();
+
+	assert!(text.trim_start().starts_with('{'));
+	assert_eq!(loaded.scene.iter_obj().count(), 2);
+	assert_eq!(loaded.camera.pos, data.camera.pos);
+}
+
+#[test]
+fn share_named_material() {
+	let mut data = build_scene();
+	data.materials.insert("gold".to_string(), Arc::new(Diffuse::new(Color3::new(1.0, 0.8, 0.2)).into()));
+	for i in 0..3 {
+		data.scene.add_obj(SceneObject::with_material_ref(
+			Sphere {pos: Point3::new(5.0, i as f32, 0.0), radius: 0.3},
+			MaterialRef::named("gold")
+		));
+	}
+	data.scene.add_obj(SceneObject::with_material_ref(
+		Sphere {pos: Point3::new(5.0, -1.0, 0.0), radius: 0.3},
+		MaterialRef::named("silver")
+	));
+
+	let path = std::env::temp_dir().join("rtracer_api_test_material.ron");
+	save_scene_data(&path, &data).unwrap();
+
+	// missing name is reported with object index
+	let err = load_scene_data(&path).err().expect("silver is not in material library");
+	assert!(err.to_string().contains("scene.objects[5].material: there is no material named 'silver'"));
+
+	// scene built in code is checked by render too, instead of rendering unresolved material black
+	data.resolve_materials();
+	let err = render(&data.scene, &data.camera, &RenderSettings::new(8, 6)).expect_err("silver is still unresolved");
+	assert!(err.to_string().starts_with("no material named 'silver'"), "{}", err);
+
+	data.materials.insert("silver".to_string(), Arc::new(Diffuse::new(Color3::new(0.8, 0.8, 0.8)).into()));
+	save_scene_data(&path, &data).unwrap();
+	let loaded = load_scene_data(&path).unwrap();
+	std::fs::remove_file(&path).unwrap();
+
+	// every gold sphere point to the same material in library
+	let gold = &loaded.materials["gold"];
+	let shared = loaded.scene.iter_obj()
+		.filter(|obj| match &obj.material {
+			MaterialRef::Named(_, Some(m)) => Arc::ptr_eq(m, gold),
+			_ => false,
+		})
+		.count();
+	assert_eq!(shared, 3);
+}
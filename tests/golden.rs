@@ -0,0 +1,197 @@
+/*
+golden image test, every fixture scene in tests/golden is rendered at fixed seed and size
+and compared with the reference png next to it
+
+render change on purpose? regenerate every reference with
+	RTRACER_BLESS=1 cargo test --test golden
+on failure the actual image and a diff heatmap are written to target/tmp/golden
+*/
+use std::env;
+use std::path::{Path, PathBuf};
+
+use image::{ImageBuffer, Rgb};
+
+use rtracer::{load_scene_data, render, RenderImage, RenderSettings, ToneMap};
+
+const SEED: u64 = 0x5EED;
+
+// rmse is of channel in [0, 1], psnr in decibel, ssim of luminance
+const MAX_RMSE: f64 = 0.01;
+const MIN_PSNR: f64 = 40.0;
+const MIN_SSIM: f64 = 0.98;
+
+// ssim is averaged over every window of this size
+const SSIM_WINDOW: u32 = 7;
+
+struct Fixture {
+	name: &'static str,
+	width: u32,
+	height: u32,
+	spp: u32,
+}
+
+const FIXTURES: &[Fixture] = &[
+	Fixture {name: "diffuse", width: 64, height: 48, spp: 1},
+	Fixture {name: "reflection", width: 64, height: 48, spp: 2},
+	Fixture {name: "lights", width: 64, height: 48, spp: 1},
+	Fixture {name: "mesh_motion", width: 64, height: 48, spp: 4},
+];
+
+struct Metrics {
+	rmse: f64,
+	psnr: f64,
+	ssim: f64,
+}
+
+impl Metrics {
+	fn pass(&self) -> bool {
+		self.rmse <= MAX_RMSE && self.psnr >= MIN_PSNR && self.ssim >= MIN_SSIM
+	}
+}
+
+fn fixture_dir() -> PathBuf {
+	Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden")
+}
+
+fn output_dir() -> PathBuf {
+	Path::new(env!("CARGO_TARGET_TMPDIR")).join("golden")
+}
+
+fn render_fixture(fixture: &Fixture) -> RenderImage {
+	let data = load_scene_data(fixture_dir().join(format!("{}.ron", fixture.name)))
+		.unwrap_or_else(|e| panic!("fixture {} doesn't load: {}", fixture.name, e));
+	let mut settings = RenderSettings::new(fixture.width, fixture.height);
+	settings.spp = fixture.spp;
+	settings.seed = Some(SEED);
+	settings.tone_map = ToneMap::Clamp(1.0);
+	render(&data.scene, &data.camera, &settings).unwrap()
+}
+
+fn channel(image: &RenderImage, x: u32, y: u32, c: usize) -> f64 {
+	image.get_pixel(x, y)[c] as f64 / 255.0
+}
+
+fn luminance(image: &RenderImage, x: u32, y: u32) -> f64 {
+	0.2126 * channel(image, x, y, 0) + 0.7152 * channel(image, x, y, 1) + 0.0722 * channel(image, x, y, 2)
+}
+
+fn rmse(a: &RenderImage, b: &RenderImage) -> f64 {
+	let sum: f64 = a.enumerate_pixels()
+		.flat_map(|(x, y, _)| (0..3).map(move |c| (x, y, c)))
+		.map(|(x, y, c)| (channel(a, x, y, c) - channel(b, x, y, c)).powi(2))
+		.sum();
+	(sum / (a.width() * a.height() * 3) as f64).sqrt()
+}
+
+fn psnr(rmse: f64) -> f64 {
+	if rmse == 0.0 { f64::INFINITY } else { 20.0 * (1.0 / rmse).log10() }
+}
+
+// mean structural similarity over sliding window, constant of the original paper for range 1
+fn ssim(a: &RenderImage, b: &RenderImage) -> f64 {
+	const C1: f64 = 0.01 * 0.01;
+	const C2: f64 = 0.03 * 0.03;
+	let window = SSIM_WINDOW.min(a.width()).min(a.height());
+	let n = (window * window) as f64;
+
+	let mut total = 0.0;
+	let mut count = 0;
+	for y0 in 0..=a.height() - window {
+		for x0 in 0..=a.width() - window {
+			let pixels: Vec<(f64, f64)> = (y0..y0 + window)
+				.flat_map(|y| (x0..x0 + window).map(move |x| (x, y)))
+				.map(|(x, y)| (luminance(a, x, y), luminance(b, x, y)))
+				.collect();
+			let mean_a = pixels.iter().map(|p| p.0).sum::<f64>() / n;
+			let mean_b = pixels.iter().map(|p| p.1).sum::<f64>() / n;
+			let var_a = pixels.iter().map(|p| (p.0 - mean_a).powi(2)).sum::<f64>() / (n - 1.0);
+			let var_b = pixels.iter().map(|p| (p.1 - mean_b).powi(2)).sum::<f64>() / (n - 1.0);
+			let cov = pixels.iter().map(|p| (p.0 - mean_a) * (p.1 - mean_b)).sum::<f64>() / (n - 1.0);
+
+			total += ((2.0 * mean_a * mean_b + C1) * (2.0 * cov + C2))
+				/ ((mean_a * mean_a + mean_b * mean_b + C1) * (var_a + var_b + C2));
+			count += 1;
+		}
+	}
+	total / count as f64
+}
+
+fn compare(actual: &RenderImage, reference: &RenderImage) -> Metrics {
+	let rmse = rmse(actual, reference);
+	Metrics {rmse, psnr: psnr(rmse), ssim: ssim(actual, reference)}
+}
+
+// largest channel error per pixel, amplified 4 times, black -> red -> yellow -> white
+fn heatmap(a: &RenderImage, b: &RenderImage) -> RenderImage {
+	ImageBuffer::from_fn(a.width(), a.height(), |x, y| {
+		let error = (0..3)
+			.map(|c| (channel(a, x, y, c) - channel(b, x, y, c)).abs())
+			.fold(0.0, f64::max);
+		let t = (error * 4.0).min(1.0) * 3.0;
+		let ramp = |from: f64| ((t - from).clamp(0.0, 1.0) * 255.0) as u8;
+		Rgb([ramp(0.0), ramp(1.0), ramp(2.0)])
+	})
+}
+
+#[test]
+fn golden_images() {
+	let bless = env::var_os("RTRACER_BLESS").is_some();
+	let mut failures = Vec::new();
+
+	for fixture in FIXTURES {
+		let actual = render_fixture(fixture);
+		let reference_path = fixture_dir().join(format!("{}.png", fixture.name));
+		if bless {
+			actual.save(&reference_path).unwrap();
+			continue;
+		}
+
+		let reference = match image::open(&reference_path) {
+			Ok(reference) => reference.to_rgb(),
+			Err(e) => {
+				failures.push(format!("{}: no reference image ({}), run with RTRACER_BLESS=1 to create it", fixture.name, e));
+				continue;
+			},
+		};
+
+		let out = output_dir();
+		std::fs::create_dir_all(&out).unwrap();
+		let actual_path = out.join(format!("{}.actual.png", fixture.name));
+		if reference.dimensions() != actual.dimensions() {
+			actual.save(&actual_path).unwrap();
+			failures.push(format!(
+				"{}: reference is {:?} but render is {:?}", fixture.name, reference.dimensions(), actual.dimensions()
+			));
+			continue;
+		}
+
+		let metrics = compare(&actual, &reference);
+		if !metrics.pass() {
+			let diff_path = out.join(format!("{}.diff.png", fixture.name));
+			actual.save(&actual_path).unwrap();
+			heatmap(&actual, &reference).save(&diff_path).unwrap();
+			failures.push(format!(
+				"{}: rmse {:.4} (max {}), psnr {:.2} dB (min {}), ssim {:.4} (min {}), see {}",
+				fixture.name, metrics.rmse, MAX_RMSE, metrics.psnr, MIN_PSNR, metrics.ssim, MIN_SSIM, diff_path.display()
+			));
+		}
+	}
+
+	assert!(failures.is_empty(), "golden image mismatch:\n{}", failures.join("\n"));
+}
+
+#[test]
+fn metrics_of_known_image() {
+	let gray: RenderImage = ImageBuffer::from_pixel(16, 16, Rgb([128, 128, 128]));
+	let same = compare(&gray, &gray);
+	assert_eq!(same.rmse, 0.0);
+	assert!(same.psnr.is_infinite());
+	assert!((same.ssim - 1.0).abs() < 1e-9);
+
+	// every channel off by 255 / 10
+	let brighter: RenderImage = ImageBuffer::from_pixel(16, 16, Rgb([153, 153, 153]));
+	let off = compare(&gray, &brighter);
+	assert!((off.rmse - 25.0 / 255.0).abs() < 1e-9);
+	assert!((off.psnr - 20.0 * (255.0f64 / 25.0).log10()).abs() < 1e-9);
+	assert!(off.ssim < 1.0 && !off.pass());
+}